@@ -2,7 +2,9 @@ use clap::Parser;
 
 use tokio::{
     io::{stdin, stdout, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::tcp::{OwnedReadHalf, OwnedWriteHalf},
     net::TcpStream,
+    sync::mpsc,
 };
 
 #[derive(Parser, Debug)]
@@ -16,7 +18,9 @@ struct Args {
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
 
-    let mut stream = TcpStream::connect(args.addr).await?;
+    let stream = TcpStream::connect(args.addr).await?;
+    let (reader, mut writer) = stream.into_split();
+    let mut responses = read_responses(reader);
 
     let mut stdin = BufReader::new(stdin());
     let mut stdout = stdout();
@@ -25,14 +29,91 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         let mut input = String::new();
         stdin.read_line(&mut input).await?;
 
-        let inputbytes = input.into_bytes();
-        stream.write(&inputbytes).await?;
+        let is_subscribe = input.trim_start().starts_with("subscribe");
+        let is_join = input.trim_start().starts_with("join");
+        write_request(&mut writer, input.as_bytes()).await?;
 
-        let mut response_buffer = [0; 1024];
-        stream.read(&mut response_buffer).await?;
-
-        stdout.write_all(&response_buffer).await?;
+        let response = next_response(&mut responses).await?;
+        stdout.write_all(&response).await?;
         stdout.write_all(&"\n".to_string().into_bytes()).await?;
         stdout.flush().await.unwrap();
+
+        // once the subscription has started the server keeps sending events until the
+        // connection is closed
+        if is_subscribe && response.starts_with(b"subscribed") {
+            loop {
+                let response = next_response(&mut responses).await?;
+                stdout.write_all(&response).await?;
+                stdout.write_all(b"\n").await?;
+                stdout.flush().await?;
+            }
+        }
+
+        // a member is sent events until the connection is closed, and acks or nacks them
+        // in the meantime
+        if is_join && response.starts_with(b"joined") {
+            // read_until keeps what it has read when the other branch completes first, so
+            // the line is kept until it is complete. Receiving a response is cancel safe as
+            // responses are read by their own task
+            let mut line = vec![];
+            loop {
+                tokio::select! {
+                    response = next_response(&mut responses) => {
+                        stdout.write_all(&response?).await?;
+                        stdout.write_all(b"\n").await?;
+                        stdout.flush().await?;
+                    }
+                    read = stdin.read_until(b'\n', &mut line) => {
+                        if read? == 0 {
+                            return Ok(());
+                        }
+                        write_request(&mut writer, &line).await?;
+                        line.clear();
+                    }
+                }
+            }
+        }
+    }
+}
+
+// requests are prefixed with their length, so the server reads each as a whole
+async fn write_request(writer: &mut OwnedWriteHalf, request: &[u8]) -> std::io::Result<()> {
+    writer.write_u32(request.len() as u32).await?;
+    writer.write_all(request).await
+}
+
+// Responses are read by a task of their own and sent over the channel, so waiting for the
+// next response can be raced against reading input without losing part of a response
+fn read_responses(mut reader: OwnedReadHalf) -> mpsc::Receiver<std::io::Result<Vec<u8>>> {
+    let (sender, receiver) = mpsc::channel(1);
+    tokio::spawn(async move {
+        loop {
+            let response = read_response(&mut reader).await;
+            let has_failed = response.is_err();
+            if sender.send(response).await.is_err() || has_failed {
+                return;
+            }
+        }
+    });
+    receiver
+}
+
+async fn next_response(
+    responses: &mut mpsc::Receiver<std::io::Result<Vec<u8>>>,
+) -> std::io::Result<Vec<u8>> {
+    match responses.recv().await {
+        Some(response) => response,
+        None => Err(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            "connection closed",
+        )),
     }
 }
+
+// responses are prefixed with their length
+async fn read_response(reader: &mut OwnedReadHalf) -> std::io::Result<Vec<u8>> {
+    let len = reader.read_u32().await?;
+    let mut response_buffer = vec![0; len as usize];
+    reader.read_exact(&mut response_buffer).await?;
+    Ok(response_buffer)
+}
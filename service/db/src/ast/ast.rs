@@ -17,12 +17,95 @@ pub enum Command {
         event: Event,
         stream: String,
         stream_id: String,
+        expected_version: Option<u64>,
     },
 
     Find {
         projections: Vec<Projection>,
-        predicates: Vec<Predicate>,
+        predicate: Option<Expression>,
+        // only the events at or before the point are used
+        as_of: Option<AsOf>,
+        group_by: Vec<Expression>,
+        order_by: Vec<Order>,
         limit: Option<Limit>,
+        offset: Option<Offset>,
+        // cursor returned with the previous page
+        after: Option<String>,
+    },
+
+    Snapshot {
+        path: String,
+    },
+
+    // read $all [from position <N>] [limit <N>]
+    ReadAll {
+        from_position: Option<i64>,
+        limit: Option<Limit>,
+    },
+
+    // subscribe to <stream | $all> [from position <N>] [where <predicate>]
+    Subscribe {
+        stream: String,
+        from_position: Option<i64>,
+        predicate: Option<Expression>,
+    },
+
+    // join <subscription> [limit <N>], where limit is the number of events the member can
+    // have without an ack or nack
+    Join {
+        subscription: String,
+        limit: Option<Limit>,
+    },
+
+    // ack <position>, ...
+    Ack {
+        positions: Vec<i64>,
+    },
+
+    // nack <position>, ...
+    Nack {
+        positions: Vec<i64>,
+    },
+
+    // read parked <subscription>
+    ReadParked {
+        subscription: String,
+    },
+
+    // replay parked <subscription>
+    ReplayParked {
+        subscription: String,
+    },
+
+    // [:find ?variable ... :where <clause> ...]
+    FindPattern {
+        variables: Vec<String>,
+        clauses: Vec<Clause>,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Clause {
+    // [?entity :attribute value], where the entity is an event and the value a variable
+    // or a literal
+    Pattern {
+        entity: String,
+        attribute: String,
+        value: Term,
+    },
+    // [(<operator> <term> <term>)]
+    Predicate(Term),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Term {
+    Variable(String),
+    Literal(Value),
+    // (<operator> <term> <term>)
+    Operation {
+        operator: BinaryOperator,
+        left: Box<Term>,
+        right: Box<Term>,
     },
 }
 
@@ -35,12 +118,26 @@ pub enum Entity {
         stream_name: String,
         attributes: Vec<AttributeDefinition>,
     },
+    // index on stream.Event.attribute
+    Index {
+        stream_name: String,
+        event_name: String,
+        attribute_name: String,
+    },
+    // subscription <name> to <stream | $all> [from position <N>] [park after <N>]
+    Subscription {
+        name: String,
+        stream_name: String,
+        from_position: Option<i64>,
+        park_after: Option<i64>,
+    },
 }
 
 #[derive(Debug, PartialEq)]
 pub struct AttributeDefinition {
     pub name: String,
     pub data_type: String,
+    pub required: bool,
 }
 
 #[derive(Debug, PartialEq)]
@@ -57,6 +154,7 @@ pub struct AttributeValue {
 
 #[derive(Debug, PartialEq)]
 pub enum Value {
+    Null,
     Bool(bool),
     String(String),
     Int(i64),
@@ -70,24 +168,42 @@ pub struct Projection {
 }
 
 #[derive(Debug, PartialEq)]
-pub enum Predicate {
-    BinaryOperation {
-        left: Expression,
-        operator: BinaryOperator,
-        right: Expression,
-    },
+pub struct Order {
+    pub expression: Expression,
+    pub direction: Direction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AsOf {
+    // milliseconds since the epoch
+    Timestamp(i64),
+    // of each stream key
+    Version(i64),
 }
 
 #[derive(Debug, PartialEq)]
 pub struct Limit(pub i64);
 
 #[derive(Debug, PartialEq)]
+pub struct Offset(pub i64);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Function {
     Sum,
     Min,
     Max,
     Avg,
     Count,
+    First,
+    Last,
+    // the position of the value, starting at 1
+    Nth(i64),
 }
 
 #[derive(Debug, PartialEq)]
@@ -97,10 +213,19 @@ pub enum Expression {
         function: Function,
         argument: Box<Expression>,
     },
+    // stream.attribute or stream.Event.attribute
     Attribute {
         stream: String,
+        event: Option<String>,
         attribute: String,
     },
+    // the stream key of the event
+    Key,
+    // milliseconds since the epoch when the event was added
+    Timestamp,
+    Version,
+    // the name of the event
+    Event,
     UnaryOperation {
         operator: UnaryOperator,
         operand: Box<Expression>,
@@ -110,14 +235,23 @@ pub enum Expression {
         operator: BinaryOperator,
         right: Box<Expression>,
     },
+    // right hand side of in and not in
+    List(Vec<Expression>),
+    // <expression> between <low> and <high>, both inclusive
+    Between {
+        expression: Box<Expression>,
+        low: Box<Expression>,
+        high: Box<Expression>,
+    },
 }
 
 #[derive(Debug, PartialEq)]
 pub enum UnaryOperator {
     Negate,
+    Not,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum BinaryOperator {
     // Arithmetic operators
     Add,      // +
@@ -142,3 +276,28 @@ pub enum BinaryOperator {
     In,    // IN
     NotIn, // NOT IN
 }
+
+impl BinaryOperator {
+    // operators with a higher precedence bind tighter, e.g. a or b and c is a or (b and c).
+    // not binds tighter than and but looser than comparisons, and unary minus binds
+    // tighter than all binary operators
+    pub fn precedence(&self) -> u8 {
+        match self {
+            BinaryOperator::Or => 1,
+            BinaryOperator::And => 2,
+            BinaryOperator::Equal
+            | BinaryOperator::NotEqual
+            | BinaryOperator::LessThan
+            | BinaryOperator::GreaterThan
+            | BinaryOperator::LessEqual
+            | BinaryOperator::GreaterEqual
+            | BinaryOperator::In
+            | BinaryOperator::NotIn => 4,
+            BinaryOperator::Add | BinaryOperator::Subtract => 5,
+            BinaryOperator::Multiply | BinaryOperator::Divide | BinaryOperator::Modulus => 6,
+        }
+    }
+}
+
+pub const NOT_PRECEDENCE: u8 = 3;
+pub const NEGATE_PRECEDENCE: u8 = 7;
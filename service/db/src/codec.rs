@@ -0,0 +1,185 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::event::{Attribute, Event, Value};
+
+// Everything written to disk is written as frames;
+//
+//   [payload length: u32][checksum of payload: u32][payload]
+//
+// A frame that is cut short or has a checksum that does not match is the result of a
+// crash in the middle of a write.
+pub const FRAME_HEADER_SIZE: usize = 8;
+
+pub fn encode_frame(payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(FRAME_HEADER_SIZE + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&checksum(payload).to_le_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+// decodes the frame at the start of the buffer and returns its payload together with the
+// length of the whole frame. Returns None if the frame is incomplete or corrupt
+pub fn decode_frame(buffer: &[u8]) -> Option<(&[u8], usize)> {
+    if buffer.len() < FRAME_HEADER_SIZE {
+        return None;
+    }
+    let len = u32::from_le_bytes(buffer[0..4].try_into().ok()?) as usize;
+    let sum = u32::from_le_bytes(buffer[4..8].try_into().ok()?);
+    let payload = buffer.get(FRAME_HEADER_SIZE..FRAME_HEADER_SIZE + len)?;
+    if checksum(payload) != sum {
+        return None;
+    }
+    Some((payload, FRAME_HEADER_SIZE + len))
+}
+
+// FNV-1a
+pub fn checksum(bytes: &[u8]) -> u32 {
+    let mut hash: u32 = 0x811c9dc5;
+    for b in bytes {
+        hash ^= *b as u32;
+        hash = hash.wrapping_mul(0x01000193);
+    }
+    hash
+}
+
+const TAG_NULL: u8 = 0;
+const TAG_BOOL: u8 = 1;
+const TAG_INT: u8 = 2;
+const TAG_FLOAT: u8 = 3;
+const TAG_STRING: u8 = 4;
+
+pub fn encode_event(buffer: &mut Vec<u8>, event: &Event) {
+    put_str(buffer, &event.stream);
+    put_str(buffer, &event.key);
+    put_str(buffer, &event.event);
+    buffer.extend_from_slice(&event.version.to_le_bytes());
+    buffer.extend_from_slice(&event.timestamp.to_le_bytes());
+    buffer.extend_from_slice(&(event.attributes.len() as u32).to_le_bytes());
+    for attribute in event.attributes.iter() {
+        put_str(buffer, &attribute.name);
+        put_value(buffer, &attribute.value);
+    }
+}
+
+pub fn decode_event(reader: &mut Reader<'_>) -> Result<Event, CodecError> {
+    let stream = reader.str()?;
+    let key = reader.str()?;
+    let event = reader.str()?;
+    let version = reader.u64()?;
+    let timestamp = reader.u128()?;
+    let n_attributes = reader.u32()?;
+    let mut attributes = vec![];
+    for _ in 0..n_attributes {
+        attributes.push(Attribute {
+            name: reader.str()?,
+            value: reader.value()?,
+        });
+    }
+    Ok(Event::new(
+        stream, key, event, version, timestamp, attributes,
+    ))
+}
+
+pub fn put_str(buffer: &mut Vec<u8>, s: &str) {
+    buffer.extend_from_slice(&(s.len() as u32).to_le_bytes());
+    buffer.extend_from_slice(s.as_bytes());
+}
+
+pub fn put_value(buffer: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Null => buffer.push(TAG_NULL),
+        Value::Bool(v) => {
+            buffer.push(TAG_BOOL);
+            buffer.push(*v as u8);
+        }
+        Value::Int(v) => {
+            buffer.push(TAG_INT);
+            buffer.extend_from_slice(&v.to_le_bytes());
+        }
+        Value::Float(v) => {
+            buffer.push(TAG_FLOAT);
+            buffer.extend_from_slice(&v.to_le_bytes());
+        }
+        Value::String(v) => {
+            buffer.push(TAG_STRING);
+            put_str(buffer, v);
+        }
+    }
+}
+
+pub struct Reader<'a> {
+    buffer: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buffer: &'a [u8]) -> Self {
+        Reader { buffer, offset: 0 }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], CodecError> {
+        let bytes = self
+            .buffer
+            .get(self.offset..self.offset + n)
+            .ok_or_else(|| CodecError::new("unexpected end of record"))?;
+        self.offset += n;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, CodecError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u32(&mut self) -> Result<u32, CodecError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, CodecError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn u128(&mut self) -> Result<u128, CodecError> {
+        Ok(u128::from_le_bytes(self.take(16)?.try_into().unwrap()))
+    }
+
+    pub fn value(&mut self) -> Result<Value, CodecError> {
+        let value = match self.u8()? {
+            TAG_NULL => Value::Null,
+            TAG_BOOL => Value::Bool(self.u8()? != 0),
+            TAG_INT => Value::Int(i64::from_le_bytes(self.take(8)?.try_into().unwrap())),
+            TAG_FLOAT => Value::Float(f64::from_le_bytes(self.take(8)?.try_into().unwrap())),
+            TAG_STRING => Value::String(self.str()?),
+            tag => return Err(CodecError::new(&format!("unknown value tag {}", tag))),
+        };
+        Ok(value)
+    }
+
+    pub fn str(&mut self) -> Result<String, CodecError> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|e| CodecError::new(&format!("invalid string in record: {}", e)))
+    }
+}
+
+#[derive(Debug)]
+pub struct CodecError {
+    message: String,
+}
+
+impl CodecError {
+    fn new(message: &str) -> Self {
+        CodecError {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for CodecError {}
@@ -0,0 +1,114 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::codec::{checksum, decode_frame, encode_frame, put_value, CodecError, Reader};
+use crate::event::Value;
+
+// A cursor is the position of the last row of a page of a find query, i.e. the values
+// the rows are ordered by, together with a checksum of the query so it can not be used
+// to resume another query. It is handed to clients as a hex encoded frame;
+//
+//   [query checksum: u32][number of values: u32][value]...
+
+pub fn encode(query: &str, values: &[Value]) -> String {
+    let mut payload = vec![];
+    payload.extend_from_slice(&checksum(query.as_bytes()).to_le_bytes());
+    payload.extend_from_slice(&(values.len() as u32).to_le_bytes());
+    for value in values {
+        put_value(&mut payload, value);
+    }
+    encode_frame(&payload)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+pub fn decode(query: &str, cursor: &str) -> Result<Vec<Value>, CursorError> {
+    let bytes = (0..cursor.len())
+        .step_by(2)
+        .map(|i| {
+            cursor
+                .get(i..i + 2)
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+        })
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(|| CursorError::new("invalid cursor"))?;
+    let payload = match decode_frame(&bytes) {
+        Some((payload, len)) if len == bytes.len() => payload,
+        _ => return Err(CursorError::new("invalid cursor")),
+    };
+
+    let mut reader = Reader::new(payload);
+    if reader.u32()? != checksum(query.as_bytes()) {
+        return Err(CursorError::new("cursor is from another query"));
+    }
+    let n = reader.u32()?;
+    (0..n)
+        .map(|_| Ok(reader.value()?))
+        .collect::<Result<Vec<Value>, CursorError>>()
+}
+
+#[derive(Debug)]
+pub struct CursorError {
+    message: String,
+}
+
+impl CursorError {
+    fn new(message: &str) -> Self {
+        CursorError {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for CursorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for CursorError {}
+
+impl From<CodecError> for CursorError {
+    fn from(e: CodecError) -> CursorError {
+        CursorError::new(&format!("invalid cursor: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod cursor_test {
+    use super::*;
+
+    #[test]
+    fn test_cursor() {
+        let values = vec![
+            Value::Null,
+            Value::Bool(true),
+            Value::Int(-1),
+            Value::Float(2.5),
+            Value::String("123".to_string()),
+        ];
+        let cursor = encode("account", &values);
+        assert_eq!(values, decode("account", &cursor).unwrap());
+
+        let test_cases = vec![
+            ("another query", "savings", cursor.clone()),
+            ("not hex", "account", format!("zz{}", &cursor[2..])),
+            ("odd length", "account", cursor[1..].to_string()),
+            (
+                "truncated",
+                "account",
+                cursor[..cursor.len() - 2].to_string(),
+            ),
+            ("trailing data", "account", format!("{}00", cursor)),
+            (
+                "corrupt",
+                "account",
+                format!("{}ff", &cursor[..cursor.len() - 2]),
+            ),
+        ];
+        for (name, query, cursor) in test_cases {
+            assert!(decode(query, &cursor).is_err(), "test case '{}'", name);
+        }
+    }
+}
@@ -1,23 +1,51 @@
 use crate::event::Event;
+use crate::executor::{self, Rows};
+use crate::persistent::{self, State};
 use crate::planner;
+use crate::snapshot::{self, Snapshot};
+use crate::storage::file::FileStore;
+use crate::storage::memory::MemoryStore;
+use crate::storage::{EventStore, SchemaChange, StorageError, VersionConflict};
 
 use std::error::Error;
 
 use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::path::{Path, PathBuf};
 
 use std::sync::{Arc, RwLock};
 
+use tokio::sync::broadcast;
+
+// events are sent to live subscribers as they are added, a subscriber that falls more than
+// this many events behind reads the events it missed from the store instead
+const LIVE_EVENTS_CAPACITY: usize = 1024;
+
 pub struct DBError {
     message: String,
+    conflict: Option<VersionConflict>,
 }
 
 impl DBError {
     fn new(message: &str) -> Self {
         DBError {
             message: message.to_string(),
+            conflict: None,
+        }
+    }
+
+    fn conflict(conflict: VersionConflict) -> Self {
+        DBError {
+            message: conflict.to_string(),
+            conflict: Some(conflict),
         }
     }
+
+    // set if the error is caused by an event that was added with a version that was not
+    // the next version of its stream key
+    pub fn version_conflict(&self) -> Option<VersionConflict> {
+        self.conflict
+    }
 }
 
 impl fmt::Display for DBError {
@@ -34,41 +62,175 @@ impl fmt::Debug for DBError {
 
 impl Error for DBError {}
 
-// (stream, key) : []Events
-#[derive(Debug)]
-pub struct Streams(pub HashMap<(String, String), Arc<RwLock<Vec<Event>>>>);
-
 #[derive(Debug, Clone, PartialEq)]
 pub struct AttributeDetails {
     pub required: bool,
     pub attribute_type: String,
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubscriptionDetails {
+    // $all for all streams
+    pub stream_name: String,
+    pub from_position: u64,
+    // times an event can fail before it is parked
+    pub park_after: u64,
+}
+
 #[derive(Debug, PartialEq, Default, Clone)]
-struct Schema {
+pub struct Schema {
     // stream
     pub streams: HashSet<String>,
     // stream, event
     pub events: HashSet<(String, String)>,
-    // stream, event, attribute -> data_type
-    pub attributes: HashMap<(String, String, String), String>,
+    // stream, event, attribute
+    pub attributes: HashMap<(String, String, String), AttributeDetails>,
+    // stream, event, attribute
+    pub indexes: HashSet<(String, String, String)>,
+    // name of the persistent subscription
+    pub subscriptions: HashMap<String, SubscriptionDetails>,
+}
+
+impl Schema {
+    fn apply(&mut self, change: &SchemaChange) {
+        match change {
+            SchemaChange::Stream { name } => {
+                self.streams.insert(name.to_string());
+            }
+            SchemaChange::Event { name, stream_name } => {
+                self.events
+                    .insert((stream_name.to_string(), name.to_string()));
+            }
+            SchemaChange::Attribute {
+                name,
+                event_name,
+                stream_name,
+                data_type,
+                required,
+            } => {
+                self.attributes.insert(
+                    (
+                        stream_name.to_string(),
+                        event_name.to_string(),
+                        name.to_string(),
+                    ),
+                    AttributeDetails {
+                        required: *required,
+                        attribute_type: data_type.to_string(),
+                    },
+                );
+            }
+            SchemaChange::Index {
+                stream_name,
+                event_name,
+                attribute_name,
+            } => {
+                self.indexes.insert((
+                    stream_name.to_string(),
+                    event_name.to_string(),
+                    attribute_name.to_string(),
+                ));
+            }
+            SchemaChange::Subscription {
+                name,
+                stream_name,
+                from_position,
+                park_after,
+            } => {
+                self.subscriptions.insert(
+                    name.to_string(),
+                    SubscriptionDetails {
+                        stream_name: stream_name.to_string(),
+                        from_position: *from_position,
+                        park_after: *park_after,
+                    },
+                );
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
-pub struct DB {
-    pub streams: Arc<RwLock<Streams>>,
+pub struct DB<S: EventStore = MemoryStore> {
+    store: S,
     pub schema: Arc<RwLock<Schema>>,
+    // every added event, with its version and position
+    live_events: broadcast::Sender<Event>,
+    // the directory snapshots are written to, a DB that is not stored on disk has none
+    snapshot_dir: Option<PathBuf>,
 }
 
-impl DB {
+#[cfg(test)]
+impl DB<MemoryStore> {
     pub fn new() -> Self {
         return DB {
-            streams: Arc::new(RwLock::new(Streams(HashMap::new()))),
+            store: MemoryStore::new(),
             schema: Arc::new(RwLock::new(Default::default())),
+            live_events: broadcast::channel(LIVE_EVENTS_CAPACITY).0,
+            snapshot_dir: None,
         };
     }
 
-    pub fn exec(&self, plan: &planner::ExecutionPlan) -> Result<(), DBError> {
+    // a DB with the schema created by the commands
+    pub fn with_schema(cmds: &[&str]) -> Arc<Self> {
+        let db = Arc::new(DB::new());
+        for cmd in cmds {
+            db.run(cmd);
+        }
+        db
+    }
+
+    // parses, plans and executes the command, it panics if any of them fails
+    pub fn run(&self, cmd: &str) -> Option<Rows> {
+        let trx = crate::parser::parse(cmd).unwrap();
+        let plan = planner::plan(&trx, self).unwrap();
+        match self.exec(&plan) {
+            Ok(rows) => rows,
+            Err(e) => panic!("failed to exec '{}': {}", cmd, e),
+        }
+    }
+}
+
+impl DB<FileStore> {
+    // opens the DB stored in dir, see FileStore. Snapshots are written to the snapshots
+    // directory in dir
+    pub fn open(dir: &Path) -> Result<Self, DBError> {
+        let store = FileStore::open(dir)
+            .map_err(|e| DBError::new(&format!("failed to open store: {}", e)))?;
+        let mut db = DB::with_store(store)?;
+        db.snapshot_dir = Some(dir.join("snapshots"));
+        Ok(db)
+    }
+}
+
+impl<S: EventStore> DB<S> {
+    // creates a DB on top of the store, the schema is rebuilt from the schema changes
+    // persisted in the store and the indexes are built from the events
+    pub fn with_store(store: S) -> Result<Self, DBError> {
+        let mut schema = Schema::default();
+        for change in store
+            .schema_changes()
+            .map_err(|e| DBError::new(&format!("failed to load schema: {}", e)))?
+        {
+            schema.apply(&change);
+        }
+        for (stream, event, attribute) in schema.indexes.iter() {
+            store
+                .create_index(stream, event, attribute)
+                .map_err(|e| DBError::new(&format!("failed to build index: {}", e)))?;
+        }
+
+        Ok(DB {
+            store,
+            schema: Arc::new(RwLock::new(schema)),
+            live_events: broadcast::channel(LIVE_EVENTS_CAPACITY).0,
+            snapshot_dir: None,
+        })
+    }
+
+    // returns the rows of the plan if it is a query
+    pub fn exec(&self, plan: &planner::ExecutionPlan) -> Result<Option<Rows>, DBError> {
+        let mut rows = None;
         for op in plan.operations.iter() {
             match op {
                 planner::Operation::CreateStream { name } => {
@@ -85,18 +247,89 @@ impl DB {
                     event_name: event,
                     stream_name: stream,
                     data_type,
+                    required,
+                } => {
+                    self.create_attribute(stream, event, name, data_type, *required)?;
+                }
+                planner::Operation::CreateIndex {
+                    stream_name,
+                    event_name,
+                    attribute_name,
                 } => {
-                    self.create_attribute(stream, event, name, data_type)?;
+                    self.create_index(stream_name, event_name, attribute_name)?;
+                }
+                planner::Operation::CreateSubscription {
+                    name,
+                    stream_name,
+                    from_position,
+                    park_after,
+                } => {
+                    self.change_schema(SchemaChange::Subscription {
+                        name: name.clone(),
+                        stream_name: stream_name.clone(),
+                        from_position: *from_position,
+                        park_after: *park_after,
+                    })?;
                 }
                 planner::Operation::CheckStreamExists { name } => {
                     self.check_stream_exists(name)?;
                 }
-
-                _ => return Err(DBError::new("unsupported operation")),
+                planner::Operation::CheckEventExists { name, stream_name } => {
+                    self.check_event_exists(stream_name, name)?;
+                }
+                planner::Operation::AddEvent { event } => {
+                    self.add_event(event.clone())?;
+                }
+                planner::Operation::Snapshot { path } => {
+                    self.snapshot(path)?;
+                }
+                // a subscription keeps sending events, see subscription::Subscriber, and
+                // the members of a persistent subscription are kept by the server, see
+                // persistent::Groups
+                planner::Operation::Subscribe { .. } | planner::Operation::Join { .. } => {
+                    return Err(DBError::new(
+                        "subscriptions can only be run by the server on their own connection",
+                    ));
+                }
+                planner::Operation::Ack { .. } | planner::Operation::Nack { .. } => {
+                    return Err(DBError::new(
+                        "only members of a persistent subscription can ack or nack events",
+                    ));
+                }
+                planner::Operation::ReplayParked { .. } => {
+                    return Err(DBError::new(
+                        "parked events can only be replayed by the server",
+                    ));
+                }
+                planner::Operation::ReadParked { subscription } => {
+                    let state = self.subscription_state(subscription)?;
+                    rows = Some(executor::event_rows(self.read_positions(&state.parked)?));
+                }
+                planner::Operation::ReadAll {
+                    from_position,
+                    limit,
+                } => {
+                    rows = Some(
+                        executor::read_all(*from_position, *limit, &self.store)
+                            .map_err(|e| DBError::new(&format!("failed to read events: {}", e)))?,
+                    );
+                }
+                planner::Operation::Find { query } => {
+                    rows = Some(
+                        executor::execute(query, &self.store)
+                            .map_err(|e| DBError::new(&format!("failed to run query: {}", e)))?,
+                    );
+                }
+                planner::Operation::FindPattern { query } => {
+                    rows = Some(
+                        executor::execute_pattern(query, &self.store)
+                            .map_err(|e| DBError::new(&format!("failed to run query: {}", e)))?,
+                    );
+                }
             }
         }
 
-        Ok(())
+        Ok(rows)
     }
 
     fn check_stream_exists(&self, stream_name: &str) -> Result<(), DBError> {
@@ -117,33 +350,47 @@ impl DB {
         Ok(())
     }
 
-    fn create_stream(&self, name: &str) -> Result<(), DBError> {
-        self.schema
-            .write()
-            .map_err(|e| {
-                DBError::new(&format!(
-                    "failed to aquire write access for schema: {}",
-                    e.to_string()
-                ))
-            })?
-            .streams
-            .insert(name.to_string());
+    // the change is persisted while holding the write lock of the schema, so that changes
+    // are persisted in the same order as they are applied. An index is built before it is
+    // added to the schema, so queries are only planned with indexes that are complete
+    fn change_schema(&self, change: SchemaChange) -> Result<(), DBError> {
+        if let SchemaChange::Index {
+            stream_name,
+            event_name,
+            attribute_name,
+        } = &change
+        {
+            self.store
+                .create_index(stream_name, event_name, attribute_name)
+                .map_err(|e| DBError::new(&format!("failed to build index: {}", e)))?;
+        }
+
+        let mut schema = self.schema.write().map_err(|e| {
+            DBError::new(&format!(
+                "failed to aquire write access for schema: {}",
+                e.to_string()
+            ))
+        })?;
+
+        self.store
+            .persist_schema_change(&change)
+            .map_err(|e| DBError::new(&format!("failed to persist schema change: {}", e)))?;
+        schema.apply(&change);
 
         return Ok(());
     }
 
+    fn create_stream(&self, name: &str) -> Result<(), DBError> {
+        self.change_schema(SchemaChange::Stream {
+            name: name.to_string(),
+        })
+    }
+
     pub fn create_event(&self, stream_name: &str, event_name: &str) -> Result<(), DBError> {
-        self.schema
-            .write()
-            .map_err(|e| {
-                DBError::new(&format!(
-                    "failed to aquire write access for schema: {}",
-                    e.to_string()
-                ))
-            })?
-            .events
-            .insert((stream_name.to_string(), event_name.to_string()));
-        return Ok(());
+        self.change_schema(SchemaChange::Event {
+            name: event_name.to_string(),
+            stream_name: stream_name.to_string(),
+        })
     }
 
     pub fn create_attribute(
@@ -152,65 +399,301 @@ impl DB {
         event_name: &str,
         attribute_name: &str,
         data_type: &str,
+        required: bool,
     ) -> Result<(), DBError> {
-        self.schema
-            .write()
-            .map_err(|e| DBError::new(&format!("failed to read schema: {}", e.to_string())))?
-            .attributes
-            .insert(
-                (
-                    stream_name.to_string(),
-                    event_name.to_string(),
-                    attribute_name.to_string(),
-                ),
-                data_type.to_string(),
-            );
-        return Ok(());
+        self.change_schema(SchemaChange::Attribute {
+            name: attribute_name.to_string(),
+            event_name: event_name.to_string(),
+            stream_name: stream_name.to_string(),
+            data_type: data_type.to_string(),
+            required,
+        })
+    }
+
+    pub fn create_index(
+        &self,
+        stream_name: &str,
+        event_name: &str,
+        attribute_name: &str,
+    ) -> Result<(), DBError> {
+        self.change_schema(SchemaChange::Index {
+            stream_name: stream_name.to_string(),
+            event_name: event_name.to_string(),
+            attribute_name: attribute_name.to_string(),
+        })
     }
 
+    // the version of the event was decided when the plan was made, or by the client with
+    // `expect version`, the event is only added if the stream key is at the version before.
+    // Sending the event to the live subscribers never blocks, subscribers that fall behind
+    // miss events rather than slowing down writers
     pub fn add_event(&self, event: Event) -> Result<(), DBError> {
-        let streams = self
-            .streams
+        let expected_version = event.version.saturating_sub(1);
+        let position = self
+            .store
+            .append(event.clone(), expected_version)
+            .map_err(|e| match e.version_conflict() {
+                Some(conflict) => DBError::conflict(conflict),
+                None => DBError::new(&e.to_string()),
+            })?;
+
+        // fails only if there are no subscribers
+        let _ = self.live_events.send(Event {
+            version: expected_version + 1,
+            position,
+            ..event
+        });
+        Ok(())
+    }
+
+    // receives the events added from now on, in about the order of their positions as
+    // events added at the same time can be sent in any order
+    pub fn live_events(&self) -> broadcast::Receiver<Event> {
+        self.live_events.subscribe()
+    }
+
+    pub fn read_all(&self, from_position: u64, limit: usize) -> Result<Vec<Event>, DBError> {
+        self.store
+            .read_all(from_position, limit)
+            .map_err(|e| DBError::new(&format!("failed to read events: {}", e)))
+    }
+
+    // the events at the positions, in the order of the positions
+    pub fn read_positions<'a>(
+        &self,
+        positions: impl IntoIterator<Item = &'a u64>,
+    ) -> Result<Vec<Event>, DBError> {
+        let mut events = vec![];
+        for position in positions {
+            events.extend(self.read_all(*position, 1)?);
+        }
+        Ok(events)
+    }
+
+    pub fn subscription(&self, name: &str) -> Result<Option<SubscriptionDetails>, DBError> {
+        Ok(self
+            .schema
             .read()
-            .map_err(|_| DBError::new(&"failed to read streams".to_string()))?;
+            .map_err(|e| DBError::new(&format!("failed to read schema: {}", e)))?
+            .subscriptions
+            .get(name)
+            .cloned())
+    }
+
+    // the state of the persistent subscription as kept in its system stream, see persistent
+    pub fn subscription_state(&self, name: &str) -> Result<State, DBError> {
+        let details = self
+            .subscription(name)?
+            .ok_or_else(|| DBError::new(&format!("unknown subscription '{}'", name)))?;
+        let events = self
+            .store
+            .get_events(persistent::STATE_STREAM, name)
+            .map_err(|e| DBError::new(&format!("failed to read event stream: {}", e)))?
+            .unwrap_or_default();
+        State::load(details.from_position, &events)
+            .map_err(|e| DBError::new(&format!("failed to load subscription state: {}", e)))
+    }
+
+    // writes a snapshot with the name to the snapshot directory. The schema read lock keeps
+    // the schema from changing while the events are read and the store reads all stream
+    // keys at a single point in time, so the snapshot is consistent even while events are
+    // being added
+    pub fn snapshot(&self, name: &str) -> Result<(), DBError> {
+        let dir = self
+            .snapshot_dir
+            .as_ref()
+            .ok_or_else(|| DBError::new("snapshots are only supported for databases on disk"))?;
+        let path = snapshot::resolve(dir, name)
+            .map_err(|e| DBError::new(&format!("failed to write snapshot: {}", e)))?;
+
+        let snapshot = {
+            let _schema = self
+                .schema
+                .read()
+                .map_err(|e| DBError::new(&format!("failed to read schema: {}", e)))?;
+            Snapshot {
+                schema_changes: self
+                    .store
+                    .schema_changes()
+                    .map_err(|e| DBError::new(&format!("failed to read schema: {}", e)))?,
+                events: self
+                    .store
+                    .snapshot()
+                    .map_err(|e| DBError::new(&format!("failed to read events: {}", e)))?,
+            }
+        };
+        // the events are restored in the order they are in the snapshot, so they get the
+        // same positions when they are in the order they were added
+        let mut snapshot = snapshot;
+        snapshot.events.sort_by_key(|event| event.position);
 
-        let stream_arc = streams
-            .0
-            .get(&(event.stream.clone(), event.key.clone()))
-            .ok_or(DBError::new(&"stream not found".to_string()))?;
+        snapshot::write(&path, &snapshot)
+            .map_err(|e| DBError::new(&format!("failed to write snapshot: {}", e)))
+    }
 
-        let mut stream = stream_arc
-            .write()
-            .map_err(|_| DBError::new(&"failed to write to stream".to_string()))?;
+    // restores the snapshot at path, only an empty DB can be restored into
+    pub fn restore(&self, path: &Path) -> Result<(), DBError> {
+        let snapshot = snapshot::read(path)
+            .map_err(|e| DBError::new(&format!("failed to read snapshot: {}", e)))?;
 
-        if event.version != stream.last().map_or(0, |e| e.version) + 1 {
+        let is_empty = *self
+            .schema
+            .read()
+            .map_err(|e| DBError::new(&format!("failed to read schema: {}", e)))?
+            == Schema::default();
+        if !is_empty {
             return Err(DBError::new(
-                &"failed to add event as version is not serial".to_string(),
+                "failed to restore snapshot as the database is not empty",
             ));
         }
 
-        stream.push(event);
+        for change in snapshot.schema_changes {
+            self.change_schema(change)?;
+        }
+        for event in snapshot.events {
+            self.add_event(event)?;
+        }
+
         Ok(())
     }
 
+    #[cfg(test)]
     pub fn get_events(
         &self,
         stream_name: String,
         key: String,
     ) -> Result<Option<Vec<Event>>, DBError> {
-        let streams = self
+        self.store
+            .get_events(&stream_name, &key)
+            .map_err(|e| DBError::new(&format!("failed to read event stream: {}", e)))
+    }
+}
+
+// reading and adding events takes blocking locks, so it is run on the blocking thread pool
+pub async fn blocking<S, T, E>(
+    db: &Arc<DB<S>>,
+    f: impl FnOnce(&DB<S>) -> Result<T, E> + Send + 'static,
+) -> Result<T, E>
+where
+    S: EventStore + 'static,
+    T: Send + 'static,
+    E: From<DBError> + Send + 'static,
+{
+    let db = db.clone();
+    tokio::task::spawn_blocking(move || f(&db))
+        .await
+        .map_err(|e| DBError::new(&format!("failed to run: {}", e)))?
+}
+
+impl<S: EventStore> planner::Catalog for DB<S> {
+    fn stream_exists(&self, stream_name: &str) -> Result<bool, planner::PlanError> {
+        Ok(self
+            .schema
+            .read()
+            .map_err(|e| planner::PlanError::new(&format!("failed to read schema: {}", e)))?
             .streams
+            .contains(stream_name))
+    }
+
+    fn streams(&self) -> Result<Vec<String>, planner::PlanError> {
+        let mut streams = self
+            .schema
             .read()
-            .map_err(|e| DBError::new(&format!("failed to read streams: {}", e.to_string())))?;
-
-        match streams.0.get(&(stream_name, key)) {
-            Some(events_lock) => {
-                let events = events_lock.read().map_err(|e| {
-                    DBError::new(&format!("failed to read event stream: {}", e.to_string()))
-                })?;
-                Ok(Some(events.clone()))
-            }
-            None => Ok(None),
+            .map_err(|e| planner::PlanError::new(&format!("failed to read schema: {}", e)))?
+            .streams
+            .iter()
+            .cloned()
+            .collect::<Vec<String>>();
+        streams.sort();
+        Ok(streams)
+    }
+
+    fn stream_events(&self, stream_name: &str) -> Result<Vec<String>, planner::PlanError> {
+        let mut events = self
+            .schema
+            .read()
+            .map_err(|e| planner::PlanError::new(&format!("failed to read schema: {}", e)))?
+            .events
+            .iter()
+            .filter(|(stream, _)| stream == stream_name)
+            .map(|(_, event)| event.clone())
+            .collect::<Vec<String>>();
+        events.sort();
+        Ok(events)
+    }
+
+    // versions of a stream key start at 1 without gaps, so the last version of each key is
+    // the number of events it has
+    fn stream_size(&self, stream_name: &str) -> Result<u64, planner::PlanError> {
+        let read_error =
+            |e: StorageError| planner::PlanError::new(&format!("failed to read stream: {}", e));
+        let mut size = 0;
+        for key in self.store.stream_keys(stream_name).map_err(read_error)? {
+            size += self
+                .store
+                .last_version(stream_name, &key)
+                .map_err(read_error)?;
+        }
+        Ok(size)
+    }
+
+    fn has_index(
+        &self,
+        stream_name: &str,
+        event_name: &str,
+        attribute_name: &str,
+    ) -> Result<bool, planner::PlanError> {
+        Ok(self
+            .schema
+            .read()
+            .map_err(|e| planner::PlanError::new(&format!("failed to read schema: {}", e)))?
+            .indexes
+            .contains(&(
+                stream_name.to_string(),
+                event_name.to_string(),
+                attribute_name.to_string(),
+            )))
+    }
+
+    fn subscription_exists(&self, name: &str) -> Result<bool, planner::PlanError> {
+        Ok(self
+            .schema
+            .read()
+            .map_err(|e| planner::PlanError::new(&format!("failed to read schema: {}", e)))?
+            .subscriptions
+            .contains_key(name))
+    }
+
+    fn last_version(&self, stream_name: &str, key: &str) -> Result<u64, planner::PlanError> {
+        self.store
+            .last_version(stream_name, key)
+            .map_err(|e| planner::PlanError::new(&format!("failed to read stream: {}", e)))
+    }
+
+    fn event_attributes(
+        &self,
+        stream_name: &str,
+        event_name: &str,
+    ) -> Result<Option<HashMap<String, AttributeDetails>>, planner::PlanError> {
+        let schema = self
+            .schema
+            .read()
+            .map_err(|e| planner::PlanError::new(&format!("failed to read schema: {}", e)))?;
+
+        if !schema
+            .events
+            .contains(&(stream_name.to_string(), event_name.to_string()))
+        {
+            return Ok(None);
         }
+
+        Ok(Some(
+            schema
+                .attributes
+                .iter()
+                .filter(|((stream, event, _), _)| stream == stream_name && event == event_name)
+                .map(|((_, _, name), details)| (name.clone(), details.clone()))
+                .collect(),
+        ))
     }
 }
@@ -1,3 +1,6 @@
+use std::cmp::Ordering;
+use std::fmt;
+
 impl Event {
     pub fn new(
         stream: String,
@@ -12,24 +15,141 @@ impl Event {
             key,
             event,
             version,
+            position: 0,
             timestamp,
             attributes,
         };
     }
+
+    // events of streams whose names start with $ are kept by the database itself, e.g. the
+    // state of persistent subscriptions, and are not read from $all
+    pub fn is_system(&self) -> bool {
+        self.stream.starts_with('$')
+    }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Event {
     pub stream: String,
     pub key: String,
     pub event: String,
     pub version: u64,
+    // the order the event was added in across all streams, starting at 1. Set by the store
+    // when the event is appended
+    pub position: u64,
     pub timestamp: u128,
     pub attributes: Vec<Attribute>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Attribute {
     pub name: String,
-    pub value: String,
+    pub value: Value,
+}
+
+// the value of an attribute, its type follows the type declared for the attribute in the
+// schema. Null is the value of an optional attribute that has no value
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+
+// values of the same type are compared as that type, ints and floats are compared as
+// numbers. Any other combination, including null, can not be compared
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Bool(a), Value::Bool(b)) => a.partial_cmp(b),
+            (Value::Int(a), Value::Int(b)) => a.partial_cmp(b),
+            (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+            (Value::Int(a), Value::Float(b)) => (*a as f64).partial_cmp(b),
+            (Value::Float(a), Value::Int(b)) => a.partial_cmp(&(*b as f64)),
+            (Value::String(a), Value::String(b)) => a.partial_cmp(b),
+            _ => None,
+        }
+    }
+}
+
+impl Value {
+    // a total order of values, so values of different types can be ordered. Nulls are
+    // first, then bools, numbers and strings
+    pub fn total_cmp(&self, other: &Self) -> Ordering {
+        let rank = |value: &Value| match value {
+            Value::Null => 0,
+            Value::Bool(_) => 1,
+            Value::Int(_) | Value::Float(_) => 2,
+            Value::String(_) => 3,
+        };
+        match (self, other) {
+            (Value::Float(a), Value::Float(b)) => a.total_cmp(b),
+            (Value::Int(a), Value::Float(b)) => (*a as f64).total_cmp(b),
+            (Value::Float(a), Value::Int(b)) => a.total_cmp(&(*b as f64)),
+            _ => self
+                .partial_cmp(other)
+                .unwrap_or_else(|| rank(self).cmp(&rank(other))),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(v) => write!(f, "{}", v),
+            Value::Int(v) => write!(f, "{}", v),
+            // debug formatting keeps the decimal point of whole numbers
+            Value::Float(v) => write!(f, "{:?}", v),
+            Value::String(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+#[cfg(test)]
+mod event_test {
+    use super::*;
+
+    #[test]
+    fn test_compare_values() {
+        let test_cases = vec![
+            ("ints", Value::Int(1), Value::Int(2), Some(Ordering::Less)),
+            (
+                "int and float",
+                Value::Int(2),
+                Value::Float(1.5),
+                Some(Ordering::Greater),
+            ),
+            (
+                "float and int",
+                Value::Float(2.0),
+                Value::Int(2),
+                Some(Ordering::Equal),
+            ),
+            (
+                "strings",
+                Value::String("b".to_string()),
+                Value::String("a".to_string()),
+                Some(Ordering::Greater),
+            ),
+            (
+                "bools",
+                Value::Bool(false),
+                Value::Bool(true),
+                Some(Ordering::Less),
+            ),
+            ("null", Value::Null, Value::Null, None),
+            (
+                "int and string",
+                Value::Int(1),
+                Value::String("1".to_string()),
+                None,
+            ),
+        ];
+        for (name, a, b, expected) in test_cases {
+            assert_eq!(expected, a.partial_cmp(&b), "test case '{}'", name);
+        }
+    }
 }
@@ -0,0 +1,1521 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+
+use crate::ast::ast::{BinaryOperator, Direction, Function};
+use crate::cursor;
+use crate::event::{Event, Value};
+use crate::planner::{
+    function_name, operator_symbol, AsOf, EventVariable, Expression, Field, Join, JoinSide,
+    PatternQuery, Query, Subscription, Term,
+};
+use crate::storage::{EventStore, StorageError};
+
+#[derive(Debug, PartialEq)]
+pub struct Rows {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+    // returned when the page is full, to get the rows after it
+    pub cursor: Option<String>,
+}
+
+// a header line with the column names followed by a line per row, values are separated
+// by tabs. The cursor, if any, is on the last line
+impl fmt::Display for Rows {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.columns.join("\t"))?;
+        for row in self.rows.iter() {
+            let values = row.iter().map(|v| v.to_string()).collect::<Vec<String>>();
+            write!(f, "\n{}", values.join("\t"))?;
+        }
+        if let Some(cursor) = &self.cursor {
+            write!(f, "\ncursor {}", cursor)?;
+        }
+        Ok(())
+    }
+}
+
+// the values of a row together with the values it is ordered by
+struct Row {
+    position: Vec<Value>,
+    values: Vec<Value>,
+}
+
+// Rows are ordered by the expressions of order by and then by what makes them unique;
+// the stream, key and version of their events, or for grouped queries the values of their
+// group. As events are never changed a row keeps its position while events are added,
+// which lets a cursor resume right after the last row of a page
+pub fn execute(query: &Query, store: &impl EventStore) -> Result<Rows, ExecutorError> {
+    let columns = query.projections.iter().map(|p| p.name.clone()).collect();
+
+    let mut rows = vec![];
+    if query.is_grouped() {
+        for group in group(query, scan(query, store, None)?)? {
+            let evaluate = |e| evaluate_aggregate(e, &query.group_by, &group);
+            let mut position = query
+                .order_by
+                .iter()
+                .map(|o| evaluate(&o.expression))
+                .collect::<Result<Vec<Value>, ExecutorError>>()?;
+            position.extend(group.values.iter().cloned());
+            rows.push(Row {
+                position,
+                values: query
+                    .projections
+                    .iter()
+                    .map(|p| evaluate(&p.expression))
+                    .collect::<Result<Vec<Value>, ExecutorError>>()?,
+            });
+        }
+    } else {
+        // events are scanned in the order of their rows, so unless the rows are
+        // ordered by something else, start after a cursor or are joined the scan can
+        // stop early
+        let scan_limit =
+            match query.order_by.is_empty() && query.after.is_none() && query.join.is_none() {
+                true => query.limit.map(|limit| limit.saturating_add(query.offset)),
+                false => None,
+            };
+        for tuple in scan(query, store, scan_limit)? {
+            let mut position = query
+                .order_by
+                .iter()
+                .map(|o| evaluate(&o.expression, &tuple))
+                .collect::<Result<Vec<Value>, ExecutorError>>()?;
+            for event in tuple.iter() {
+                position.extend([
+                    Value::String(event.stream.clone()),
+                    Value::String(event.key.clone()),
+                    Value::Int(event.version as i64),
+                ]);
+            }
+            rows.push(Row {
+                position,
+                values: query
+                    .projections
+                    .iter()
+                    .map(|p| evaluate(&p.expression, &tuple))
+                    .collect::<Result<Vec<Value>, ExecutorError>>()?,
+            });
+        }
+    }
+
+    rows.sort_by(|a, b| compare_positions(query, &a.position, &b.position));
+    if let Some(after) = &query.after {
+        rows.retain(|row| compare_positions(query, &row.position, after) == Ordering::Greater);
+    }
+    let rows = rows
+        .into_iter()
+        .skip(query.offset)
+        .take(query.limit.unwrap_or(usize::MAX))
+        .collect::<Vec<Row>>();
+
+    let cursor = match (query.limit, rows.last()) {
+        (Some(limit), Some(last)) if rows.len() == limit => {
+            Some(cursor::encode(&query.fingerprint(), &last.position))
+        }
+        _ => None,
+    };
+    Ok(Rows {
+        columns,
+        rows: rows.into_iter().map(|row| row.values).collect(),
+        cursor,
+    })
+}
+
+// a row per event of all streams from the position, in the order the events were added.
+// The events of system streams take up positions but are left out, so there can be fewer
+// rows than the limit
+pub fn read_all(
+    from_position: u64,
+    limit: Option<usize>,
+    store: &impl EventStore,
+) -> Result<Rows, ExecutorError> {
+    let events = store.read_all(from_position, limit.unwrap_or(usize::MAX))?;
+    Ok(event_rows(
+        events
+            .into_iter()
+            .filter(|event| !event.is_system())
+            .collect(),
+    ))
+}
+
+// a row per event with its position, stream, key, version, event name, timestamp and
+// attributes. The attributes are written as in add, e.g. owner-name="axel", balance=100
+pub fn event_rows(events: Vec<Event>) -> Rows {
+    let columns = [
+        "position",
+        "stream",
+        "key",
+        "version",
+        "event",
+        "timestamp",
+        "attributes",
+    ]
+    .into_iter()
+    .map(|c| c.to_string())
+    .collect();
+    let rows = events
+        .into_iter()
+        .map(|event| {
+            let attributes = event
+                .attributes
+                .iter()
+                .map(|a| match &a.value {
+                    Value::String(v) => format!("{}={:?}", a.name, v),
+                    value => format!("{}={}", a.name, value),
+                })
+                .collect::<Vec<String>>()
+                .join(", ");
+            vec![
+                Value::Int(event.position as i64),
+                Value::String(event.stream),
+                Value::String(event.key),
+                Value::Int(event.version as i64),
+                Value::String(event.event),
+                Value::Int(event.timestamp as i64),
+                Value::String(attributes),
+            ]
+        })
+        .collect();
+
+    Rows {
+        columns,
+        rows,
+        cursor: None,
+    }
+}
+
+// whether the event is of the stream of the subscription and matches its predicates,
+// events of system streams are not of $all
+pub fn is_subscribed(subscription: &Subscription, event: &Event) -> Result<bool, ExecutorError> {
+    let is_of_stream = match &subscription.stream {
+        Some(stream) => *stream == event.stream,
+        None => !event.is_system(),
+    };
+    if !is_of_stream {
+        return Ok(false);
+    }
+    is_true(&subscription.predicates, std::slice::from_ref(event))
+}
+
+// Events are matched to the patterns of each event variable in turn and joined with the
+// rows so far on the variables they share, by comparing every pair of rows. A predicate
+// is applied as soon as all its variables are bound
+pub fn execute_pattern(
+    query: &PatternQuery,
+    store: &impl EventStore,
+) -> Result<Rows, ExecutorError> {
+    let mut bindings = Bindings {
+        variables: vec![],
+        rows: vec![vec![]],
+    };
+    let mut applied = vec![false; query.predicates.len()];
+    for entity in query.entities.iter() {
+        bindings = join(bindings, bind(entity, store)?);
+
+        for (predicate, applied) in query.predicates.iter().zip(applied.iter_mut()) {
+            let mut used = vec![];
+            predicate.variables(&mut used);
+            if *applied
+                || !used
+                    .iter()
+                    .all(|v| bindings.variables.iter().any(|b| b == v))
+            {
+                continue;
+            }
+            *applied = true;
+
+            let mut rows = vec![];
+            for row in bindings.rows {
+                match evaluate_term(predicate, &bindings.variables, &row)? {
+                    Value::Bool(true) => rows.push(row),
+                    Value::Bool(false) | Value::Null => {}
+                    value => {
+                        return Err(ExecutorError::new(&format!(
+                            "predicate '{}' must be true or false, got '{}'",
+                            predicate, value
+                        )))
+                    }
+                }
+            }
+            bindings.rows = rows;
+        }
+    }
+
+    let positions = query
+        .variables
+        .iter()
+        .map(|name| variable_position(&bindings.variables, name))
+        .collect::<Result<Vec<usize>, ExecutorError>>()?;
+    // values are not hashable, so rows are told apart by the debug format of their values
+    let mut seen = HashSet::new();
+    let mut rows = vec![];
+    for row in bindings.rows {
+        let row = positions
+            .iter()
+            .map(|i| row[*i].clone())
+            .collect::<Vec<Value>>();
+        if seen.insert(format!("{:?}", row)) {
+            rows.push(row);
+        }
+    }
+
+    Ok(Rows {
+        columns: query.variables.iter().map(|v| format!("?{}", v)).collect(),
+        rows,
+        cursor: None,
+    })
+}
+
+// the values of the variables for each row of a pattern query
+struct Bindings {
+    variables: Vec<String>,
+    rows: Vec<Vec<Value>>,
+}
+
+// a row per event that matches all patterns of the event variable, with the values of
+// the variables of the patterns
+fn bind(entity: &EventVariable, store: &impl EventStore) -> Result<Bindings, ExecutorError> {
+    let mut variables: Vec<String> = vec![];
+    for pattern in entity.patterns.iter() {
+        if let Term::Variable(name) = &pattern.value {
+            if !variables.contains(name) {
+                variables.push(name.clone());
+            }
+        }
+    }
+
+    let mut rows = vec![];
+    for stream in entity.streams.iter() {
+        for key in store.stream_keys(stream)? {
+            for event in store.get_events(stream, &key)?.unwrap_or_default() {
+                if let Some(row) = bind_event(entity, &variables, &event) {
+                    rows.push(row);
+                }
+            }
+        }
+    }
+    Ok(Bindings { variables, rows })
+}
+
+// the values of the variables if the event matches all patterns. A pattern on an
+// attribute the event does not have, or has as null, does not match
+fn bind_event(entity: &EventVariable, variables: &[String], event: &Event) -> Option<Vec<Value>> {
+    let mut row = vec![Value::Null; variables.len()];
+    for pattern in entity.patterns.iter() {
+        let value = field(event, &pattern.field);
+        if value == Value::Null {
+            return None;
+        }
+        match &pattern.value {
+            Term::Variable(name) => {
+                let i = variables.iter().position(|v| v == name)?;
+                if row[i] == Value::Null {
+                    row[i] = value;
+                } else if !is_equal(&row[i], &value) {
+                    return None;
+                }
+            }
+            Term::Literal(literal) => {
+                if !is_equal(literal, &value) {
+                    return None;
+                }
+            }
+            Term::Binary { .. } => return None,
+        }
+    }
+    Some(row)
+}
+
+fn field(event: &Event, field: &Field) -> Value {
+    match field {
+        Field::Stream => Value::String(event.stream.clone()),
+        Field::Key => Value::String(event.key.clone()),
+        Field::Event => Value::String(event.event.clone()),
+        Field::Version => Value::Int(event.version as i64),
+        Field::Timestamp => Value::Int(event.timestamp as i64),
+        Field::Attribute(name) => event
+            .attributes
+            .iter()
+            .find(|a| &a.name == name)
+            .map_or(Value::Null, |a| a.value.clone()),
+    }
+}
+
+// the rows of both sides combined where the values of the variables they share are equal
+fn join(left: Bindings, right: Bindings) -> Bindings {
+    let mut shared = vec![];
+    let mut added = vec![];
+    for (r, variable) in right.variables.iter().enumerate() {
+        match left.variables.iter().position(|v| v == variable) {
+            Some(l) => shared.push((l, r)),
+            None => added.push(r),
+        }
+    }
+
+    let mut variables = left.variables;
+    variables.extend(added.iter().map(|r| right.variables[*r].clone()));
+    let mut rows = vec![];
+    for l in left.rows.iter() {
+        for r in right.rows.iter() {
+            if shared.iter().all(|(li, ri)| is_equal(&l[*li], &r[*ri])) {
+                let mut row = l.clone();
+                row.extend(added.iter().map(|i| r[*i].clone()));
+                rows.push(row);
+            }
+        }
+    }
+    Bindings { variables, rows }
+}
+
+fn evaluate_term(term: &Term, variables: &[String], row: &[Value]) -> Result<Value, ExecutorError> {
+    match term {
+        Term::Variable(name) => Ok(row[variable_position(variables, name)?].clone()),
+        Term::Literal(value) => Ok(value.clone()),
+        Term::Binary {
+            left,
+            operator,
+            right,
+        } => binary(
+            evaluate_term(left, variables, row)?,
+            operator,
+            evaluate_term(right, variables, row)?,
+        ),
+    }
+}
+
+fn variable_position(variables: &[String], name: &str) -> Result<usize, ExecutorError> {
+    variables
+        .iter()
+        .position(|v| v == name)
+        .ok_or_else(|| ExecutorError::new(&format!("variable '?{}' is not bound", name)))
+}
+
+// like =, values of different types are never equal but ints and floats are compared as
+// numbers
+fn is_equal(a: &Value, b: &Value) -> bool {
+    a.partial_cmp(b) == Some(Ordering::Equal)
+}
+
+fn compare_positions(query: &Query, a: &[Value], b: &[Value]) -> Ordering {
+    for (i, (a, b)) in a.iter().zip(b.iter()).enumerate() {
+        let ordering = match query.order_by.get(i).map(|o| o.direction) {
+            Some(Direction::Desc) => a.total_cmp(b).reverse(),
+            _ => a.total_cmp(b),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+// the events a row is computed from; a single event, or an event of each stream of a join
+type Tuple = Vec<Event>;
+
+// events read from the store in batches
+type Batches<'a> = Box<dyn Iterator<Item = Result<Vec<Event>, StorageError>> + 'a>;
+
+// tuples with equal values for the expressions that are grouped by
+struct Group {
+    values: Vec<Value>,
+    tuples: Vec<Tuple>,
+}
+
+// groups are in the order their first tuple was scanned. Without group by all tuples
+// are in a single group, which is there even if there are no tuples so aggregates over
+// no events give a row
+fn group(query: &Query, tuples: Vec<Tuple>) -> Result<Vec<Group>, ExecutorError> {
+    if query.group_by.is_empty() {
+        return Ok(vec![Group {
+            values: vec![],
+            tuples,
+        }]);
+    }
+
+    let mut groups: Vec<Group> = vec![];
+    // values are not hashable, so groups are looked up by the keys of their values (see
+    // value_key), which keeps e.g. the string "1" and the int 1 apart
+    let mut index = HashMap::new();
+    for tuple in tuples {
+        let values = query
+            .group_by
+            .iter()
+            .map(|e| evaluate(e, &tuple))
+            .collect::<Result<Vec<Value>, ExecutorError>>()?;
+        let key = values.iter().map(value_key).collect::<Vec<String>>();
+        let i = *index.entry(key).or_insert_with(|| {
+            groups.push(Group {
+                values,
+                tuples: vec![],
+            });
+            groups.len() - 1
+        });
+        groups[i].tuples.push(tuple);
+    }
+    Ok(groups)
+}
+
+// tuples of the streams that match all predicates, ordered by stream, key and version
+// unless the query is a join. For queries that are not grouped tuples that have none of
+// the projected attributes are skipped, as they would only give rows of nulls
+fn scan(
+    query: &Query,
+    store: &impl EventStore,
+    limit: Option<usize>,
+) -> Result<Vec<Tuple>, ExecutorError> {
+    let mut projected = vec![];
+    if !query.is_grouped() {
+        for projection in query.projections.iter() {
+            projection.expression.attributes(&mut projected);
+        }
+    }
+
+    if let Some(join) = &query.join {
+        let mut matches = vec![];
+        for tuple in hash_join(query, join, store)? {
+            if is_match(query, &projected, &tuple)? {
+                matches.push(tuple);
+            }
+        }
+        return Ok(matches);
+    }
+
+    let mut matches = vec![];
+    for stream in query.streams.iter() {
+        for events in read_stream(query, stream, store)? {
+            for event in events? {
+                let tuple = vec![event];
+                if !is_match(query, &projected, &tuple)? {
+                    continue;
+                }
+                matches.push(tuple);
+                if limit.is_some_and(|limit| matches.len() >= limit) {
+                    return Ok(matches);
+                }
+            }
+        }
+    }
+    Ok(matches)
+}
+
+// the events of the stream in batches ordered by key and version, a batch per stream key.
+// Only the events in the index range, time range or of the event types of the query are
+// read if it has one, and only the versions up to the version the query is as of
+fn read_stream<'a>(
+    query: &Query,
+    stream: &'a str,
+    store: &'a impl EventStore,
+) -> Result<Batches<'a>, ExecutorError> {
+    let to_version = match query.as_of {
+        Some(AsOf::Version(version)) => version,
+        _ => u64::MAX,
+    };
+
+    if let Some(index) = query.index.as_ref().filter(|index| index.stream == stream) {
+        let range = (index.from.as_ref(), index.to.as_ref());
+        let events = store
+            .read_index(stream, &index.event, &index.attribute, range)
+            .map(|events| {
+                events
+                    .into_iter()
+                    .filter(|e| e.version <= to_version)
+                    .filter(|e| {
+                        query
+                            .time_range
+                            .is_none_or(|(from, to)| (from..=to).contains(&e.timestamp))
+                    })
+                    .collect()
+            });
+        return Ok(Box::new(std::iter::once(events)));
+    }
+
+    if let Some((from, to)) = query.time_range {
+        let events = store.read_time_range(stream, from, to).map(|events| {
+            events
+                .into_iter()
+                .filter(|e| e.version <= to_version)
+                .collect()
+        });
+        return Ok(Box::new(std::iter::once(events)));
+    }
+
+    if let Some(event_types) = &query.event_types {
+        let event_names = event_types
+            .iter()
+            .filter(|(s, _)| s == stream)
+            .map(|(_, event)| event.clone())
+            .collect::<Vec<String>>();
+        let events = store.read_event_types(stream, &event_names).map(|events| {
+            events
+                .into_iter()
+                .filter(|e| e.version <= to_version)
+                .collect()
+        });
+        return Ok(Box::new(std::iter::once(events)));
+    }
+
+    let keys = store.stream_keys(stream)?;
+    Ok(Box::new(keys.into_iter().map(move |key| {
+        store.read_range(stream, &key, 1, to_version)
+    })))
+}
+
+// The events of the build side are put in a hash table by the value of their attribute,
+// then each event of the probe side is paired with the events in the table with an equal
+// value. Events of either side that do not match the predicates of their side are left
+// out before they are joined. The events of a tuple are in order of their stream
+fn hash_join(
+    query: &Query,
+    join: &Join,
+    store: &impl EventStore,
+) -> Result<Vec<Tuple>, ExecutorError> {
+    let mut table: HashMap<String, Vec<Event>> = HashMap::new();
+    for event in scan_side(query, &join.build, store)? {
+        let value = evaluate(&join.build.attribute, std::slice::from_ref(&event))?;
+        if let Some(key) = join_key(&value) {
+            table.entry(key).or_default().push(event);
+        }
+    }
+
+    let mut tuples = vec![];
+    for event in scan_side(query, &join.probe, store)? {
+        let value = evaluate(&join.probe.attribute, std::slice::from_ref(&event))?;
+        let Some(matches) = join_key(&value).and_then(|key| table.get(&key)) else {
+            continue;
+        };
+        for build in matches {
+            let mut tuple = vec![build.clone(), event.clone()];
+            tuple.sort_by(|a, b| a.stream.cmp(&b.stream));
+            tuples.push(tuple);
+        }
+    }
+    Ok(tuples)
+}
+
+// events of the stream of the side that match its predicates
+fn scan_side(
+    query: &Query,
+    side: &JoinSide,
+    store: &impl EventStore,
+) -> Result<Vec<Event>, ExecutorError> {
+    let mut matches = vec![];
+    for events in read_stream(query, &side.stream, store)? {
+        for event in events? {
+            let tuple = std::slice::from_ref(&event);
+            if is_true(&side.predicates, tuple)? {
+                matches.push(event);
+            }
+        }
+    }
+    Ok(matches)
+}
+
+// the value as a key of the hash table of a join. Like =, null is not equal to anything
+fn join_key(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::Float(v) if v.is_nan() => None,
+        value => Some(value_key(value)),
+    }
+}
+
+// the value as a hashable key, like = ints and floats have the same key if they are the
+// same number
+fn value_key(value: &Value) -> String {
+    match value {
+        Value::Float(v) if v.fract() == 0.0 && v.abs() < i64::MAX as f64 => {
+            format!("{:?}", Value::Int(*v as i64))
+        }
+        value => format!("{:?}", value),
+    }
+}
+
+fn is_match(
+    query: &Query,
+    projected: &[&Expression],
+    tuple: &[Event],
+) -> Result<bool, ExecutorError> {
+    let has_projected_attribute = projected.is_empty()
+        || projected
+            .iter()
+            .any(|attribute| evaluate(attribute, tuple).is_ok_and(|v| v != Value::Null));
+    if !has_projected_attribute {
+        return Ok(false);
+    }
+    is_true(&query.predicates, tuple)
+}
+
+fn is_true(predicates: &[Expression], tuple: &[Event]) -> Result<bool, ExecutorError> {
+    for predicate in predicates.iter() {
+        match evaluate(predicate, tuple)? {
+            Value::Bool(true) => {}
+            Value::Bool(false) | Value::Null => return Ok(false),
+            value => {
+                return Err(ExecutorError::new(&format!(
+                    "predicate '{}' must be true or false, got '{}'",
+                    predicate, value
+                )))
+            }
+        }
+    }
+    Ok(true)
+}
+
+// the value of the attribute of the event of the stream in the tuple, null if there is
+// no such event, it does not have the attribute or it is not of the event type
+fn attribute(tuple: &[Event], stream: &str, event_name: Option<&str>, name: &str) -> Value {
+    let Some(event) = tuple.iter().find(|e| e.stream == stream) else {
+        return Value::Null;
+    };
+    if event_name.is_some_and(|e| e != event.event) {
+        return Value::Null;
+    }
+    event
+        .attributes
+        .iter()
+        .find(|a| a.name == name)
+        .map_or(Value::Null, |a| a.value.clone())
+}
+
+// key, timestamp, version and event are only used in queries on a single stream at a
+// time, so the tuple has one event
+fn evaluate(expression: &Expression, tuple: &[Event]) -> Result<Value, ExecutorError> {
+    match expression {
+        Expression::Literal(value) => Ok(value.clone()),
+        Expression::Attribute {
+            stream,
+            event: event_name,
+            name,
+        } => Ok(attribute(tuple, stream, event_name.as_deref(), name)),
+        Expression::Key => Ok(tuple
+            .first()
+            .map_or(Value::Null, |e| Value::String(e.key.clone()))),
+        Expression::Timestamp => Ok(tuple
+            .first()
+            .map_or(Value::Null, |e| Value::Int(e.timestamp as i64))),
+        Expression::Version => Ok(tuple
+            .first()
+            .map_or(Value::Null, |e| Value::Int(e.version as i64))),
+        Expression::Event => Ok(tuple
+            .first()
+            .map_or(Value::Null, |e| Value::String(e.event.clone()))),
+        Expression::Aggregate { .. } => Err(ExecutorError::new(&format!(
+            "aggregate '{}' can not be evaluated for a single event",
+            expression
+        ))),
+        Expression::Negate(operand) => negate(evaluate(operand, tuple)?),
+        Expression::Not(operand) => not(evaluate(operand, tuple)?),
+        Expression::Binary {
+            left,
+            operator,
+            right,
+        } => binary(evaluate(left, tuple)?, operator, evaluate(right, tuple)?),
+        Expression::In {
+            expression,
+            list,
+            negated,
+        } => {
+            let list = list
+                .iter()
+                .map(|e| evaluate(e, tuple))
+                .collect::<Result<Vec<Value>, ExecutorError>>()?;
+            in_list(evaluate(expression, tuple)?, list, *negated)
+        }
+    }
+}
+
+// aggregates are computed over the tuples of the group and expressions that are grouped
+// by are the values of the group, everything else in the expression is evaluated once
+// with their results
+fn evaluate_aggregate(
+    expression: &Expression,
+    group_by: &[Expression],
+    group: &Group,
+) -> Result<Value, ExecutorError> {
+    if let Some(i) = group_by.iter().position(|e| e == expression) {
+        return Ok(group.values[i].clone());
+    }
+
+    match expression {
+        Expression::Literal(value) => Ok(value.clone()),
+        Expression::Attribute { .. }
+        | Expression::Key
+        | Expression::Timestamp
+        | Expression::Version
+        | Expression::Event => Err(ExecutorError::new(&format!(
+            "'{}' must be used in an aggregate or be in group by",
+            expression
+        ))),
+        Expression::Aggregate { function, argument } => {
+            let values = group
+                .tuples
+                .iter()
+                .map(|tuple| evaluate(argument, tuple))
+                .collect::<Result<Vec<Value>, ExecutorError>>()?;
+            aggregate(function, values)
+        }
+        Expression::Negate(operand) => negate(evaluate_aggregate(operand, group_by, group)?),
+        Expression::Not(operand) => not(evaluate_aggregate(operand, group_by, group)?),
+        Expression::Binary {
+            left,
+            operator,
+            right,
+        } => binary(
+            evaluate_aggregate(left, group_by, group)?,
+            operator,
+            evaluate_aggregate(right, group_by, group)?,
+        ),
+        Expression::In {
+            expression,
+            list,
+            negated,
+        } => {
+            let list = list
+                .iter()
+                .map(|e| evaluate_aggregate(e, group_by, group))
+                .collect::<Result<Vec<Value>, ExecutorError>>()?;
+            in_list(
+                evaluate_aggregate(expression, group_by, group)?,
+                list,
+                *negated,
+            )
+        }
+    }
+}
+
+// null values are skipped. count is an int, avg is a float, sum is an int if all values
+// are ints and min and max have the type of the values. first, last and nth pick a value
+// in the order the events were scanned, which is by version for events of a stream key
+fn aggregate(function: &Function, values: Vec<Value>) -> Result<Value, ExecutorError> {
+    let values = values
+        .into_iter()
+        .filter(|v| *v != Value::Null)
+        .collect::<Vec<Value>>();
+
+    match function {
+        Function::Count => Ok(Value::Int(values.len() as i64)),
+        // the sum of no values is 0
+        Function::Sum => values.into_iter().try_fold(Value::Int(0), |sum, value| {
+            binary(sum, &BinaryOperator::Add, numeric(function, value)?)
+        }),
+        // the average of no values is null
+        Function::Avg if values.is_empty() => Ok(Value::Null),
+        Function::Avg => {
+            let n = values.len() as f64;
+            let sum = values.into_iter().try_fold(0.0, |sum, value| {
+                Ok::<f64, ExecutorError>(sum + as_float(&numeric(function, value)?))
+            })?;
+            Ok(Value::Float(sum / n))
+        }
+        Function::Min => extreme(function, values, Ordering::Less),
+        Function::Max => extreme(function, values, Ordering::Greater),
+        // null if there are not enough values
+        Function::First => Ok(values.into_iter().next().unwrap_or(Value::Null)),
+        Function::Last => Ok(values.into_iter().last().unwrap_or(Value::Null)),
+        Function::Nth(n) => Ok(values
+            .into_iter()
+            .nth(*n as usize - 1)
+            .unwrap_or(Value::Null)),
+    }
+}
+
+fn numeric(function: &Function, value: Value) -> Result<Value, ExecutorError> {
+    match value {
+        Value::Int(_) | Value::Float(_) => Ok(value),
+        value => Err(ExecutorError::new(&format!(
+            "{} can only be used on numbers, got '{}'",
+            function_name(function),
+            value
+        ))),
+    }
+}
+
+// the value that is ordered first in the direction, null if there are no values
+fn extreme(
+    function: &Function,
+    values: Vec<Value>,
+    direction: Ordering,
+) -> Result<Value, ExecutorError> {
+    let mut result = Value::Null;
+    for value in values {
+        if result == Value::Null {
+            result = value;
+            continue;
+        }
+        match value.partial_cmp(&result) {
+            Some(ordering) if ordering == direction => result = value,
+            Some(_) => {}
+            None => {
+                return Err(ExecutorError::new(&format!(
+                    "{} can not compare '{}' and '{}'",
+                    function_name(function),
+                    result,
+                    value
+                )))
+            }
+        }
+    }
+    Ok(result)
+}
+
+fn negate(value: Value) -> Result<Value, ExecutorError> {
+    match value {
+        Value::Null => Ok(Value::Null),
+        Value::Int(v) => v
+            .checked_neg()
+            .map(Value::Int)
+            .ok_or_else(|| ExecutorError::new(&format!("overflow when negating {}", v))),
+        Value::Float(v) => Ok(Value::Float(-v)),
+        value => Err(ExecutorError::new(&format!("can not negate '{}'", value))),
+    }
+}
+
+fn not(value: Value) -> Result<Value, ExecutorError> {
+    match value {
+        Value::Null => Ok(Value::Null),
+        Value::Bool(v) => Ok(Value::Bool(!v)),
+        value => Err(ExecutorError::new(&format!(
+            "not can only be used on true or false, got '{}'",
+            value
+        ))),
+    }
+}
+
+// null if the value is null, or if it is not in the list but the list has a null. Like
+// for =, values of different types are never equal
+fn in_list(value: Value, list: Vec<Value>, negated: bool) -> Result<Value, ExecutorError> {
+    if value == Value::Null {
+        return Ok(Value::Null);
+    }
+    let mut result = Value::Bool(false);
+    for item in list {
+        match binary(value.clone(), &BinaryOperator::Equal, item)? {
+            Value::Bool(true) => {
+                result = Value::Bool(true);
+                break;
+            }
+            Value::Null => result = Value::Null,
+            _ => {}
+        }
+    }
+    match negated {
+        true => not(result),
+        false => Ok(result),
+    }
+}
+
+// and and or are true, false or null (unknown) where null is only the result if the
+// other value does not decide it, e.g. false and null is false
+fn logical(left: Value, operator: &BinaryOperator, right: Value) -> Result<Value, ExecutorError> {
+    let as_bool = |value: &Value| match value {
+        Value::Null => Ok(None),
+        Value::Bool(v) => Ok(Some(*v)),
+        _ => Err(operator_error(&left, operator, &right)),
+    };
+    let (l, r) = (as_bool(&left)?, as_bool(&right)?);
+    let decided_by = *operator == BinaryOperator::Or;
+    let value = match (l, r) {
+        (Some(v), _) | (_, Some(v)) if v == decided_by => Value::Bool(decided_by),
+        (Some(_), Some(_)) => Value::Bool(!decided_by),
+        _ => Value::Null,
+    };
+    Ok(value)
+}
+
+// ints are only kept as ints if both values are ints, any operation with null is null
+// except for and and or
+fn binary(left: Value, operator: &BinaryOperator, right: Value) -> Result<Value, ExecutorError> {
+    if matches!(operator, BinaryOperator::And | BinaryOperator::Or) {
+        return logical(left, operator, right);
+    }
+    if left == Value::Null || right == Value::Null {
+        return Ok(Value::Null);
+    }
+
+    let value = match operator {
+        BinaryOperator::Add
+        | BinaryOperator::Subtract
+        | BinaryOperator::Multiply
+        | BinaryOperator::Divide
+        | BinaryOperator::Modulus => arithmetic(&left, operator, &right)?,
+        BinaryOperator::Equal => Value::Bool(left.partial_cmp(&right) == Some(Ordering::Equal)),
+        BinaryOperator::NotEqual => Value::Bool(left.partial_cmp(&right) != Some(Ordering::Equal)),
+        BinaryOperator::LessThan => Value::Bool(left < right),
+        BinaryOperator::GreaterThan => Value::Bool(left > right),
+        BinaryOperator::LessEqual => Value::Bool(left <= right),
+        BinaryOperator::GreaterEqual => Value::Bool(left >= right),
+        // and and or are evaluated by logical, in and not in by in_list as their right
+        // hand side is a list
+        BinaryOperator::And | BinaryOperator::Or | BinaryOperator::In | BinaryOperator::NotIn => {
+            return Err(operator_error(&left, operator, &right))
+        }
+    };
+    Ok(value)
+}
+
+fn arithmetic(
+    left: &Value,
+    operator: &BinaryOperator,
+    right: &Value,
+) -> Result<Value, ExecutorError> {
+    match (left, right) {
+        (Value::Int(l), Value::Int(r)) => {
+            let result = match operator {
+                BinaryOperator::Add => l.checked_add(*r),
+                BinaryOperator::Subtract => l.checked_sub(*r),
+                BinaryOperator::Multiply => l.checked_mul(*r),
+                BinaryOperator::Divide if *r == 0 => {
+                    return Err(ExecutorError::new("division by zero"))
+                }
+                BinaryOperator::Divide => l.checked_div(*r),
+                BinaryOperator::Modulus if *r == 0 => {
+                    return Err(ExecutorError::new("division by zero"))
+                }
+                BinaryOperator::Modulus => l.checked_rem(*r),
+                _ => return Err(operator_error(left, operator, right)),
+            };
+            result.map(Value::Int).ok_or_else(|| {
+                ExecutorError::new(&format!(
+                    "overflow in {} {} {}",
+                    l,
+                    operator_symbol(operator),
+                    r
+                ))
+            })
+        }
+        (Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => {
+            let (l, r) = (as_float(left), as_float(right));
+            let result = match operator {
+                BinaryOperator::Add => l + r,
+                BinaryOperator::Subtract => l - r,
+                BinaryOperator::Multiply => l * r,
+                BinaryOperator::Divide => l / r,
+                BinaryOperator::Modulus => l % r,
+                _ => return Err(operator_error(left, operator, right)),
+            };
+            Ok(Value::Float(result))
+        }
+        _ => Err(operator_error(left, operator, right)),
+    }
+}
+
+fn as_float(value: &Value) -> f64 {
+    match value {
+        Value::Int(v) => *v as f64,
+        Value::Float(v) => *v,
+        _ => f64::NAN,
+    }
+}
+
+fn operator_error(left: &Value, operator: &BinaryOperator, right: &Value) -> ExecutorError {
+    ExecutorError::new(&format!(
+        "can not apply '{}' to '{}' and '{}'",
+        operator_symbol(operator),
+        left,
+        right
+    ))
+}
+
+#[derive(Debug)]
+pub struct ExecutorError {
+    message: String,
+}
+
+impl ExecutorError {
+    fn new(message: &str) -> Self {
+        ExecutorError {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ExecutorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for ExecutorError {}
+
+impl From<crate::storage::StorageError> for ExecutorError {
+    fn from(e: crate::storage::StorageError) -> ExecutorError {
+        ExecutorError::new(&format!("failed to read events: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod executor_test {
+    use super::*;
+    use crate::event::Attribute;
+    use crate::planner::Projection;
+    use crate::storage::memory::MemoryStore;
+
+    fn attribute(name: &str) -> Expression {
+        Expression::Attribute {
+            stream: "account".to_string(),
+            event: None,
+            name: name.to_string(),
+        }
+    }
+
+    fn projection(expression: Expression) -> Projection {
+        Projection {
+            name: expression.to_string(),
+            expression,
+        }
+    }
+
+    fn store() -> MemoryStore {
+        let store = MemoryStore::new();
+        for (key, event, attributes) in [
+            (
+                "123",
+                "AccountCreated",
+                vec![("owner", Value::String("axel".to_string()))],
+            ),
+            ("123", "MoneyDeposited", vec![("amount", Value::Int(100))]),
+            ("123", "MoneyDeposited", vec![("amount", Value::Int(50))]),
+            (
+                "456",
+                "AccountCreated",
+                vec![("owner", Value::String("bob".to_string()))],
+            ),
+            ("456", "MoneyDeposited", vec![("amount", Value::Float(2.5))]),
+        ] {
+            let version = store.last_version("account", key).unwrap();
+            let event = Event::new(
+                "account".to_string(),
+                key.to_string(),
+                event.to_string(),
+                0,
+                1700000000000,
+                attributes
+                    .into_iter()
+                    .map(|(name, value)| Attribute {
+                        name: name.to_string(),
+                        value,
+                    })
+                    .collect(),
+            );
+            store.append(event, version).unwrap();
+        }
+        store
+    }
+
+    #[test]
+    fn test_execute() {
+        let store = store();
+        let test_cases = vec![
+            (
+                "events with the projected attribute",
+                Query {
+                    streams: vec!["account".to_string()],
+                    predicates: vec![],
+                    group_by: vec![],
+                    order_by: vec![],
+                    join: None,
+                    time_range: None,
+                    index: None,
+                    event_types: None,
+                    as_of: None,
+                    projections: vec![projection(attribute("amount"))],
+                    limit: None,
+                    offset: 0,
+                    after: None,
+                },
+                vec![
+                    vec![Value::Int(100)],
+                    vec![Value::Int(50)],
+                    vec![Value::Float(2.5)],
+                ],
+            ),
+            (
+                "predicate and limit",
+                Query {
+                    streams: vec!["account".to_string()],
+                    predicates: vec![Expression::Binary {
+                        left: Box::new(attribute("amount")),
+                        operator: BinaryOperator::LessThan,
+                        right: Box::new(Expression::Literal(Value::Int(100))),
+                    }],
+                    group_by: vec![],
+                    order_by: vec![],
+                    join: None,
+                    time_range: None,
+                    index: None,
+                    event_types: None,
+                    as_of: None,
+                    projections: vec![projection(Expression::Binary {
+                        left: Box::new(attribute("amount")),
+                        operator: BinaryOperator::Multiply,
+                        right: Box::new(Expression::Literal(Value::Int(2))),
+                    })],
+                    limit: Some(1),
+                    offset: 0,
+                    after: None,
+                },
+                vec![vec![Value::Int(100)]],
+            ),
+            (
+                "aggregate",
+                Query {
+                    streams: vec!["account".to_string()],
+                    predicates: vec![],
+                    group_by: vec![],
+                    order_by: vec![],
+                    join: None,
+                    time_range: None,
+                    index: None,
+                    event_types: None,
+                    as_of: None,
+                    projections: vec![projection(Expression::Binary {
+                        left: Box::new(Expression::Aggregate {
+                            function: Function::Sum,
+                            argument: Box::new(attribute("amount")),
+                        }),
+                        operator: BinaryOperator::Subtract,
+                        right: Box::new(Expression::Literal(Value::Int(2))),
+                    })],
+                    limit: None,
+                    offset: 0,
+                    after: None,
+                },
+                vec![vec![Value::Float(150.5)]],
+            ),
+            (
+                "aggregate without matches",
+                Query {
+                    streams: vec!["account".to_string()],
+                    predicates: vec![Expression::Binary {
+                        left: Box::new(attribute("owner")),
+                        operator: BinaryOperator::Equal,
+                        right: Box::new(Expression::Literal(Value::String("carl".to_string()))),
+                    }],
+                    group_by: vec![],
+                    order_by: vec![],
+                    join: None,
+                    time_range: None,
+                    index: None,
+                    event_types: None,
+                    as_of: None,
+                    projections: vec![projection(Expression::Aggregate {
+                        function: Function::Sum,
+                        argument: Box::new(attribute("amount")),
+                    })],
+                    limit: None,
+                    offset: 0,
+                    after: None,
+                },
+                vec![vec![Value::Int(0)]],
+            ),
+            (
+                "group by key",
+                Query {
+                    streams: vec!["account".to_string()],
+                    predicates: vec![],
+                    group_by: vec![Expression::Key],
+                    order_by: vec![],
+                    join: None,
+                    time_range: None,
+                    index: None,
+                    event_types: None,
+                    as_of: None,
+                    projections: vec![
+                        projection(Expression::Key),
+                        projection(Expression::Aggregate {
+                            function: Function::Sum,
+                            argument: Box::new(attribute("amount")),
+                        }),
+                        projection(Expression::Aggregate {
+                            function: Function::Count,
+                            argument: Box::new(attribute("owner")),
+                        }),
+                    ],
+                    limit: None,
+                    offset: 0,
+                    after: None,
+                },
+                vec![
+                    vec![
+                        Value::String("123".to_string()),
+                        Value::Int(150),
+                        Value::Int(1),
+                    ],
+                    vec![
+                        Value::String("456".to_string()),
+                        Value::Float(2.5),
+                        Value::Int(1),
+                    ],
+                ],
+            ),
+            (
+                "group by attribute with limit",
+                Query {
+                    streams: vec!["account".to_string()],
+                    predicates: vec![],
+                    group_by: vec![attribute("amount")],
+                    order_by: vec![],
+                    join: None,
+                    time_range: None,
+                    index: None,
+                    event_types: None,
+                    as_of: None,
+                    projections: vec![projection(Expression::Aggregate {
+                        function: Function::Count,
+                        argument: Box::new(Expression::Key),
+                    })],
+                    limit: Some(2),
+                    offset: 0,
+                    after: None,
+                },
+                // events without an amount are grouped together under null
+                vec![vec![Value::Int(2)], vec![Value::Int(1)]],
+            ),
+        ];
+
+        for (name, query, expected) in test_cases {
+            let rows = match execute(&query, &store) {
+                Ok(rows) => rows,
+                Err(e) => panic!("test case '{}' failed: {}", name, e),
+            };
+            assert_eq!(expected, rows.rows, "test case '{}'", name);
+        }
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let values = vec![Value::Int(3), Value::Null, Value::Int(1), Value::Int(2)];
+        let mixed = vec![Value::Int(1), Value::Float(2.5)];
+        let strings = vec![
+            Value::String("b".to_string()),
+            Value::String("a".to_string()),
+        ];
+        let test_cases = vec![
+            (Function::Count, values.clone(), Ok(Value::Int(3))),
+            (Function::Count, vec![], Ok(Value::Int(0))),
+            (Function::Sum, values.clone(), Ok(Value::Int(6))),
+            (Function::Sum, mixed.clone(), Ok(Value::Float(3.5))),
+            (Function::Sum, vec![], Ok(Value::Int(0))),
+            (Function::Sum, strings.clone(), Err(())),
+            (Function::Avg, values.clone(), Ok(Value::Float(2.0))),
+            (Function::Avg, mixed.clone(), Ok(Value::Float(1.75))),
+            (Function::Avg, vec![Value::Null], Ok(Value::Null)),
+            (Function::Min, values.clone(), Ok(Value::Int(1))),
+            (Function::Max, values.clone(), Ok(Value::Int(3))),
+            (Function::Max, mixed.clone(), Ok(Value::Float(2.5))),
+            (
+                Function::Min,
+                strings.clone(),
+                Ok(Value::String("a".to_string())),
+            ),
+            (Function::Min, vec![], Ok(Value::Null)),
+            (Function::First, values.clone(), Ok(Value::Int(3))),
+            (Function::Last, values.clone(), Ok(Value::Int(2))),
+            (Function::Last, vec![], Ok(Value::Null)),
+            (Function::Nth(2), values.clone(), Ok(Value::Int(1))),
+            (Function::Nth(4), values.clone(), Ok(Value::Null)),
+            (
+                Function::Max,
+                vec![Value::Int(1), Value::String("a".to_string())],
+                Err(()),
+            ),
+        ];
+        for (function, values, expected) in test_cases {
+            let name = format!("{}({:?})", function_name(&function), values);
+            let result = aggregate(&function, values).map_err(|_| ());
+            assert_eq!(expected, result, "test case '{}'", name);
+        }
+    }
+
+    #[test]
+    fn test_group() {
+        let query = Query {
+            streams: vec!["account".to_string()],
+            predicates: vec![],
+            group_by: vec![attribute("amount")],
+            order_by: vec![],
+            join: None,
+            time_range: None,
+            index: None,
+            event_types: None,
+            as_of: None,
+            projections: vec![],
+            limit: None,
+            offset: 0,
+            after: None,
+        };
+        let test_cases = vec![
+            (
+                "ints and floats of the same number",
+                vec![Value::Int(1), Value::Float(1.0), Value::Int(2)],
+                vec![2, 1],
+            ),
+            (
+                "strings are not numbers",
+                vec![Value::Int(1), Value::String("1".to_string())],
+                vec![1, 1],
+            ),
+            (
+                "nulls",
+                vec![Value::Null, Value::Int(1), Value::Null],
+                vec![2, 1],
+            ),
+        ];
+        for (name, amounts, expected) in test_cases {
+            let tuples = amounts
+                .into_iter()
+                .map(|amount| {
+                    vec![Event::new(
+                        "account".to_string(),
+                        "123".to_string(),
+                        "MoneyDeposited".to_string(),
+                        1,
+                        1700000000000,
+                        vec![Attribute {
+                            name: "amount".to_string(),
+                            value: amount,
+                        }],
+                    )]
+                })
+                .collect();
+            let groups = group(&query, tuples).unwrap();
+            let sizes = groups
+                .iter()
+                .map(|group| group.tuples.len())
+                .collect::<Vec<usize>>();
+            assert_eq!(expected, sizes, "test case '{}'", name);
+        }
+    }
+
+    #[test]
+    fn test_binary() {
+        let test_cases = vec![
+            (
+                Value::Int(7),
+                BinaryOperator::Divide,
+                Value::Int(2),
+                Ok(Value::Int(3)),
+            ),
+            (
+                Value::Int(7),
+                BinaryOperator::Divide,
+                Value::Float(2.0),
+                Ok(Value::Float(3.5)),
+            ),
+            (
+                Value::Int(7),
+                BinaryOperator::Modulus,
+                Value::Int(0),
+                Err(()),
+            ),
+            (
+                Value::Int(i64::MAX),
+                BinaryOperator::Add,
+                Value::Int(1),
+                Err(()),
+            ),
+            (
+                Value::Int(1),
+                BinaryOperator::Add,
+                Value::Null,
+                Ok(Value::Null),
+            ),
+            (
+                Value::Int(1),
+                BinaryOperator::Equal,
+                Value::Float(1.0),
+                Ok(Value::Bool(true)),
+            ),
+            (
+                Value::String("a".to_string()),
+                BinaryOperator::LessThan,
+                Value::String("b".to_string()),
+                Ok(Value::Bool(true)),
+            ),
+            (
+                Value::String("a".to_string()),
+                BinaryOperator::Add,
+                Value::Int(1),
+                Err(()),
+            ),
+            (
+                Value::Bool(true),
+                BinaryOperator::And,
+                Value::Bool(false),
+                Ok(Value::Bool(false)),
+            ),
+            (
+                Value::Null,
+                BinaryOperator::And,
+                Value::Bool(false),
+                Ok(Value::Bool(false)),
+            ),
+            (
+                Value::Bool(true),
+                BinaryOperator::And,
+                Value::Null,
+                Ok(Value::Null),
+            ),
+            (
+                Value::Null,
+                BinaryOperator::Or,
+                Value::Bool(true),
+                Ok(Value::Bool(true)),
+            ),
+            (
+                Value::Bool(false),
+                BinaryOperator::Or,
+                Value::Bool(false),
+                Ok(Value::Bool(false)),
+            ),
+            (
+                Value::Int(1),
+                BinaryOperator::Or,
+                Value::Bool(true),
+                Err(()),
+            ),
+            (
+                Value::Int(1),
+                BinaryOperator::NotEqual,
+                Value::String("1".to_string()),
+                Ok(Value::Bool(true)),
+            ),
+        ];
+        for (left, operator, right, expected) in test_cases {
+            let name = format!("{} {} {}", left, operator_symbol(&operator), right);
+            let result = binary(left, &operator, right).map_err(|_| ());
+            assert_eq!(expected, result, "test case '{}'", name);
+        }
+    }
+
+    #[test]
+    fn test_in_list() {
+        let list = vec![Value::Int(1), Value::Float(2.0)];
+        let test_cases = vec![
+            (Value::Int(2), list.clone(), false, Value::Bool(true)),
+            (Value::Int(3), list.clone(), false, Value::Bool(false)),
+            (Value::Int(3), list.clone(), true, Value::Bool(true)),
+            (Value::Null, list.clone(), false, Value::Null),
+            (
+                Value::Int(3),
+                vec![Value::Int(1), Value::Null],
+                true,
+                Value::Null,
+            ),
+            (
+                Value::Int(1),
+                vec![Value::Null, Value::Int(1)],
+                false,
+                Value::Bool(true),
+            ),
+        ];
+        for (value, list, negated, expected) in test_cases {
+            let name = format!("{} in {:?} negated {}", value, list, negated);
+            let result = in_list(value, list, negated).unwrap();
+            assert_eq!(expected, result, "test case '{}'", name);
+        }
+    }
+}
@@ -1,79 +1,346 @@
 mod ast;
+mod codec;
+mod cursor;
 mod db;
 mod event;
+mod executor;
 mod parser;
+mod persistent;
 mod planner;
+mod snapshot;
+mod storage;
+mod subscription;
 mod tokenizer;
+mod wal;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
+use persistent::{Groups, Member};
+use planner::Subscription;
+use storage::EventStore;
+use subscription::Subscriber;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+
+// requests larger than this are not read, the connection is closed instead
+const MAX_REQUEST_SIZE: u32 = 1024 * 1024;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let db = Arc::new(db::DB::new());
+    let data_dir = PathBuf::from(std::env::var("ADB_DATA_DIR").unwrap_or("data".to_string()));
+    let db = Arc::new(db::DB::open(&data_dir)?);
+    println!("Opened database in {:?}", data_dir);
+
+    // `restore <snapshot>` restores the snapshot into the database and exits, so that it
+    // is only done once
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    match args.as_slice() {
+        [] => {}
+        [command, snapshot_path] if command == "restore" => {
+            db.restore(Path::new(snapshot_path))?;
+            println!("Restored database from {:?}", snapshot_path);
+            return Ok(());
+        }
+        _ => return Err("usage: db [restore <snapshot>]".into()),
+    }
 
     let listener = TcpListener::bind("127.0.0.1:8080").await?;
     println!("Server listening on port 8080");
 
+    let groups = Arc::new(Groups::new(db.clone()));
     loop {
         let (socket, addr) = listener.accept().await?;
         println!("New connection from: {}", addr);
 
         let db = db.clone();
-        tokio::spawn(async move { handle_connection(socket, db).await });
+        let groups = groups.clone();
+        tokio::spawn(async move { handle_connection(socket, db, groups).await });
     }
 }
 
-async fn handle_connection(mut socket: TcpStream, db: Arc<db::DB>) {
-    loop {
-        let mut buffer = [0; 1024];
-        match socket.read(&mut buffer).await {
-            Ok(n) if n == 0 => {
-                println!("Connection closed by client");
+async fn handle_connection<S: EventStore + 'static>(
+    socket: TcpStream,
+    db: Arc<db::DB<S>>,
+    groups: Arc<Groups<S>>,
+) {
+    let (reader, mut writer) = socket.into_split();
+    let mut requests = read_requests(reader);
+    while let Some(msg) = requests.recv().await {
+        println!("Received: {}", msg);
+        let mut plan = match plan(&msg, db.as_ref()) {
+            Ok(plan) => plan,
+            Err(e) => {
+                if let Err(e) = write_response(&mut writer, &e).await {
+                    eprintln!("failed to write message: {}", e);
+                    return;
+                }
+                continue;
+            }
+        };
+        let return_msg = match plan.operations.as_slice() {
+            [planner::Operation::Subscribe { .. }] => {
+                if let Some(planner::Operation::Subscribe { subscription }) = plan.operations.pop()
+                {
+                    subscribe(&mut writer, &mut requests, subscription, db).await;
+                }
                 return;
             }
-            Ok(n) => {
-                let msg = String::from_utf8_lossy(&buffer[..n]);
-                println!("Received: {}", msg);
-                let return_msg = exec(&msg, db.clone()).await;
-                match return_msg {
-                    Err(e) => {
-                        if let Err(e) = socket.write(&e.to_string().into_bytes()).await {
-                            eprintln!("failed to write message: {}", e);
-                            return;
-                        }
-                    }
-                    Ok(m) => {
-                        let _ = socket.write(&m.into_bytes()).await;
-                        continue;
-                    }
+            [planner::Operation::Join {
+                subscription,
+                limit,
+            }] => match groups.get(subscription).await {
+                Ok(group) => {
+                    join(&mut writer, &mut requests, group.join(), *limit, db).await;
+                    return;
+                }
+                Err(e) => format!("failed to join: {}", e),
+            },
+            [planner::Operation::ReplayParked { subscription }] => {
+                match groups.get(subscription).await {
+                    Ok(group) => match group.replay_parked().await {
+                        Ok(n) => format!("replayed {} parked events", n),
+                        Err(e) => format!("failed to replay parked events: {}", e),
+                    },
+                    Err(e) => format!("failed to replay parked events: {}", e),
+                }
+            }
+            operations if operations.iter().any(is_run_by_server) => {
+                "subscribe, join and replay parked must be the only command of a transaction"
+                    .to_string()
+            }
+            _ => match exec_plan(plan, db.clone()).await {
+                Ok(m) => m,
+                Err(e) => e,
+            },
+        };
+        if let Err(e) = write_response(&mut writer, &return_msg).await {
+            eprintln!("failed to write message: {}", e);
+            return;
+        }
+    }
+    println!("Connection closed by client");
+}
+
+// operations that are run by the server rather than by the DB, as they use the connection
+// or the members of persistent subscriptions
+fn is_run_by_server(operation: &planner::Operation) -> bool {
+    matches!(
+        operation,
+        planner::Operation::Subscribe { .. }
+            | planner::Operation::Join { .. }
+            | planner::Operation::ReplayParked { .. }
+    )
+}
+
+// Requests, like responses, are prefixed with their length as a big endian u32. They are
+// read by a task of their own and sent over the channel, so waiting for the next request
+// can be raced against sending events without losing part of a request. The channel is
+// closed when the connection is
+fn read_requests(mut reader: OwnedReadHalf) -> mpsc::Receiver<String> {
+    let (sender, receiver) = mpsc::channel(1);
+    tokio::spawn(async move {
+        loop {
+            let msg = match read_request(&mut reader).await {
+                Ok(msg) => msg,
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return,
+                Err(e) => {
+                    eprintln!("failed to read from connection: {}", e);
+                    return;
+                }
+            };
+            if sender.send(msg).await.is_err() {
+                return;
+            }
+        }
+    });
+    receiver
+}
+
+async fn read_request(reader: &mut OwnedReadHalf) -> std::io::Result<String> {
+    let len = reader.read_u32().await?;
+    if len > MAX_REQUEST_SIZE {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!("request of {} bytes is too large", len),
+        ));
+    }
+    let mut buffer = vec![0; len as usize];
+    reader.read_exact(&mut buffer).await?;
+    Ok(String::from_utf8_lossy(&buffer).to_string())
+}
+
+// responses are prefixed with their length as a big endian u32, since the rows of a query
+// do not fit in a single read
+async fn write_response(writer: &mut OwnedWriteHalf, msg: &str) -> std::io::Result<()> {
+    writer.write_u32(msg.len() as u32).await?;
+    writer.write_all(msg.as_bytes()).await
+}
+
+// The connection is used for the subscription until the client sends anything or closes
+// it. A response is written when the subscription starts and then one per batch of events,
+// with a row per event as for read
+async fn subscribe<S: EventStore + 'static>(
+    writer: &mut OwnedWriteHalf,
+    requests: &mut mpsc::Receiver<String>,
+    subscription: Subscription,
+    db: Arc<db::DB<S>>,
+) {
+    let msg = format!("subscribed from position {}", subscription.from_position);
+    if let Err(e) = write_response(writer, &msg).await {
+        eprintln!("failed to write message: {}", e);
+        return;
+    }
+
+    let mut subscriber = Subscriber::new(db, subscription);
+    loop {
+        tokio::select! {
+            events = subscriber.next() => {
+                let (msg, has_failed) = match events {
+                    Ok(events) => (executor::event_rows(events).to_string(), false),
+                    Err(e) => (format!("subscription failed: {}", e), true),
+                };
+                if let Err(e) = write_response(writer, &msg).await {
+                    eprintln!("failed to write events: {}", e);
+                    return;
                 }
+                if has_failed {
+                    return;
+                }
+            }
+            _ = requests.recv() => {
+                println!("Subscription ended by client");
+                return;
             }
+        }
+    }
+}
+
+// The connection is used by the member until it is closed. A response is written when the
+// member has joined and then one per event, with a row as for read. Acks and nacks only
+// get a response if they fail
+async fn join<S: EventStore + 'static>(
+    writer: &mut OwnedWriteHalf,
+    requests: &mut mpsc::Receiver<String>,
+    member: Member<S>,
+    limit: usize,
+    db: Arc<db::DB<S>>,
+) {
+    let msg = format!("joined {}", member.group().name());
+    if let Err(e) = write_response(writer, &msg).await {
+        eprintln!("failed to write message: {}", e);
+        return;
+    }
+
+    loop {
+        let in_flight = match member.in_flight() {
+            Ok(n) => n,
             Err(e) => {
-                eprintln!("Failed to read from connection: {}", e);
+                eprintln!("failed to count events in flight: {}", e);
                 return;
             }
+        };
+        tokio::select! {
+            event = member.next(), if in_flight < limit => {
+                let (msg, has_failed) = match event {
+                    Ok(event) => (executor::event_rows(vec![event]).to_string(), false),
+                    Err(e) => (format!("subscription failed: {}", e), true),
+                };
+                if let Err(e) = write_response(writer, &msg).await {
+                    eprintln!("failed to write event: {}", e);
+                    return;
+                }
+                if has_failed {
+                    return;
+                }
+            }
+            request = requests.recv() => {
+                let Some(msg) = request else {
+                    println!("Member left {}", member.group().name());
+                    return;
+                };
+                if let Err(e) = settle(&msg, &member, db.as_ref()).await {
+                    if let Err(e) = write_response(writer, &e).await {
+                        eprintln!("failed to write message: {}", e);
+                        return;
+                    }
+                }
+            }
         }
     }
 }
 
-async fn exec(msg: &str, db: Arc<db::DB>) -> Result<String, String> {
-    let trx = parser::parse(msg).map_err(|e| format!("failed to parse: {}", e))?;
-    let plan = planner::plan(&trx).map_err(|e| format!("failed to plan: {}", e))?;
+// acks or nacks the events of the member. A transaction can only ack and nack events, they
+// are settled in order
+async fn settle<S: EventStore + 'static>(
+    msg: &str,
+    member: &Member<S>,
+    db: &db::DB<S>,
+) -> Result<(), String> {
+    let plan = plan(msg, db)?;
+    let is_settle = |op: &planner::Operation| {
+        matches!(
+            op,
+            planner::Operation::Ack { .. } | planner::Operation::Nack { .. }
+        )
+    };
+    if !plan.operations.iter().all(is_settle) {
+        return Err("members can only ack or nack events".to_string());
+    }
+    for op in plan.operations {
+        match op {
+            planner::Operation::Ack { positions } => member
+                .ack(&positions)
+                .await
+                .map_err(|e| format!("failed to ack: {}", e))?,
+            planner::Operation::Nack { positions } => member
+                .nack(&positions)
+                .await
+                .map_err(|e| format!("failed to nack: {}", e))?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
 
-    db.exec(&plan)
-        .map_err(|e| format!("failed to execute plan: {}", e))?;
+// plans and executes the message as the server does, for anything but subscriptions
+#[cfg(test)]
+async fn exec<S: EventStore + 'static>(msg: &str, db: Arc<db::DB<S>>) -> Result<String, String> {
+    let plan = plan(msg, db.as_ref())?;
+    exec_plan(plan, db).await
+}
+
+fn plan<S: EventStore>(msg: &str, db: &db::DB<S>) -> Result<planner::ExecutionPlan, String> {
+    let trx = parser::parse(msg).map_err(|e| format!("failed to parse: {}", e))?;
+    planner::plan(&trx, db).map_err(|e| format!("failed to plan: {}", e))
+}
 
-    dbg!(&trx, &plan);
+async fn exec_plan<S: EventStore + 'static>(
+    plan: planner::ExecutionPlan,
+    db: Arc<db::DB<S>>,
+) -> Result<String, String> {
+    let rows = db::blocking(&db, move |db| db.exec(&plan))
+        .await
+        .map_err(|e| match e.version_conflict() {
+            // conflicts are expected when clients race each other, so they are reported
+            // as such rather than as a failing plan
+            Some(conflict) => format!("failed to add event: {}", conflict),
+            None => format!("failed to execute plan: {}", e),
+        })?;
 
-    return Ok("all ok".to_string());
+    match rows {
+        Some(rows) => Ok(rows.to_string()),
+        None => Ok("all ok".to_string()),
+    }
 }
 
 #[cfg(test)]
 mod e2e_test {
     use super::*;
     use crate::db::DB;
+    use crate::event::Value;
     use std::sync::Arc;
 
     #[tokio::test]
@@ -102,6 +369,1328 @@ mod e2e_test {
             Err(e) => panic!("failed to create event: {}", e),
         }
     }
+
+    #[tokio::test]
+    async fn test_add_event() {
+        let db = Arc::new(DB::new());
+
+        let cmd = "create stream account;";
+        match exec(&cmd, db.clone()).await {
+            Ok(_) => eprintln!("created stream succeefully"),
+            Err(e) => panic!("failed to create stream: {}", e),
+        }
+
+        let cmd = "create event AccountCreated(
+                    owner string,
+                    amount int 
+                ) on account;";
+        match exec(&cmd, db.clone()).await {
+            Ok(_) => eprintln!("created event succeefully"),
+            Err(e) => panic!("failed to create event: {}", e),
+        }
+
+        let cmd = r#"add AccountCreated(owner="axel", amount=100) to account(id="123");"#;
+        for _ in 0..2 {
+            match exec(&cmd, db.clone()).await {
+                Ok(_) => eprintln!("added event succeefully"),
+                Err(e) => panic!("failed to add event: {}", e),
+            }
+        }
+
+        let events = match db.get_events("account".to_string(), "123".to_string()) {
+            Ok(Some(events)) => events,
+            Ok(None) => panic!("expected events for account:123, got none"),
+            Err(e) => panic!("failed to get events: {}", e),
+        };
+
+        assert_eq!(2, events.len());
+        for (i, event) in events.iter().enumerate() {
+            assert_eq!("AccountCreated", event.event);
+            assert_eq!(i as u64 + 1, event.version);
+            assert_eq!(
+                vec![
+                    crate::event::Attribute {
+                        name: "owner".to_string(),
+                        value: Value::String("axel".to_string()),
+                    },
+                    crate::event::Attribute {
+                        name: "amount".to_string(),
+                        value: Value::Int(100),
+                    },
+                ],
+                event.attributes
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_event_validated_against_schema() {
+        let db = Arc::new(DB::new());
+        for cmd in [
+            "create stream account;",
+            "create event MoneyDeposited(
+                amount float,
+                currency string,
+                note string optional
+            ) on account;",
+        ] {
+            if let Err(e) = exec(cmd, db.clone()).await {
+                panic!("failed to exec '{}': {}", cmd, e)
+            }
+        }
+
+        let test_cases = vec![
+            (
+                "unknown event",
+                r#"add MoneyWithdrawn(amount=1.0, currency="SEK") to account(id="123");"#,
+                Some("unknown event 'MoneyWithdrawn' on stream 'account'"),
+            ),
+            (
+                "unknown stream",
+                r#"add MoneyDeposited(amount=1.0, currency="SEK") to user(id="123");"#,
+                Some("unknown event 'MoneyDeposited' on stream 'user'"),
+            ),
+            (
+                "unknown attribute",
+                r#"add MoneyDeposited(amount=1.0, currency="SEK", fee=1) to account(id="123");"#,
+                Some("unknown attribute 'fee' on event 'MoneyDeposited'"),
+            ),
+            (
+                "missing required attribute",
+                r#"add MoneyDeposited(amount=1.0) to account(id="123");"#,
+                Some("missing required attribute 'currency' on event 'MoneyDeposited'"),
+            ),
+            (
+                "type mismatch",
+                r#"add MoneyDeposited(amount="1.0", currency="SEK") to account(id="123");"#,
+                Some("attribute 'amount' is of type float but got string"),
+            ),
+            (
+                "duplicate attribute",
+                r#"add MoneyDeposited(amount=1.0, amount=2.0, currency="SEK") to account(id="123");"#,
+                Some("attribute 'amount' is given more than once"),
+            ),
+            (
+                "optional attribute left out and int for float",
+                r#"add MoneyDeposited(amount=1, currency="SEK") to account(id="123");"#,
+                None,
+            ),
+            (
+                "optional attribute given",
+                r#"add MoneyDeposited(amount=1.5, currency="SEK", note="salary") to account(id="123");"#,
+                None,
+            ),
+        ];
+        for (name, cmd, expected_error) in test_cases {
+            match (exec(cmd, db.clone()).await, expected_error) {
+                (Ok(_), None) => {}
+                (Err(e), Some(expected)) => assert!(
+                    e.contains(expected),
+                    "test case '{}' expected error '{}' but got '{}'",
+                    name,
+                    expected,
+                    e
+                ),
+                (result, _) => panic!("test case '{}' got unexpected result {:?}", name, result),
+            }
+        }
+
+        let events = db
+            .get_events("account".to_string(), "123".to_string())
+            .unwrap()
+            .unwrap();
+        assert_eq!(2, events.len());
+
+        let cmd = "create event AccountCreated(owner text) on account;";
+        match exec(cmd, db.clone()).await {
+            Ok(_) => panic!("created attribute with unsupported type"),
+            Err(e) => assert!(e.contains("attribute 'owner' has unsupported type 'text'")),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_event_typed_values() {
+        let db = Arc::new(DB::new());
+        for cmd in [
+            "create stream account;",
+            "create event AccountUpdated(
+                balance float,
+                transactions int,
+                active bool,
+                note string optional
+            ) on account;",
+            r#"add AccountUpdated(balance=-100, transactions=3, active=true, note=null) to account(id="123");"#,
+        ] {
+            if let Err(e) = exec(cmd, db.clone()).await {
+                panic!("failed to exec '{}': {}", cmd, e)
+            }
+        }
+
+        let events = db
+            .get_events("account".to_string(), "123".to_string())
+            .unwrap()
+            .unwrap();
+        let values = events[0]
+            .attributes
+            .iter()
+            .map(|a| a.value.clone())
+            .collect::<Vec<Value>>();
+        assert_eq!(
+            vec![
+                Value::Float(-100.0),
+                Value::Int(3),
+                Value::Bool(true),
+                Value::Null
+            ],
+            values
+        );
+
+        let cmd = r#"add AccountUpdated(balance=null, transactions=3, active=true) to account(id="123");"#;
+        match exec(cmd, db.clone()).await {
+            Ok(_) => panic!("added null for required attribute"),
+            Err(e) => assert!(e.contains("attribute 'balance' is required and can not be null")),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_find() {
+        let db = Arc::new(DB::new());
+        for cmd in [
+            "create stream account;",
+            "create event AccountCreated(owner string) on account;",
+            "create event MoneyDeposited(amount int) on account;",
+            r#"add AccountCreated(owner="axel") to account(id="123");"#,
+            r#"add MoneyDeposited(amount=100) to account(id="123");"#,
+            r#"add MoneyDeposited(amount=50) to account(id="123");"#,
+            r#"add AccountCreated(owner="bob") to account(id="456");"#,
+            r#"add MoneyDeposited(amount=10) to account(id="456");"#,
+        ] {
+            if let Err(e) = exec(cmd, db.clone()).await {
+                panic!("failed to exec '{}': {}", cmd, e)
+            }
+        }
+
+        let test_cases = vec![
+            ("rows", "find account.owner;", "account.owner\naxel\nbob"),
+            (
+                "where and limit",
+                "find account.amount where account.amount > 20 limit 3;",
+                "account.amount\n100\n50",
+            ),
+            (
+                "aggregate",
+                "find sum(account.amount) - 10, sum(account.amount);",
+                "sum(account.amount) - 10\tsum(account.amount)\n150\t160",
+            ),
+            (
+                "boolean where",
+                r#"find account.amount where (account.amount > 60 or account.amount = 10) and not key != "123";"#,
+                "account.amount\n100",
+            ),
+            (
+                "in and not in",
+                r#"find account.owner where account.owner in ("bob", "carl") or key not in ("123", "456");"#,
+                "account.owner\nbob",
+            ),
+            (
+                "group by key",
+                "find key, sum(account.amount) group by key;",
+                "key\tsum(account.amount)\n123\t150\n456\t10",
+            ),
+            (
+                "group by with where",
+                "find key, count(account.amount) where account.amount > 20 group by key;",
+                "key\tcount(account.amount)\n123\t2",
+            ),
+            (
+                "arithmetic",
+                "find (sum(account.amount) - 10) * 2 - -sum(account.amount) % 7;",
+                "(sum(account.amount) - 10) * 2 - -sum(account.amount) % 7\n306",
+            ),
+            (
+                "first, last and nth",
+                "find key, first(account.amount), last(account.amount), nth(account.amount, 2) group by key;",
+                "key\tfirst(account.amount)\tlast(account.amount)\tnth(account.amount, 2)\n123\t100\t50\t50\n456\t10\t10\tnull",
+            ),
+            (
+                "all aggregates",
+                "find count(account.amount), min(account.amount), max(account.amount), avg(account.amount), count(account.owner);",
+                "count(account.amount)\tmin(account.amount)\tmax(account.amount)\tavg(account.amount)\tcount(account.owner)\n3\t10\t100\t53.333333333333336\t2",
+            ),
+        ];
+        for (name, cmd, expected) in test_cases {
+            match exec(cmd, db.clone()).await {
+                Ok(result) => assert_eq!(expected, result, "test case '{}'", name),
+                Err(e) => panic!("test case '{}' failed: {}", name, e),
+            }
+        }
+
+        let test_cases = vec![
+            (
+                "unknown stream",
+                "find loan.amount;",
+                "unknown stream 'loan'",
+            ),
+            (
+                "attribute next to aggregate",
+                "find account.owner, sum(account.amount);",
+                "attribute 'account.owner' must be used in an aggregate",
+            ),
+            (
+                "nth out of range",
+                "find nth(account.amount, 0);",
+                "the position of nth starts at 1, got 0",
+            ),
+            (
+                "attribute not in group by",
+                "find account.owner, sum(account.amount) group by key;",
+                "'account.owner' must be used in an aggregate or be in group by",
+            ),
+        ];
+        for (name, cmd, expected) in test_cases {
+            match exec(cmd, db.clone()).await {
+                Ok(result) => panic!("test case '{}' did not fail: {}", name, result),
+                Err(e) => assert!(e.contains(expected), "test case '{}' got '{}'", name, e),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_find_event_attributes() {
+        let db = Arc::new(DB::new());
+        for cmd in [
+            "create stream account;",
+            "create event MoneyDeposited(amount int) on account;",
+            "create event MoneyWithdrawn(amount int) on account;",
+            r#"add MoneyDeposited(amount=100) to account(id="123");"#,
+            r#"add MoneyWithdrawn(amount=30) to account(id="123");"#,
+            r#"add MoneyDeposited(amount=50) to account(id="123");"#,
+            r#"add MoneyDeposited(amount=10) to account(id="456");"#,
+        ] {
+            if let Err(e) = exec(cmd, db.clone()).await {
+                panic!("failed to exec '{}': {}", cmd, e)
+            }
+        }
+
+        let test_cases = vec![
+            (
+                "balance",
+                r#"find sum(account.MoneyDeposited.amount) - sum(account.MoneyWithdrawn.amount) where key = "123";"#,
+                "sum(account.MoneyDeposited.amount) - sum(account.MoneyWithdrawn.amount)\n120",
+            ),
+            (
+                "attribute of any event",
+                r#"find sum(account.amount) where key = "123";"#,
+                "sum(account.amount)\n180",
+            ),
+            (
+                "rows of one event type",
+                "find key, account.MoneyWithdrawn.amount;",
+                "key\taccount.MoneyWithdrawn.amount\n123\t30",
+            ),
+        ];
+        for (name, cmd, expected) in test_cases {
+            match exec(cmd, db.clone()).await {
+                Ok(result) => assert_eq!(expected, result, "test case '{}'", name),
+                Err(e) => panic!("test case '{}' failed: {}", name, e),
+            }
+        }
+
+        let test_cases = vec![
+            (
+                "unknown event",
+                "find account.MoneyLent.amount;",
+                "unknown event 'MoneyLent' on stream 'account'",
+            ),
+            (
+                "unknown attribute on event",
+                "find account.MoneyDeposited.fee;",
+                "unknown attribute 'fee' on event 'MoneyDeposited'",
+            ),
+            (
+                "unknown attribute on stream",
+                "find key where account.fee > 1;",
+                "unknown attribute 'fee' on stream 'account'",
+            ),
+        ];
+        for (name, cmd, expected) in test_cases {
+            match exec(cmd, db.clone()).await {
+                Ok(result) => panic!("test case '{}' did not fail: {}", name, result),
+                Err(e) => assert!(e.contains(expected), "test case '{}' got '{}'", name, e),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_find_pseudo_attributes() {
+        let db = Arc::new(DB::new());
+        for cmd in [
+            "create stream account;",
+            "create event AccountCreated(owner string) on account;",
+            "create event MoneyDeposited(amount int) on account;",
+            "create stream user;",
+            "create event UserCreated(account string) on user;",
+            r#"add AccountCreated(owner="axel") to account(id="123");"#,
+            r#"add MoneyDeposited(amount=100) to account(id="123");"#,
+            r#"add AccountCreated(owner="bob") to account(id="456");"#,
+            r#"add MoneyDeposited(amount=50) to account(id="456");"#,
+            r#"add MoneyDeposited(amount=70) to account(id="456");"#,
+            r#"add UserCreated(account="123") to user(id="u1");"#,
+        ] {
+            if let Err(e) = exec(cmd, db.clone()).await {
+                panic!("failed to exec '{}': {}", cmd, e)
+            }
+        }
+
+        let test_cases = vec![
+            (
+                "projected",
+                r#"find key, version, event where key = "123";"#,
+                "key\tversion\tevent\n123\t1\tAccountCreated\n123\t2\tMoneyDeposited",
+            ),
+            (
+                "in predicates",
+                r#"find key, account.amount where event = "MoneyDeposited" and version > 2;"#,
+                "key\taccount.amount\n456\t70",
+            ),
+            (
+                "after a timestamp",
+                "find key, version where timestamp > '2020-01-01' and version between 2 and 3;",
+                "key\tversion\n123\t2\n456\t2\n456\t3",
+            ),
+            (
+                "before a timestamp",
+                "find key where timestamp < '2020-01-01 12:00:00';",
+                "key",
+            ),
+            (
+                "between timestamps",
+                "find count(key) where timestamp between '2020-01-01' and '2100-01-01';",
+                "count(key)\n6",
+            ),
+            (
+                "grouped by event",
+                "find event, count(version) group by event order by event;",
+                "event\tcount(version)\nAccountCreated\t2\nMoneyDeposited\t3\nUserCreated\t1",
+            ),
+        ];
+        for (name, cmd, expected) in test_cases {
+            match exec(cmd, db.clone()).await {
+                Ok(result) => assert_eq!(expected, result, "test case '{}'", name),
+                Err(e) => panic!("test case '{}' failed: {}", name, e),
+            }
+        }
+
+        let test_cases = vec![
+            (
+                "invalid timestamp",
+                "find key where timestamp > '2026-13-01';",
+                "invalid timestamp '2026-13-01'",
+            ),
+            (
+                "timestamp in a join",
+                "find account.owner where account.owner = user.account and timestamp > 0;",
+                "timestamp can not be used in a join",
+            ),
+        ];
+        for (name, cmd, expected) in test_cases {
+            match exec(cmd, db.clone()).await {
+                Ok(result) => panic!("test case '{}' did not fail: {}", name, result),
+                Err(e) => assert!(e.contains(expected), "test case '{}' got '{}'", name, e),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_find_as_of() {
+        let db = Arc::new(DB::new());
+        for cmd in [
+            "create stream account;",
+            "create event MoneyDeposited(amount int) on account;",
+            r#"add MoneyDeposited(amount=100) to account(id="123");"#,
+            r#"add MoneyDeposited(amount=50) to account(id="123");"#,
+            r#"add MoneyDeposited(amount=10) to account(id="456");"#,
+        ] {
+            if let Err(e) = exec(cmd, db.clone()).await {
+                panic!("failed to exec '{}': {}", cmd, e)
+            }
+        }
+
+        // the events added after the point in time are left out
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        let month_end = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis();
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        for cmd in [
+            r#"add MoneyDeposited(amount=1000) to account(id="123");"#,
+            r#"add MoneyDeposited(amount=1000) to account(id="789");"#,
+        ] {
+            if let Err(e) = exec(cmd, db.clone()).await {
+                panic!("failed to exec '{}': {}", cmd, e)
+            }
+        }
+
+        let test_cases = vec![
+            (
+                "as of now",
+                "find key, sum(account.amount) group by key;".to_string(),
+                "key\tsum(account.amount)\n123\t1150\n456\t10\n789\t1000",
+            ),
+            (
+                "as of a timestamp",
+                format!(
+                    "find key, sum(account.amount) as of timestamp {} group by key;",
+                    month_end
+                ),
+                "key\tsum(account.amount)\n123\t150\n456\t10",
+            ),
+            (
+                "as of a timestamp with a time range",
+                format!(
+                    "find key, account.amount where timestamp > 0 as of timestamp {};",
+                    month_end
+                ),
+                "key\taccount.amount\n123\t100\n123\t50\n456\t10",
+            ),
+            (
+                "as of a version",
+                "find key, sum(account.amount) as of version 1 group by key;".to_string(),
+                "key\tsum(account.amount)\n123\t100\n456\t10\n789\t1000",
+            ),
+            (
+                "as of a timestamp literal",
+                "find key as of timestamp '2020-01-01';".to_string(),
+                "key",
+            ),
+        ];
+        for (name, cmd, expected) in test_cases {
+            match exec(&cmd, db.clone()).await {
+                Ok(result) => assert_eq!(expected, result, "test case '{}'", name),
+                Err(e) => panic!("test case '{}' failed: {}", name, e),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_find_index() {
+        let db = Arc::new(DB::new());
+        for cmd in [
+            "create stream account;",
+            "create event AccountCreated(owner-name string, balance int) on account;",
+            "create event AccountClosed(owner-name string) on account;",
+            r#"add AccountCreated(owner-name="axel", balance=100) to account(id="123");"#,
+            r#"add AccountCreated(owner-name="bob", balance=10) to account(id="456");"#,
+            "create index on account.AccountCreated.owner-name;",
+            "create index on account.AccountCreated.balance;",
+            r#"add AccountCreated(owner-name="axel", balance=50) to account(id="789");"#,
+            r#"add AccountClosed(owner-name="axel") to account(id="123");"#,
+        ] {
+            if let Err(e) = exec(cmd, db.clone()).await {
+                panic!("failed to exec '{}': {}", cmd, e)
+            }
+        }
+
+        let test_cases = vec![
+            (
+                "equality on events added before and after the index was created",
+                r#"find key, account.balance where account.AccountCreated.owner-name = "axel";"#,
+                "key\taccount.balance\n123\t100\n789\t50",
+            ),
+            (
+                "literal on the left",
+                r#"find key where "bob" = account.AccountCreated.owner-name;"#,
+                "key\n456",
+            ),
+            (
+                "range",
+                "find key where account.AccountCreated.balance >= 50 and account.AccountCreated.balance < 100;",
+                "key\n789",
+            ),
+            (
+                "between",
+                "find key where account.AccountCreated.balance between 10 and 50;",
+                "key\n456\n789",
+            ),
+            (
+                "index and other predicates",
+                r#"find key where account.AccountCreated.owner-name = "axel" and account.balance > 60;"#,
+                "key\n123",
+            ),
+            (
+                "no match",
+                r#"find key where account.AccountCreated.owner-name = "eve";"#,
+                "key",
+            ),
+            (
+                "as of a version",
+                r#"find key, version where account.AccountCreated.owner-name = "axel" as of version 0;"#,
+                "key\tversion",
+            ),
+        ];
+        for (name, cmd, expected) in test_cases {
+            match exec(cmd, db.clone()).await {
+                Ok(result) => assert_eq!(expected, result, "test case '{}'", name),
+                Err(e) => panic!("test case '{}' failed: {}", name, e),
+            }
+        }
+
+        let test_cases = vec![
+            (
+                "index that exists",
+                "create index on account.AccountCreated.owner-name;",
+                "index on account.AccountCreated.owner-name already exists",
+            ),
+            (
+                "unknown attribute",
+                "create index on account.AccountCreated.note;",
+                "unknown attribute 'note' on event 'AccountCreated'",
+            ),
+            (
+                "unknown event",
+                "create index on account.MoneyDeposited.amount;",
+                "unknown event 'MoneyDeposited' on stream 'account'",
+            ),
+        ];
+        for (name, cmd, expected) in test_cases {
+            match exec(cmd, db.clone()).await {
+                Ok(result) => panic!("test case '{}' did not fail: {}", name, result),
+                Err(e) => assert!(e.contains(expected), "test case '{}' got '{}'", name, e),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_find_event_types() {
+        let db = Arc::new(DB::new());
+        for cmd in [
+            "create stream account;",
+            "create event MoneyDeposited(amount int) on account;",
+            "create event MoneyWithdrawn(amount int) on account;",
+            "create stream user;",
+            "create event UserCreated(name string) on user;",
+            r#"add MoneyDeposited(amount=100) to account(id="123");"#,
+            r#"add MoneyWithdrawn(amount=30) to account(id="123");"#,
+            r#"add MoneyDeposited(amount=50) to account(id="456");"#,
+            r#"add MoneyWithdrawn(amount=20) to account(id="123");"#,
+            r#"add UserCreated(name="axel") to user(id="1");"#,
+        ] {
+            if let Err(e) = exec(cmd, db.clone()).await {
+                panic!("failed to exec '{}': {}", cmd, e)
+            }
+        }
+
+        let test_cases = vec![
+            (
+                "event",
+                r#"find key, version, account.amount where event = "MoneyWithdrawn";"#,
+                "key\tversion\taccount.amount\n123\t2\t30\n123\t3\t20",
+            ),
+            (
+                "event in, all streams",
+                r#"find key, event where event in ("UserCreated", "MoneyDeposited");"#,
+                "key\tevent\n123\tMoneyDeposited\n456\tMoneyDeposited\n1\tUserCreated",
+            ),
+            (
+                "attribute of an event type",
+                "find sum(account.amount) where account.MoneyWithdrawn.amount > 0;",
+                "sum(account.amount)\n50",
+            ),
+            (
+                "no event type matches",
+                r#"find key where event = "MoneyDeposited" and account.MoneyWithdrawn.amount > 0;"#,
+                "key",
+            ),
+            (
+                "as of a version",
+                r#"find key, version where event = "MoneyWithdrawn" as of version 2;"#,
+                "key\tversion\n123\t2",
+            ),
+        ];
+        for (name, cmd, expected) in test_cases {
+            match exec(cmd, db.clone()).await {
+                Ok(result) => assert_eq!(expected, result, "test case '{}'", name),
+                Err(e) => panic!("test case '{}' failed: {}", name, e),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_find_join() {
+        let db = Arc::new(DB::new());
+        for cmd in [
+            "create stream user;",
+            "create event UserCreated(id string, name string) on user;",
+            "create stream account;",
+            "create event AccountOpened(user_id string, balance int) on account;",
+            "create stream loan;",
+            "create event LoanSigned(user_id string, amount int) on loan;",
+            r#"add UserCreated(id="u1", name="axel") to user(id="u1");"#,
+            r#"add UserCreated(id="u2", name="bob") to user(id="u2");"#,
+            r#"add UserCreated(id="u3", name="eve") to user(id="u3");"#,
+            r#"add AccountOpened(user_id="u1", balance=100) to account(id="a1");"#,
+            r#"add AccountOpened(user_id="u1", balance=50) to account(id="a2");"#,
+            r#"add AccountOpened(user_id="u2", balance=10) to account(id="a3");"#,
+            r#"add AccountOpened(user_id="u4", balance=70) to account(id="a4");"#,
+        ] {
+            if let Err(e) = exec(cmd, db.clone()).await {
+                panic!("failed to exec '{}': {}", cmd, e)
+            }
+        }
+
+        let test_cases = vec![
+            (
+                "join",
+                "find user.name, account.balance where account.user_id = user.id;",
+                "user.name\taccount.balance\naxel\t100\naxel\t50\nbob\t10",
+            ),
+            (
+                "join with predicates on each stream",
+                r#"find user.name, account.balance where user.id = account.user_id and account.balance > 20 and user.name != "bob";"#,
+                "user.name\taccount.balance\naxel\t100\naxel\t50",
+            ),
+            (
+                "join with a predicate on both streams",
+                r#"find user.name where user.id = account.user_id and (user.name = "bob" or account.balance = 50);"#,
+                "user.name\naxel\nbob",
+            ),
+            (
+                "grouped join",
+                "find user.name, sum(account.balance) where account.user_id = user.id group by user.name order by user.name desc;",
+                "user.name\tsum(account.balance)\nbob\t10\naxel\t150",
+            ),
+        ];
+        for (name, cmd, expected) in test_cases {
+            match exec(cmd, db.clone()).await {
+                Ok(result) => assert_eq!(expected, result, "test case '{}'", name),
+                Err(e) => panic!("test case '{}' failed: {}", name, e),
+            }
+        }
+
+        let test_cases = vec![
+            (
+                "key in a join",
+                "find key where account.user_id = user.id;",
+                "key can not be used in a join",
+            ),
+            (
+                "more than two streams",
+                "find loan.amount where loan.user_id = user.id and account.user_id = user.id;",
+                "joins are only supported between two streams, got 3",
+            ),
+        ];
+        for (name, cmd, expected) in test_cases {
+            match exec(cmd, db.clone()).await {
+                Ok(result) => panic!("test case '{}' did not fail: {}", name, result),
+                Err(e) => assert!(e.contains(expected), "test case '{}' got '{}'", name, e),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_find_pattern() {
+        let db = Arc::new(DB::new());
+        for cmd in [
+            "create stream account;",
+            "create event AccountCreated(owner string, balance int) on account;",
+            "create event MoneyDeposited(amount int) on account;",
+            "create event MoneyWithdrawn(amount int) on account;",
+            "create stream loan;",
+            "create event LoanSigned(account string, amount int) on loan;",
+            r#"add AccountCreated(owner="axel", balance=100) to account(id="123");"#,
+            r#"add MoneyDeposited(amount=150) to account(id="123");"#,
+            r#"add MoneyWithdrawn(amount=30) to account(id="123");"#,
+            r#"add MoneyDeposited(amount=50) to account(id="123");"#,
+            r#"add AccountCreated(owner="bob", balance=0) to account(id="456");"#,
+            r#"add MoneyDeposited(amount=10) to account(id="456");"#,
+            r#"add MoneyDeposited(amount=10) to account(id="456");"#,
+            r#"add LoanSigned(account="456", amount=1000) to loan(id="l1");"#,
+        ] {
+            if let Err(e) = exec(cmd, db.clone()).await {
+                panic!("failed to exec '{}': {}", cmd, e)
+            }
+        }
+
+        let test_cases = vec![
+            (
+                "withdrawals within an hour of creation",
+                r#"[:find ?account ?amount
+                    :where
+                    [?c :type "AccountCreated"]
+                    [?c :key ?account]
+                    [?c :timestamp ?created]
+                    [?w :type "MoneyWithdrawn"]
+                    [?w :key ?account]
+                    [?w :amount ?amount]
+                    [?w :timestamp ?withdrawn]
+                    [(< (- ?withdrawn ?created) 3600000)]];"#,
+                "?account\t?amount\n123\t30",
+            ),
+            (
+                "deposits larger than the initial balance",
+                r#"[:find ?account ?amount
+                    :where
+                    [?c :type "AccountCreated"]
+                    [?c :key ?account]
+                    [?c :balance ?initial]
+                    [?d :type "MoneyDeposited"]
+                    [?d :key ?account]
+                    [?d :amount ?amount]
+                    [(> ?amount ?initial)]];"#,
+                "?account\t?amount\n123\t150\n456\t10",
+            ),
+            (
+                "owners with a loan across streams",
+                r#"[:find ?owner ?loan
+                    :where
+                    [?c :owner ?owner]
+                    [?c :key ?account]
+                    [?l :stream "loan"]
+                    [?l :account ?account]
+                    [?l :amount ?loan]];"#,
+                "?owner\t?loan\nbob\t1000",
+            ),
+        ];
+        for (name, cmd, expected) in test_cases {
+            match exec(cmd, db.clone()).await {
+                Ok(result) => assert_eq!(expected, result, "test case '{}'", name),
+                Err(e) => panic!("test case '{}' failed: {}", name, e),
+            }
+        }
+
+        let test_cases = vec![
+            (
+                "unbound variable in find",
+                "[:find ?owner :where [?c :balance ?balance]];",
+                "variable '?owner' of :find is not bound by a pattern",
+            ),
+            (
+                "unbound variable in predicate",
+                "[:find ?balance :where [?c :balance ?balance] [(> ?balance ?limit)]];",
+                "variable '?limit' of predicate '(> ?balance ?limit)' is not bound by a pattern",
+            ),
+            (
+                "unknown attribute",
+                "[:find ?fee :where [?c :fee ?fee]];",
+                "unknown attribute ':fee'",
+            ),
+            (
+                "unknown event",
+                r#"[:find ?a :where [?c :type "MoneyLent"] [?c :amount ?a]];"#,
+                "unknown event 'MoneyLent'",
+            ),
+        ];
+        for (name, cmd, expected) in test_cases {
+            match exec(cmd, db.clone()).await {
+                Ok(result) => panic!("test case '{}' did not fail: {}", name, result),
+                Err(e) => assert!(e.contains(expected), "test case '{}' got '{}'", name, e),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_find_pages() {
+        let db = Arc::new(DB::new());
+        for cmd in [
+            "create stream account;",
+            "create event MoneyDeposited(amount int) on account;",
+            r#"add MoneyDeposited(amount=30) to account(id="1");"#,
+            r#"add MoneyDeposited(amount=10) to account(id="2");"#,
+            r#"add MoneyDeposited(amount=20) to account(id="3");"#,
+            r#"add MoneyDeposited(amount=20) to account(id="4");"#,
+        ] {
+            if let Err(e) = exec(cmd, db.clone()).await {
+                panic!("failed to exec '{}': {}", cmd, e)
+            }
+        }
+
+        // splits the response into its rows and cursor
+        fn page(response: String) -> (Vec<String>, Option<String>) {
+            let mut rows = vec![];
+            let mut cursor = None;
+            for line in response.lines().skip(1) {
+                match line.strip_prefix("cursor ") {
+                    Some(c) => cursor = Some(c.to_string()),
+                    None => rows.push(line.to_string()),
+                }
+            }
+            (rows, cursor)
+        }
+
+        let test_cases = vec![
+            (
+                "order by",
+                "find key, account.amount order by account.amount desc;",
+                vec!["1\t30", "3\t20", "4\t20", "2\t10"],
+            ),
+            (
+                "order by, limit and offset",
+                "find key order by account.amount, key desc limit 2 offset 1;",
+                vec!["4", "3"],
+            ),
+            (
+                "order by aggregate",
+                "find key, sum(account.amount) group by key order by sum(account.amount) limit 1;",
+                vec!["2\t10"],
+            ),
+        ];
+        for (name, cmd, expected) in test_cases {
+            match exec(cmd, db.clone()).await {
+                Ok(result) => assert_eq!(expected, page(result).0, "test case '{}'", name),
+                Err(e) => panic!("test case '{}' failed: {}", name, e),
+            }
+        }
+
+        // events added between pages are only returned if they come after the cursor
+        let query = "find key, account.amount order by account.amount limit 2";
+        let (rows, cursor) = page(exec(&format!("{};", query), db.clone()).await.unwrap());
+        assert_eq!(vec!["2\t10", "3\t20"], rows);
+        for cmd in [
+            r#"add MoneyDeposited(amount=5) to account(id="5");"#,
+            r#"add MoneyDeposited(amount=25) to account(id="6");"#,
+        ] {
+            exec(cmd, db.clone()).await.unwrap();
+        }
+        let after = format!("{} after \"{}\";", query, cursor.unwrap());
+        let (rows, cursor) = page(exec(&after, db.clone()).await.unwrap());
+        assert_eq!(vec!["4\t20", "6\t25"], rows);
+        let after = format!("{} after \"{}\";", query, cursor.unwrap());
+        let (rows, cursor) = page(exec(&after, db.clone()).await.unwrap());
+        assert_eq!(vec!["1\t30"], rows);
+        assert_eq!(None, cursor);
+
+        let (_, cursor) = page(exec(&format!("{};", query), db.clone()).await.unwrap());
+        let cmd = format!(
+            "find key order by account.amount desc limit 2 after \"{}\";",
+            cursor.unwrap()
+        );
+        match exec(&cmd, db.clone()).await {
+            Ok(result) => panic!("cursor of another query was accepted: {}", result),
+            Err(e) => assert!(e.contains("cursor is from another query"), "got '{}'", e),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_event_expecting_version() {
+        let db = Arc::new(DB::new());
+        for cmd in [
+            "create stream account;",
+            "create event AccountCreated(owner string) on account;",
+            r#"add AccountCreated(owner="axel") to account(id="123") expect version 0;"#,
+            r#"add AccountCreated(owner="bob") to account(id="123") expect version 1;"#,
+        ] {
+            if let Err(e) = exec(cmd, db.clone()).await {
+                panic!("failed to exec '{}': {}", cmd, e)
+            }
+        }
+
+        let test_cases = vec![("stale version", 1), ("version ahead of stream key", 5)];
+        for (name, expected_version) in test_cases {
+            let cmd = format!(
+                r#"add AccountCreated(owner="carl") to account(id="123") expect version {};"#,
+                expected_version
+            );
+            match exec(&cmd, db.clone()).await {
+                Ok(_) => panic!("test case '{}' did not conflict", name),
+                Err(e) => assert!(
+                    e.contains(&format!(
+                        "expected version {} but stream key is at version 2",
+                        expected_version
+                    )),
+                    "test case '{}' got unexpected error: {}",
+                    name,
+                    e
+                ),
+            }
+        }
+
+        let events = db
+            .get_events("account".to_string(), "123".to_string())
+            .unwrap()
+            .unwrap();
+        assert_eq!(2, events.len());
+    }
+
+    #[tokio::test]
+    async fn test_restart() {
+        let path = std::env::temp_dir()
+            .join(format!("adb-e2e-test-{}", std::process::id()))
+            .join("restart");
+        let _ = std::fs::remove_dir_all(&path);
+
+        let db = Arc::new(DB::open(&path).unwrap());
+        for cmd in [
+            "create stream account;",
+            "create event AccountCreated(owner string) on account;",
+            "create index on account.AccountCreated.owner;",
+            r#"add AccountCreated(owner="axel") to account(id="123");"#,
+            r#"add AccountCreated(owner="bob") to account(id="123");"#,
+        ] {
+            if let Err(e) = exec(cmd, db.clone()).await {
+                panic!("failed to exec '{}': {}", cmd, e)
+            }
+        }
+        let schema = db.schema.read().unwrap().clone();
+        let events = db.get_events("account".to_string(), "123".to_string());
+        drop(db);
+
+        let db = Arc::new(DB::open(&path).unwrap());
+        assert_eq!(schema, *db.schema.read().unwrap());
+        assert_eq!(
+            events.unwrap(),
+            db.get_events("account".to_string(), "123".to_string())
+                .unwrap()
+        );
+
+        // new events continue from the replayed version
+        let cmd = r#"add AccountCreated(owner="carl") to account(id="123");"#;
+        if let Err(e) = exec(cmd, db.clone()).await {
+            panic!("failed to exec '{}': {}", cmd, e)
+        }
+        let events = db
+            .get_events("account".to_string(), "123".to_string())
+            .unwrap()
+            .unwrap();
+        assert_eq!(3, events.last().unwrap().version);
+
+        // the index is built again from the events
+        let cmd = r#"find key, version where account.AccountCreated.owner = "bob";"#;
+        match exec(cmd, db.clone()).await {
+            Ok(result) => assert_eq!("key\tversion\n123\t2", result),
+            Err(e) => panic!("failed to exec '{}': {}", cmd, e),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_and_restore() {
+        let dir = std::env::temp_dir()
+            .join(format!("adb-e2e-test-{}", std::process::id()))
+            .join("snapshot");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let db = Arc::new(DB::open(&dir.join("source")).unwrap());
+        for cmd in [
+            "create stream account;",
+            "create event AccountCreated(owner string) on account;",
+            r#"add AccountCreated(owner="axel") to account(id="123");"#,
+            r#"add AccountCreated(owner="carl") to account(id="456");"#,
+            r#"add AccountCreated(owner="bob") to account(id="123");"#,
+            r#"snapshot to "backups/db.snap";"#,
+            // not part of the snapshot
+            r#"add AccountCreated(owner="dave") to account(id="123");"#,
+        ] {
+            if let Err(e) = exec(cmd, db.clone()).await {
+                panic!("failed to exec '{}': {}", cmd, e)
+            }
+        }
+        let snapshot_path = dir.join("source/snapshots/backups/db.snap");
+
+        // snapshots are only written to new files in the snapshot directory
+        for cmd in [
+            r#"snapshot to "backups/db.snap";"#,
+            r#"snapshot to "../wal.log";"#,
+            r#"snapshot to "/tmp/db.snap";"#,
+        ] {
+            if exec(cmd, db.clone()).await.is_ok() {
+                panic!("expected '{}' to fail", cmd)
+            }
+        }
+
+        let restored = Arc::new(DB::open(&dir.join("restored")).unwrap());
+        restored.restore(&snapshot_path).unwrap();
+        let events = db
+            .get_events("account".to_string(), "123".to_string())
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            Some(events[..2].to_vec()),
+            restored
+                .get_events("account".to_string(), "123".to_string())
+                .unwrap()
+        );
+        assert_eq!(
+            db.get_events("account".to_string(), "456".to_string())
+                .unwrap(),
+            restored
+                .get_events("account".to_string(), "456".to_string())
+                .unwrap()
+        );
+
+        // the restored database is kept when it is opened again
+        drop(restored);
+        let restored = Arc::new(DB::open(&dir.join("restored")).unwrap());
+        assert_eq!(*db.schema.read().unwrap(), *restored.schema.read().unwrap());
+        assert_eq!(
+            Some(events[..2].to_vec()),
+            restored
+                .get_events("account".to_string(), "123".to_string())
+                .unwrap()
+        );
+        let cmd = r#"add AccountCreated(owner="erik") to account(id="123");"#;
+        if let Err(e) = exec(cmd, restored.clone()).await {
+            panic!("failed to exec '{}': {}", cmd, e)
+        }
+        let cmd = r#"find key, version where account.AccountCreated.owner = "erik";"#;
+        match exec(cmd, restored.clone()).await {
+            Ok(result) => assert_eq!("key\tversion\n123\t3", result),
+            Err(e) => panic!("failed to exec '{}': {}", cmd, e),
+        }
+
+        // a database that is not empty can not be restored into
+        assert!(restored.restore(&snapshot_path).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_all() {
+        let db = Arc::new(DB::new());
+        for cmd in [
+            "create stream account;",
+            "create event AccountCreated(owner string) on account;",
+            "create event MoneyDeposited(amount int) on account;",
+            "create stream user;",
+            "create event UserCreated(name string, admin bool) on user;",
+            r#"add AccountCreated(owner="axel") to account(id="123");"#,
+            r#"add UserCreated(name="axel", admin=true) to user(id="1");"#,
+            r#"add MoneyDeposited(amount=100) to account(id="123");"#,
+            r#"add AccountCreated(owner="bob") to account(id="456");"#,
+        ] {
+            if let Err(e) = exec(cmd, db.clone()).await {
+                panic!("failed to exec '{}': {}", cmd, e)
+            }
+        }
+
+        let test_cases = vec![
+            (
+                "all",
+                "read $all;",
+                vec![
+                    "position\tstream\tkey\tversion\tevent\tattributes",
+                    "1\taccount\t123\t1\tAccountCreated\towner=\"axel\"",
+                    "2\tuser\t1\t1\tUserCreated\tname=\"axel\", admin=true",
+                    "3\taccount\t123\t2\tMoneyDeposited\tamount=100",
+                    "4\taccount\t456\t1\tAccountCreated\towner=\"bob\"",
+                ],
+            ),
+            (
+                "from position with limit",
+                "read $all from position 2 limit 2;",
+                vec![
+                    "position\tstream\tkey\tversion\tevent\tattributes",
+                    "2\tuser\t1\t1\tUserCreated\tname=\"axel\", admin=true",
+                    "3\taccount\t123\t2\tMoneyDeposited\tamount=100",
+                ],
+            ),
+            (
+                "after the last position",
+                "read $all from position 5;",
+                vec!["position\tstream\tkey\tversion\tevent\tattributes"],
+            ),
+        ];
+        for (name, cmd, expected) in test_cases {
+            let result = match exec(cmd, db.clone()).await {
+                Ok(result) => result,
+                Err(e) => panic!("test case '{}' failed: {}", name, e),
+            };
+            // the timestamps are left out as they are the time the events were added
+            let lines = result
+                .lines()
+                .map(|line| {
+                    let mut values = line.split('\t').collect::<Vec<&str>>();
+                    values.remove(5);
+                    values.join("\t")
+                })
+                .collect::<Vec<String>>();
+            assert_eq!(expected, lines, "test case '{}'", name);
+        }
+
+        match exec("read account;", db.clone()).await {
+            Ok(result) => panic!("reading a stream did not fail: {}", result),
+            Err(e) => assert!(e.contains("only $all can be read"), "got '{}'", e),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_persistent_subscription() {
+        let db = Arc::new(DB::new());
+        for cmd in [
+            "create stream account;",
+            "create event AccountCreated(owner string) on account;",
+            "create subscription workers to account;",
+            r#"add AccountCreated(owner="axel") to account(id="123");"#,
+        ] {
+            if let Err(e) = exec(cmd, db.clone()).await {
+                panic!("failed to exec '{}': {}", cmd, e)
+            }
+        }
+
+        // acking the event writes the checkpoint to the system stream of the subscription,
+        // which is not read from $all
+        let member = Groups::new(db.clone()).get("workers").await.unwrap().join();
+        let event = member.next().await.unwrap();
+        member.ack(&[event.position]).await.unwrap();
+        assert_eq!(2, db.subscription_state("workers").unwrap().checkpoint);
+        match exec("read $all;", db.clone()).await {
+            Ok(result) => assert_eq!(2, result.lines().count(), "got '{}'", result),
+            Err(e) => panic!("failed to read $all: {}", e),
+        }
+
+        let test_cases = vec![
+            (
+                "read parked",
+                "read parked workers;",
+                Ok("position\tstream\tkey\tversion\tevent\ttimestamp\tattributes"),
+            ),
+            (
+                "create again",
+                "create subscription workers to account;",
+                Err("subscription 'workers' already exists"),
+            ),
+            (
+                "ack without joining",
+                "ack 1;",
+                Err("only members of a persistent subscription can ack or nack events"),
+            ),
+            (
+                "join unknown",
+                "join jobs;",
+                Err("unknown subscription 'jobs'"),
+            ),
+        ];
+        for (name, cmd, expected) in test_cases {
+            match (exec(cmd, db.clone()).await, expected) {
+                (Ok(result), Ok(expected)) => {
+                    assert_eq!(expected, result, "test case '{}'", name)
+                }
+                (Err(e), Err(expected)) => {
+                    assert!(e.contains(expected), "test case '{}' got '{}'", name, e)
+                }
+                (result, _) => panic!("test case '{}' got {:?}", name, result),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod e2e_connection_test {
+    use super::*;
+
+    use crate::db::DB;
+
+    use std::sync::Arc;
+    use tokio::time::Duration;
+
+    // starts a server for the db and returns the address it listens on
+    async fn serve(db: Arc<DB<storage::memory::MemoryStore>>) -> std::net::SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let groups = Arc::new(Groups::new(db.clone()));
+        tokio::spawn(async move {
+            loop {
+                let (socket, _) = listener.accept().await.unwrap();
+                tokio::spawn(handle_connection(socket, db.clone(), groups.clone()));
+            }
+        });
+        addr
+    }
+
+    async fn send(client: &mut TcpStream, msg: &str) {
+        client.write_u32(msg.len() as u32).await.unwrap();
+        client.write_all(msg.as_bytes()).await.unwrap();
+    }
+
+    async fn receive(client: &mut TcpStream) -> String {
+        let read = async {
+            let len = client.read_u32().await.unwrap();
+            let mut buffer = vec![0; len as usize];
+            client.read_exact(&mut buffer).await.unwrap();
+            String::from_utf8(buffer).unwrap()
+        };
+        tokio::time::timeout(Duration::from_secs(5), read)
+            .await
+            .expect("no response was received")
+    }
+
+    async fn assert_no_response(client: &mut TcpStream) {
+        let mut buffer = [0; 1];
+        let read = client.read(&mut buffer);
+        if let Ok(result) = tokio::time::timeout(Duration::from_millis(100), read).await {
+            panic!("got unexpected response {:?}", result);
+        }
+    }
+
+    // position of the event of a response with a single row
+    fn position(response: &str) -> &str {
+        let row = response.lines().nth(1).expect("response has no row");
+        row.split('\t').next().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_connection() {
+        let db = Arc::new(DB::new());
+        for cmd in [
+            "create stream account;",
+            "create event AccountCreated(owner string) on account;",
+            "create subscription workers to account;",
+        ] {
+            if let Err(e) = exec(cmd, db.clone()).await {
+                panic!("failed to exec '{}': {}", cmd, e)
+            }
+        }
+        let addr = serve(db.clone()).await;
+
+        // a request can hold several commands, which are run as one transaction
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        send(
+            &mut client,
+            r#"add AccountCreated(owner="a") to account(id="1");
+            add AccountCreated(owner="b") to account(id="2");
+            add AccountCreated(owner="c") to account(id="3");"#,
+        )
+        .await;
+        assert_eq!("all ok", receive(&mut client).await);
+
+        let test_cases = vec![
+            ("subscribe and add", "subscribe to account; read $all;"),
+            ("join and add", "join workers; read $all;"),
+            ("replay parked and add", "read $all; replay parked workers;"),
+        ];
+        for (name, cmd) in test_cases {
+            send(&mut client, cmd).await;
+            assert_eq!(
+                "subscribe, join and replay parked must be the only command of a transaction",
+                receive(&mut client).await,
+                "test case '{}'",
+                name
+            );
+        }
+
+        // every ack of a request is applied, and requests mixing acks with other commands
+        // are rejected
+        let mut member = TcpStream::connect(addr).await.unwrap();
+        send(&mut member, "join workers;").await;
+        assert_eq!("joined workers", receive(&mut member).await);
+        let mut positions = vec![];
+        for _ in 0..3 {
+            positions.push(position(&receive(&mut member).await).to_string());
+        }
+        assert_eq!(vec!["1", "2", "3"], positions);
+        send(&mut member, "ack 1; ack 2;").await;
+        assert_no_response(&mut member).await;
+        send(&mut member, "ack 3; read $all;").await;
+        assert_eq!(
+            "members can only ack or nack events",
+            receive(&mut member).await
+        );
+        drop(member);
+
+        // only the event that was not acked is sent to the next member
+        let mut member = TcpStream::connect(addr).await.unwrap();
+        send(&mut member, "join workers;").await;
+        assert_eq!("joined workers", receive(&mut member).await);
+        assert_eq!("3", position(&receive(&mut member).await));
+        assert_no_response(&mut member).await;
+    }
 }
 
 #[cfg(test)]
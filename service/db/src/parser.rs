@@ -39,32 +39,70 @@ macro_rules! match_extract {
 pub fn parse(input: &str) -> Result<ast::Transaction, ParserError> {
     let mut tokens = tokenize(input);
 
+    // a transaction is one or more commands, each ended by ;
     let mut commands = vec![];
-    let token = tokens.next()?;
-    match token {
-        Token::Keyword(Keyword::Show) => {
-            let cmd = parse_show(&mut tokens)?;
-            commands.push(cmd);
-        }
-        Token::Keyword(Keyword::Create) => {
-            let cmd = parse_create(&mut tokens)?;
-            commands.push(cmd);
-        }
-        Token::Keyword(Keyword::Add) => {
-            let cmd = parse_add(&mut tokens)?;
-            commands.push(cmd);
-        }
-        Token::Keyword(Keyword::Find) => {
-            let cmd = parse_find(&mut tokens)?;
-            commands.push(cmd);
-        }
-        _ => {
-            return Err(ParserError::new(&format!(
-                "got unexpected token '{:?}'",
-                token
-            )))
+    loop {
+        let token = tokens.next()?;
+        match token {
+            Token::Keyword(Keyword::Show) => {
+                let cmd = parse_show(&mut tokens)?;
+                commands.push(cmd);
+            }
+            Token::Keyword(Keyword::Create) => {
+                let cmd = parse_create(&mut tokens)?;
+                commands.push(cmd);
+            }
+            Token::Keyword(Keyword::Add) => {
+                let cmd = parse_add(&mut tokens)?;
+                commands.push(cmd);
+            }
+            Token::Keyword(Keyword::Find) => {
+                let cmd = parse_find(&mut tokens)?;
+                commands.push(cmd);
+            }
+            Token::Keyword(Keyword::Snapshot) => {
+                let cmd = parse_snapshot(&mut tokens)?;
+                commands.push(cmd);
+            }
+            Token::Keyword(Keyword::Read) => {
+                let cmd = parse_read(&mut tokens)?;
+                commands.push(cmd);
+            }
+            Token::Keyword(Keyword::Subscribe) => {
+                let cmd = parse_subscribe(&mut tokens)?;
+                commands.push(cmd);
+            }
+            Token::Keyword(Keyword::Join) => {
+                let cmd = parse_join(&mut tokens)?;
+                commands.push(cmd);
+            }
+            Token::Keyword(Keyword::Ack) => {
+                let positions = parse_positions(&mut tokens)?;
+                commands.push(ast::Command::Ack { positions });
+            }
+            Token::Keyword(Keyword::Nack) => {
+                let positions = parse_positions(&mut tokens)?;
+                commands.push(ast::Command::Nack { positions });
+            }
+            Token::Keyword(Keyword::Replay) => {
+                let cmd = parse_replay(&mut tokens)?;
+                commands.push(cmd);
+            }
+            Token::PatternStart => {
+                let cmd = parse_find_pattern(&mut tokens)?;
+                commands.push(cmd);
+            }
+            _ => {
+                return Err(ParserError::new(&format!(
+                    "got unexpected token '{:?}'",
+                    token
+                )))
+            }
+        };
+        if tokens.is_done() {
+            break;
         }
-    };
+    }
 
     return Ok(ast::Transaction { commands });
 }
@@ -85,11 +123,18 @@ fn parse_create(tokens: &mut Tokens<'_>) -> Result<ast::Command, ParserError> {
                     break;
                 }
 
-                let attribute = ast::AttributeDefinition {
-                    name: match_extract!(tokens, Token::Identifier(name) => name),
-                    data_type: match_extract!(tokens, Token::Identifier(name) => name),
-                };
-                attributes.push(attribute);
+                let name = match_extract!(tokens, Token::Identifier(name) => name);
+                let data_type = match_extract!(tokens, Token::Identifier(name) => name);
+                // attributes are required unless marked as optional
+                let required = tokens.peek()? != Token::Identifier("optional".to_string());
+                if !required {
+                    tokens.next()?;
+                }
+                attributes.push(ast::AttributeDefinition {
+                    name,
+                    data_type,
+                    required,
+                });
 
                 if matches!(tokens.peek()?, Token::Seperator) {
                     tokens.next()?;
@@ -104,6 +149,34 @@ fn parse_create(tokens: &mut Tokens<'_>) -> Result<ast::Command, ParserError> {
                 attributes,
             }
         }
+        // create index on <stream>.<event>.<attribute>
+        "index" => {
+            match_extract!(tokens, Token::AuxiliaryOn);
+            let stream_name = match_extract!(tokens, Token::Identifier(name) => name);
+            match_extract!(tokens, Token::Accessor);
+            let event_name = match_extract!(tokens, Token::Identifier(name) => name);
+            match_extract!(tokens, Token::Accessor);
+            let attribute_name = match_extract!(tokens, Token::Identifier(name) => name);
+            ast::Entity::Index {
+                stream_name,
+                event_name,
+                attribute_name,
+            }
+        }
+        // create subscription <name> to <stream | $all> [from position <N>] [park after <N>]
+        "subscription" => {
+            let name = match_extract!(tokens, Token::Identifier(name) => name);
+            match_extract!(tokens, Token::AuxiliaryTo);
+            let stream_name = match_extract!(tokens, Token::Identifier(name) => name);
+            let from_position = parse_optional_from_position_clause(tokens)?;
+            let park_after = parse_optional_park_after_clause(tokens)?;
+            ast::Entity::Subscription {
+                name,
+                stream_name,
+                from_position,
+                park_after,
+            }
+        }
         _ => {
             return Err(ParserError::new(&format!(
                 "Got unsupported entity '{}'",
@@ -111,6 +184,7 @@ fn parse_create(tokens: &mut Tokens<'_>) -> Result<ast::Command, ParserError> {
             )))
         }
     };
+    match_extract!(tokens, Token::EOF);
 
     Ok(ast::Command::Create { entity })
 }
@@ -131,7 +205,18 @@ fn parse_add(tokens: &mut Tokens<'_>) -> Result<ast::Command, ParserError> {
             Token::LiteralInt(v) => ast::Value::Int(v),
             Token::LiteralStr(v) => ast::Value::String(v),
             Token::LiteralFloat(v) => ast::Value::Float(v),
+            Token::Operator(Operator::Subtract) => match tokens.next()? {
+                Token::LiteralInt(v) => ast::Value::Int(-v),
+                Token::LiteralFloat(v) => ast::Value::Float(-v),
+                token => {
+                    return Err(ParserError::new(&format!(
+                        "expected a number after -, got {:?}",
+                        token
+                    )))
+                }
+            },
             Token::LiteralBool(v) => ast::Value::Bool(v),
+            Token::LiteralNull => ast::Value::Null,
             _ => {
                 return Err(ParserError::new(&format!(
                     "got unexpected type {:?}",
@@ -158,6 +243,7 @@ fn parse_add(tokens: &mut Tokens<'_>) -> Result<ast::Command, ParserError> {
     match_extract!(tokens, Token::Assign);
     let stream_id = match_extract!(tokens, Token::LiteralStr(stream_id) => stream_id); //id=<stream_id>
     match_extract!(tokens, Token::GroupEnd);
+    let expected_version = parse_optional_expect_clause(tokens)?;
     match_extract!(tokens, Token::EOF);
 
     Ok(ast::Command::Add {
@@ -167,34 +253,163 @@ fn parse_add(tokens: &mut Tokens<'_>) -> Result<ast::Command, ParserError> {
         },
         stream,
         stream_id,
+        expected_version,
     })
 }
 
+// expect version <N>
+fn parse_optional_expect_clause(tokens: &mut Tokens<'_>) -> Result<Option<u64>, ParserError> {
+    if tokens.peek()? != Token::Keyword(Keyword::Expect) {
+        return Ok(None);
+    }
+    tokens.next()?;
+
+    let word = match_extract!(tokens, Token::Identifier(word) => word);
+    if word != "version" {
+        return Err(ParserError::new(&format!(
+            "expected 'version' after 'expect' but got '{}'",
+            word
+        )));
+    }
+    let version = match_extract!(tokens, Token::LiteralInt(version) => version);
+    let version = u64::try_from(version).map_err(|_| {
+        ParserError::new(&format!(
+            "expected version can not be negative: {}",
+            version
+        ))
+    })?;
+
+    Ok(Some(version))
+}
+
 fn parse_find(tokens: &mut Tokens<'_>) -> Result<ast::Command, ParserError> {
     let projections = parse_projections_clause(tokens)?;
-    let predicates = parse_optional_where_clause(tokens)?;
+    let predicate = parse_optional_where_clause(tokens)?;
+    let as_of = parse_optional_as_of_clause(tokens)?;
+    let group_by = parse_optional_group_by_clause(tokens)?;
+    let order_by = parse_optional_order_by_clause(tokens)?;
     let limit = parse_optional_limit_clause(tokens)?;
+    let offset = parse_optional_offset_clause(tokens)?;
+    let after = parse_optional_after_clause(tokens)?;
 
     match_extract!(tokens, Token::EOF);
 
     Ok(ast::Command::Find {
         projections,
-        predicates,
+        predicate,
+        group_by,
+        as_of,
+        order_by,
         limit,
+        offset,
+        after,
     })
 }
 
+// as of timestamp <timestamp> | as of version <version>
+fn parse_optional_as_of_clause(tokens: &mut Tokens<'_>) -> Result<Option<ast::AsOf>, ParserError> {
+    if tokens.peek()? != Token::Keyword(Keyword::As) {
+        return Ok(None);
+    }
+    tokens.next()?;
+    match_extract!(tokens, Token::Keyword(Keyword::Of) => ());
+
+    let point = match_extract!(tokens, Token::Identifier(point) => point);
+    let as_of = match (point.as_str(), tokens.next()?) {
+        ("timestamp", Token::LiteralTimestamp(t) | Token::LiteralInt(t)) => ast::AsOf::Timestamp(t),
+        ("version", Token::LiteralInt(n)) => ast::AsOf::Version(n),
+        (point, token) => {
+            return Err(ParserError::new(&format!(
+                "expected as of timestamp or version, got '{}' {:?}",
+                point, token
+            )))
+        }
+    };
+    Ok(Some(as_of))
+}
+
+// order by <expression> [asc|desc], ... where the direction defaults to asc
+fn parse_optional_order_by_clause(tokens: &mut Tokens<'_>) -> Result<Vec<ast::Order>, ParserError> {
+    if tokens.peek()? != Token::Keyword(Keyword::Order) {
+        return Ok(vec![]);
+    }
+    tokens.next()?;
+    match_extract!(tokens, Token::Keyword(Keyword::By) => ());
+
+    let mut order_by = vec![];
+    loop {
+        let expression = parse_expression(tokens)?;
+        let direction = match tokens.peek()? {
+            Token::Keyword(Keyword::Asc) => {
+                tokens.next()?;
+                ast::Direction::Asc
+            }
+            Token::Keyword(Keyword::Desc) => {
+                tokens.next()?;
+                ast::Direction::Desc
+            }
+            _ => ast::Direction::Asc,
+        };
+        order_by.push(ast::Order {
+            expression,
+            direction,
+        });
+
+        if tokens.peek()? != Token::Seperator {
+            return Ok(order_by);
+        }
+        tokens.next()?;
+    }
+}
+
+fn parse_optional_offset_clause(
+    tokens: &mut Tokens<'_>,
+) -> Result<Option<ast::Offset>, ParserError> {
+    if tokens.peek()? != Token::Keyword(Keyword::Offset) {
+        return Ok(None);
+    }
+    tokens.next()?;
+    let n = match_extract!(tokens, Token::LiteralInt(n) => n);
+    Ok(Some(ast::Offset(n)))
+}
+
+fn parse_optional_after_clause(tokens: &mut Tokens<'_>) -> Result<Option<String>, ParserError> {
+    if tokens.peek()? != Token::Keyword(Keyword::After) {
+        return Ok(None);
+    }
+    tokens.next()?;
+    let cursor = match_extract!(tokens, Token::LiteralStr(cursor) => cursor);
+    Ok(Some(cursor))
+}
+
+fn parse_optional_group_by_clause(
+    tokens: &mut Tokens<'_>,
+) -> Result<Vec<ast::Expression>, ParserError> {
+    if tokens.peek()? != Token::Keyword(Keyword::Group) {
+        return Ok(vec![]);
+    }
+    tokens.next()?;
+    match_extract!(tokens, Token::Keyword(Keyword::By) => ());
+
+    let mut expressions = vec![parse_expression(tokens)?];
+    while tokens.peek()? == Token::Seperator {
+        tokens.next()?;
+        expressions.push(parse_expression(tokens)?);
+    }
+    Ok(expressions)
+}
+
 fn parse_optional_where_clause(
     tokens: &mut Tokens<'_>,
-) -> Result<Vec<ast::Predicate>, ParserError> {
+) -> Result<Option<ast::Expression>, ParserError> {
     let token = tokens.peek()?;
 
     if token != Token::Keyword(Keyword::Where) {
-        return Ok(vec![]);
+        return Ok(None);
     }
 
     tokens.next()?;
-    parse_predicates(tokens)
+    Ok(Some(parse_expression(tokens)?))
 }
 
 fn parse_optional_limit_clause(tokens: &mut Tokens<'_>) -> Result<Option<ast::Limit>, ParserError> {
@@ -217,39 +432,40 @@ fn parse_optional_limit_clause(tokens: &mut Tokens<'_>) -> Result<Option<ast::Li
     }
 }
 
-fn parse_predicates(tokens: &mut Tokens<'_>) -> Result<Vec<ast::Predicate>, ParserError> {
-    let mut predicates = vec![];
-    loop {
-        let peeked_token = tokens.peek()?;
-        if peeked_token == Token::EOF {
-            break;
-        }
-
-        let left = parse_expression(tokens)?;
-        let token_operator = match_extract!(tokens, Token::Operator(op) => op);
-        let operator = map_operator_to_binary_operator(&token_operator);
-        let right = parse_expression(tokens)?;
-
-        let predicate = ast::Predicate::BinaryOperation {
-            left,
-            operator,
-            right,
-        };
-
-        predicates.push(predicate);
+// (<expression>, ...)
+fn parse_list(tokens: &mut Tokens<'_>) -> Result<ast::Expression, ParserError> {
+    match_extract!(tokens, Token::GroupStart);
+    let mut expressions = vec![parse_expression(tokens)?];
+    while tokens.peek()? == Token::Seperator {
+        tokens.next()?;
+        expressions.push(parse_expression(tokens)?);
     }
+    match_extract!(tokens, Token::GroupEnd);
+    Ok(ast::Expression::List(expressions))
+}
 
-    Ok(predicates)
+// the clauses of find that can follow the projections and the predicates
+fn is_end_of_clause(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::EOF
+            | Token::Keyword(
+                Keyword::As
+                    | Keyword::Group
+                    | Keyword::Order
+                    | Keyword::Limit
+                    | Keyword::Offset
+                    | Keyword::After
+            )
+    )
 }
 
 fn parse_projections_clause(tokens: &mut Tokens<'_>) -> Result<Vec<ast::Projection>, ParserError> {
     let mut projections = vec![];
     loop {
         // stop of there are no more projections
-        if matches!(
-            tokens.peek()?,
-            Token::Keyword(Keyword::Where) | Token::Keyword(Keyword::Limit)
-        ) {
+        let token = tokens.peek()?;
+        if token == Token::Keyword(Keyword::Where) || is_end_of_clause(&token) {
             break;
         }
 
@@ -266,54 +482,369 @@ fn parse_projections_clause(tokens: &mut Tokens<'_>) -> Result<Vec<ast::Projecti
     Ok(projections)
 }
 
-// account.user_id
-// sum(account.amount)
-// sum(account.amount) + (100 + 100))
-// 100
+// expressions are parsed by precedence climbing, see ast::BinaryOperator::precedence.
+// An operand is parsed first, followed by operators and their right hand side for as
+// long as the operators bind at least as tight as min_precedence
+//
+//   sum(account.amount) - sum(account.fee) * 2
+//   account.amount > 10 and not (account.owner = "axel" or key in ("1", "2"))
 fn parse_expression(tokens: &mut Tokens<'_>) -> Result<ast::Expression, ParserError> {
+    parse_operation(tokens, 0)
+}
+
+fn parse_operation(
+    tokens: &mut Tokens<'_>,
+    min_precedence: u8,
+) -> Result<ast::Expression, ParserError> {
+    let mut left = parse_operand(tokens)?;
+
+    loop {
+        // between binds like the comparisons, its bounds are parsed like the right hand
+        // side of a comparison so the and in between is not taken as a logical and
+        if tokens.peek()? == Token::Keyword(Keyword::Between) {
+            let precedence = ast::BinaryOperator::LessEqual.precedence();
+            if precedence < min_precedence {
+                break;
+            }
+            tokens.next()?;
+            let low = parse_operation(tokens, precedence + 1)?;
+            match_extract!(tokens, Token::Keyword(Keyword::And) => ());
+            let high = parse_operation(tokens, precedence + 1)?;
+            left = ast::Expression::Between {
+                expression: Box::new(left),
+                low: Box::new(low),
+                high: Box::new(high),
+            };
+            continue;
+        }
+
+        let operator = match tokens.peek()? {
+            Token::Keyword(Keyword::Or) => ast::BinaryOperator::Or,
+            Token::Keyword(Keyword::And) => ast::BinaryOperator::And,
+            Token::Keyword(Keyword::In) => ast::BinaryOperator::In,
+            // not after an operand can only be not in
+            Token::Keyword(Keyword::Not) => ast::BinaryOperator::NotIn,
+            // = is accepted as well as ==
+            Token::Assign => ast::BinaryOperator::Equal,
+            Token::Operator(operator) => map_operator_to_binary_operator(&operator),
+            _ => break,
+        };
+        // operators of the same precedence are left associative
+        let precedence = operator.precedence();
+        if precedence < min_precedence {
+            break;
+        }
+        tokens.next()?;
+
+        let right = match operator {
+            ast::BinaryOperator::In => parse_list(tokens)?,
+            ast::BinaryOperator::NotIn => {
+                match_extract!(tokens, Token::Keyword(Keyword::In) => ());
+                parse_list(tokens)?
+            }
+            _ => parse_operation(tokens, precedence + 1)?,
+        };
+        left = ast::Expression::BinaryOperation {
+            left: Box::new(left),
+            operator,
+            right: Box::new(right),
+        };
+    }
+
+    Ok(left)
+}
+
+fn parse_operand(tokens: &mut Tokens<'_>) -> Result<ast::Expression, ParserError> {
     let token = tokens.next()?;
     let expression = match token {
+        Token::Keyword(Keyword::Not) => ast::Expression::UnaryOperation {
+            operator: ast::UnaryOperator::Not,
+            operand: Box::new(parse_operation(tokens, ast::NOT_PRECEDENCE)?),
+        },
+        Token::Operator(Operator::Subtract) => ast::Expression::UnaryOperation {
+            operator: ast::UnaryOperator::Negate,
+            operand: Box::new(parse_operation(tokens, ast::NEGATE_PRECEDENCE)?),
+        },
+        Token::GroupStart => {
+            let expression = parse_expression(tokens)?;
+            match_extract!(tokens, Token::GroupEnd);
+            expression
+        }
+        // key, timestamp, version and event are pseudo-attributes of every event unless
+        // they are the name of a stream
+        Token::Identifier(name)
+            if is_pseudo_attribute(&name) && tokens.peek()? != Token::Accessor =>
+        {
+            match name.as_str() {
+                "key" => ast::Expression::Key,
+                "timestamp" => ast::Expression::Timestamp,
+                "version" => ast::Expression::Version,
+                _ => ast::Expression::Event,
+            }
+        }
         Token::Identifier(stream) => {
             match_extract!(tokens, Token::Accessor);
+            let name = match_extract!(tokens, Token::Identifier(v) => v);
+            if tokens.peek()? != Token::Accessor {
+                return Ok(ast::Expression::Attribute {
+                    stream,
+                    event: None,
+                    attribute: name,
+                });
+            }
+            tokens.next()?;
             let attribute = match_extract!(tokens, Token::Identifier(v) => v);
-            ast::Expression::Attribute { stream, attribute }
+            ast::Expression::Attribute {
+                stream,
+                event: Some(name),
+                attribute,
+            }
         }
-        Token::Function(Function::Sum) => {
+        Token::Function(function) => {
             match_extract!(tokens, Token::GroupStart);
             let expression = parse_expression(tokens)?;
+            let function = parse_aggregate_function(tokens, &function)?;
             match_extract!(tokens, Token::GroupEnd);
             ast::Expression::Aggregate {
-                function: ast::Function::Sum,
+                function,
                 argument: Box::new(expression),
             }
         }
         Token::LiteralStr(str) => ast::Expression::Literal(ast::Value::String(str)),
         Token::LiteralInt(int) => ast::Expression::Literal(ast::Value::Int(int)),
         Token::LiteralFloat(float) => ast::Expression::Literal(ast::Value::Float(float)),
+        Token::LiteralBool(bool) => ast::Expression::Literal(ast::Value::Bool(bool)),
+        Token::LiteralNull => ast::Expression::Literal(ast::Value::Null),
+        Token::LiteralTimestamp(v) => ast::Expression::Literal(ast::Value::Int(v)),
         _ => return Err(ParserError::new(&format!("unexpected token: {:?}`", token))),
     };
+    Ok(expression)
+}
 
-    if matches!(
-        tokens.peek()?,
-        Token::Operator(Operator::Add) | Token::Operator(Operator::Subtract)
-    ) {
-        tokens.next()?;
-        return Ok(ast::Expression::BinaryOperation {
-            left: Box::new(expression),
-            operator: ast::BinaryOperator::Add,
-            right: Box::new(parse_expression(tokens)?),
-        });
+fn is_pseudo_attribute(name: &str) -> bool {
+    matches!(name, "key" | "timestamp" | "version" | "event")
+}
+
+// [:find ?variable ... :where <clause> ...]
+//
+//   [:find ?owner ?amount
+//    :where [?c :type "AccountCreated"] [?c :key ?id] [?c :owner ?owner]
+//           [?d :type "MoneyDeposited"] [?d :key ?id] [?d :amount ?amount]
+//           [(> ?amount 100)]];
+fn parse_find_pattern(tokens: &mut Tokens<'_>) -> Result<ast::Command, ParserError> {
+    parse_symbol(tokens, "find")?;
+    let mut variables = vec![];
+    while let Token::Variable(_) = tokens.peek()? {
+        variables.push(match_extract!(tokens, Token::Variable(name) => name));
     }
 
-    Ok(expression)
+    parse_symbol(tokens, "where")?;
+    let mut clauses = vec![];
+    while tokens.peek()? == Token::PatternStart {
+        clauses.push(parse_clause(tokens)?);
+    }
+    match_extract!(tokens, Token::PatternEnd);
+    match_extract!(tokens, Token::EOF);
+
+    Ok(ast::Command::FindPattern { variables, clauses })
+}
+
+fn parse_symbol(tokens: &mut Tokens<'_>, expected: &str) -> Result<(), ParserError> {
+    match tokens.next()? {
+        Token::Symbol(symbol) if symbol == expected => Ok(()),
+        token => Err(ParserError::new(&format!(
+            "expected :{} but got {:?}",
+            expected, token
+        ))),
+    }
+}
+
+// [?entity :attribute value] or [(<operator> <term> <term>)]
+fn parse_clause(tokens: &mut Tokens<'_>) -> Result<ast::Clause, ParserError> {
+    match_extract!(tokens, Token::PatternStart);
+    let clause = match tokens.peek()? {
+        Token::GroupStart => ast::Clause::Predicate(parse_term(tokens)?),
+        _ => {
+            let entity = match_extract!(tokens, Token::Variable(name) => name);
+            let attribute = match_extract!(tokens, Token::Symbol(name) => name);
+            let value = parse_term(tokens)?;
+            if let ast::Term::Operation { .. } = value {
+                return Err(ParserError::new(&format!(
+                    "the value of a pattern must be a variable or a literal, got {:?}",
+                    value
+                )));
+            }
+            ast::Clause::Pattern {
+                entity,
+                attribute,
+                value,
+            }
+        }
+    };
+    match_extract!(tokens, Token::PatternEnd);
+    Ok(clause)
+}
+
+fn parse_term(tokens: &mut Tokens<'_>) -> Result<ast::Term, ParserError> {
+    let token = tokens.next()?;
+    let term = match token {
+        Token::Variable(name) => ast::Term::Variable(name),
+        Token::GroupStart => {
+            let operator = match tokens.next()? {
+                Token::Operator(operator) => map_operator_to_binary_operator(&operator),
+                Token::Assign => ast::BinaryOperator::Equal,
+                Token::Keyword(Keyword::And) => ast::BinaryOperator::And,
+                Token::Keyword(Keyword::Or) => ast::BinaryOperator::Or,
+                token => {
+                    return Err(ParserError::new(&format!(
+                        "expected an operator but got {:?}",
+                        token
+                    )))
+                }
+            };
+            let left = parse_term(tokens)?;
+            let right = parse_term(tokens)?;
+            match_extract!(tokens, Token::GroupEnd);
+            ast::Term::Operation {
+                operator,
+                left: Box::new(left),
+                right: Box::new(right),
+            }
+        }
+        Token::Operator(Operator::Subtract) => match tokens.next()? {
+            Token::LiteralInt(v) => ast::Term::Literal(ast::Value::Int(-v)),
+            Token::LiteralFloat(v) => ast::Term::Literal(ast::Value::Float(-v)),
+            token => {
+                return Err(ParserError::new(&format!(
+                    "expected a number after -, got {:?}",
+                    token
+                )))
+            }
+        },
+        Token::LiteralStr(v) => ast::Term::Literal(ast::Value::String(v)),
+        Token::LiteralInt(v) => ast::Term::Literal(ast::Value::Int(v)),
+        Token::LiteralFloat(v) => ast::Term::Literal(ast::Value::Float(v)),
+        Token::LiteralBool(v) => ast::Term::Literal(ast::Value::Bool(v)),
+        Token::LiteralNull => ast::Term::Literal(ast::Value::Null),
+        Token::LiteralTimestamp(v) => ast::Term::Literal(ast::Value::Int(v)),
+        _ => return Err(ParserError::new(&format!("unexpected token: {:?}", token))),
+    };
+    Ok(term)
 }
 
 fn parse_show(tokens: &mut Tokens<'_>) -> Result<ast::Command, ParserError> {
     let entity = parse_entity(tokens)?;
+    match_extract!(tokens, Token::EOF);
     let cmd = ast::Command::Show { entity };
     Ok(cmd)
 }
 
+fn parse_snapshot(tokens: &mut Tokens<'_>) -> Result<ast::Command, ParserError> {
+    match_extract!(tokens, Token::AuxiliaryTo);
+    let path = match_extract!(tokens, Token::LiteralStr(path) => path);
+    match_extract!(tokens, Token::EOF);
+    Ok(ast::Command::Snapshot { path })
+}
+
+// $all is the only stream that can be read, the events of all streams in the order they
+// were added. The parked events of a persistent subscription are read with read parked
+fn parse_read(tokens: &mut Tokens<'_>) -> Result<ast::Command, ParserError> {
+    let stream = match_extract!(tokens, Token::Identifier(stream) => stream);
+    if stream == "parked" {
+        let subscription = match_extract!(tokens, Token::Identifier(name) => name);
+        match_extract!(tokens, Token::EOF);
+        return Ok(ast::Command::ReadParked { subscription });
+    }
+    if stream != "$all" {
+        return Err(ParserError::new(&format!(
+            "only $all can be read, got '{}'",
+            stream
+        )));
+    }
+    let from_position = parse_optional_from_position_clause(tokens)?;
+    let limit = parse_optional_limit_clause(tokens)?;
+    match_extract!(tokens, Token::EOF);
+    Ok(ast::Command::ReadAll {
+        from_position,
+        limit,
+    })
+}
+
+fn parse_subscribe(tokens: &mut Tokens<'_>) -> Result<ast::Command, ParserError> {
+    match_extract!(tokens, Token::AuxiliaryTo);
+    let stream = match_extract!(tokens, Token::Identifier(stream) => stream);
+    let from_position = parse_optional_from_position_clause(tokens)?;
+    let predicate = parse_optional_where_clause(tokens)?;
+    match_extract!(tokens, Token::EOF);
+    Ok(ast::Command::Subscribe {
+        stream,
+        from_position,
+        predicate,
+    })
+}
+
+fn parse_join(tokens: &mut Tokens<'_>) -> Result<ast::Command, ParserError> {
+    let subscription = match_extract!(tokens, Token::Identifier(name) => name);
+    let limit = parse_optional_limit_clause(tokens)?;
+    match_extract!(tokens, Token::EOF);
+    Ok(ast::Command::Join {
+        subscription,
+        limit,
+    })
+}
+
+fn parse_replay(tokens: &mut Tokens<'_>) -> Result<ast::Command, ParserError> {
+    let parked = match_extract!(tokens, Token::Identifier(name) => name);
+    if parked != "parked" {
+        return Err(ParserError::new(&format!(
+            "only parked events can be replayed, got '{}'",
+            parked
+        )));
+    }
+    let subscription = match_extract!(tokens, Token::Identifier(name) => name);
+    match_extract!(tokens, Token::EOF);
+    Ok(ast::Command::ReplayParked { subscription })
+}
+
+// <position>, ... ;
+fn parse_positions(tokens: &mut Tokens<'_>) -> Result<Vec<i64>, ParserError> {
+    let mut positions = vec![match_extract!(tokens, Token::LiteralInt(n) => n)];
+    while tokens.peek()? == Token::Seperator {
+        tokens.next()?;
+        positions.push(match_extract!(tokens, Token::LiteralInt(n) => n));
+    }
+    match_extract!(tokens, Token::EOF);
+    Ok(positions)
+}
+
+// park after <N>, the number of times an event can fail before it is parked
+fn parse_optional_park_after_clause(tokens: &mut Tokens<'_>) -> Result<Option<i64>, ParserError> {
+    if tokens.peek()? != Token::Identifier("park".to_string()) {
+        return Ok(None);
+    }
+    tokens.next()?;
+    match_extract!(tokens, Token::Keyword(Keyword::After) => ());
+    Ok(Some(match_extract!(tokens, Token::LiteralInt(n) => n)))
+}
+
+fn parse_optional_from_position_clause(
+    tokens: &mut Tokens<'_>,
+) -> Result<Option<i64>, ParserError> {
+    if tokens.peek()? != Token::Keyword(Keyword::From) {
+        return Ok(None);
+    }
+    tokens.next()?;
+
+    let name = match_extract!(tokens, Token::Identifier(name) => name);
+    if name != "position" {
+        return Err(ParserError::new(&format!(
+            "expected position after from, got '{}'",
+            name
+        )));
+    }
+    Ok(Some(match_extract!(tokens, Token::LiteralInt(n) => n)))
+}
+
 fn parse_entity(tokens: &mut Tokens<'_>) -> Result<ast::Entity, ParserError> {
     // let entity_name = match_extract!(tokens, Token::Identifier(entity_name) => entity_name)
     let token = tokens.next()?;
@@ -359,6 +890,28 @@ impl From<TokenizerError> for ParserError {
 
 impl Error for ParserError {}
 
+// the arguments of the function that follow the expression it is computed over, e.g.
+// the position of nth(account.owner, 2)
+fn parse_aggregate_function(
+    tokens: &mut Tokens<'_>,
+    function: &Function,
+) -> Result<ast::Function, ParserError> {
+    let function = match function {
+        Function::Sum => ast::Function::Sum,
+        Function::Min => ast::Function::Min,
+        Function::Max => ast::Function::Max,
+        Function::Avg => ast::Function::Avg,
+        Function::Count => ast::Function::Count,
+        Function::First => ast::Function::First,
+        Function::Last => ast::Function::Last,
+        Function::Nth => {
+            match_extract!(tokens, Token::Seperator);
+            ast::Function::Nth(match_extract!(tokens, Token::LiteralInt(n) => n))
+        }
+    };
+    Ok(function)
+}
+
 pub fn map_operator_to_binary_operator(operator: &Operator) -> ast::BinaryOperator {
     match operator {
         Operator::Add => ast::BinaryOperator::Add,
@@ -380,7 +933,41 @@ mod parser_test {
 
     use crate::ast::ast;
 
-    use super::parse;
+    use super::{parse, parse_expression};
+    use crate::tokenizer::{tokenize, Token};
+
+    #[test]
+    fn test_parse_transaction() {
+        let test_cases = vec![
+            (
+                "commands",
+                "ack 1; nack 2;\n",
+                Some(vec![
+                    ast::Command::Ack { positions: vec![1] },
+                    ast::Command::Nack { positions: vec![2] },
+                ]),
+            ),
+            (
+                "create and show",
+                "create stream account; show schema;",
+                Some(vec![
+                    ast::Command::Create {
+                        entity: ast::Entity::Stream("account".to_string()),
+                    },
+                    ast::Command::Show {
+                        entity: ast::Entity::Schema,
+                    },
+                ]),
+            ),
+            ("trailing input", "ack 1; ack", None),
+            ("empty command", "ack 1;;", None),
+            ("empty", " ", None),
+        ];
+        for (name, input, expected) in test_cases {
+            let commands = parse(input).ok().map(|trx| trx.commands);
+            assert_eq!(expected, commands, "test case '{}'", name);
+        }
+    }
 
     #[test]
     fn test_parse_show() {
@@ -397,6 +984,203 @@ mod parser_test {
         assert_eq!(expected, ast)
     }
 
+    #[test]
+    fn test_parse_read_all() {
+        let test_cases = vec![
+            (
+                "from position with limit",
+                "read $all from position 42 limit 10;",
+                ast::Command::ReadAll {
+                    from_position: Some(42),
+                    limit: Some(ast::Limit(10)),
+                },
+            ),
+            (
+                "from the start",
+                "read $all;",
+                ast::Command::ReadAll {
+                    from_position: None,
+                    limit: None,
+                },
+            ),
+        ];
+
+        for (name, input, expected) in test_cases {
+            match parse(input) {
+                Ok(ast) => assert_eq!(
+                    ast::Transaction {
+                        commands: vec![expected]
+                    },
+                    ast,
+                    "test case '{}'",
+                    name
+                ),
+                Err(e) => panic!("test case '{}' failed to parse: {}", name, e),
+            }
+        }
+
+        for input in ["read account;", "read $all from 42;"] {
+            assert!(parse(input).is_err(), "'{}' was parsed", input);
+        }
+    }
+
+    #[test]
+    fn test_parse_subscribe() {
+        let test_cases = vec![
+            (
+                "stream from position",
+                "subscribe to account from position 42;",
+                ast::Command::Subscribe {
+                    stream: "account".to_string(),
+                    from_position: Some(42),
+                    predicate: None,
+                },
+            ),
+            (
+                "all streams with a predicate",
+                r#"subscribe to $all where key = "123";"#,
+                ast::Command::Subscribe {
+                    stream: "$all".to_string(),
+                    from_position: None,
+                    predicate: Some(ast::Expression::BinaryOperation {
+                        left: Box::new(ast::Expression::Key),
+                        operator: ast::BinaryOperator::Equal,
+                        right: Box::new(ast::Expression::Literal(ast::Value::String(
+                            "123".to_string(),
+                        ))),
+                    }),
+                },
+            ),
+        ];
+
+        for (name, input, expected) in test_cases {
+            match parse(input) {
+                Ok(ast) => assert_eq!(
+                    ast::Transaction {
+                        commands: vec![expected]
+                    },
+                    ast,
+                    "test case '{}'",
+                    name
+                ),
+                Err(e) => panic!("test case '{}' failed to parse: {}", name, e),
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_persistent_subscription() {
+        let test_cases = vec![
+            (
+                "create",
+                "create subscription workers to account;",
+                ast::Command::Create {
+                    entity: ast::Entity::Subscription {
+                        name: "workers".to_string(),
+                        stream_name: "account".to_string(),
+                        from_position: None,
+                        park_after: None,
+                    },
+                },
+            ),
+            (
+                "create from position and park after",
+                "create subscription workers to $all from position 42 park after 3;",
+                ast::Command::Create {
+                    entity: ast::Entity::Subscription {
+                        name: "workers".to_string(),
+                        stream_name: "$all".to_string(),
+                        from_position: Some(42),
+                        park_after: Some(3),
+                    },
+                },
+            ),
+            (
+                "join",
+                "join workers;",
+                ast::Command::Join {
+                    subscription: "workers".to_string(),
+                    limit: None,
+                },
+            ),
+            (
+                "join with limit",
+                "join workers limit 1;",
+                ast::Command::Join {
+                    subscription: "workers".to_string(),
+                    limit: Some(ast::Limit(1)),
+                },
+            ),
+            (
+                "ack",
+                "ack 42;",
+                ast::Command::Ack {
+                    positions: vec![42],
+                },
+            ),
+            (
+                "nack many",
+                "nack 42, 43, 45;",
+                ast::Command::Nack {
+                    positions: vec![42, 43, 45],
+                },
+            ),
+            (
+                "read parked",
+                "read parked workers;",
+                ast::Command::ReadParked {
+                    subscription: "workers".to_string(),
+                },
+            ),
+            (
+                "replay parked",
+                "replay parked workers;",
+                ast::Command::ReplayParked {
+                    subscription: "workers".to_string(),
+                },
+            ),
+        ];
+
+        for (name, input, expected) in test_cases {
+            match parse(input) {
+                Ok(ast) => assert_eq!(
+                    ast::Transaction {
+                        commands: vec![expected]
+                    },
+                    ast,
+                    "test case '{}'",
+                    name
+                ),
+                Err(e) => panic!("test case '{}' failed to parse: {}", name, e),
+            }
+        }
+
+        for input in [
+            "create subscription workers to account park 3;",
+            "ack;",
+            "ack 1, ;",
+            "replay workers;",
+            "join workers from position 1;",
+        ] {
+            assert!(parse(input).is_err(), "'{}' was parsed", input);
+        }
+    }
+
+    #[test]
+    fn test_parse_snapshot() {
+        let ast = match parse("snapshot to \"backups/2024-01-01.snap\";") {
+            Ok(a) => a,
+            Err(e) => panic!("failed to parse: {}", e),
+        };
+        let expected = ast::Transaction {
+            commands: vec![ast::Command::Snapshot {
+                path: "backups/2024-01-01.snap".to_string(),
+            }],
+        };
+
+        assert_eq!(expected, ast)
+    }
+
     #[test]
     fn test_parse_create() {
         let test_cases = vec![
@@ -413,7 +1197,8 @@ mod parser_test {
                 "create event",
                 "create event AccountCreated(
                     owner string,
-                    amount int 
+                    amount int,
+                    note string optional
                 ) on account;",
                 ast::Transaction {
                     commands: vec![ast::Command::Create {
@@ -424,16 +1209,36 @@ mod parser_test {
                                 ast::AttributeDefinition {
                                     name: "owner".to_string(),
                                     data_type: "string".to_string(),
+                                    required: true,
                                 },
                                 ast::AttributeDefinition {
                                     name: "amount".to_string(),
                                     data_type: "int".to_string(),
+                                    required: true,
+                                },
+                                ast::AttributeDefinition {
+                                    name: "note".to_string(),
+                                    data_type: "string".to_string(),
+                                    required: false,
                                 },
                             ],
                         },
                     }],
                 },
             ),
+            (
+                "create index",
+                "create index on account.AccountCreated.owner-name;",
+                ast::Transaction {
+                    commands: vec![ast::Command::Create {
+                        entity: ast::Entity::Index {
+                            stream_name: "account".to_string(),
+                            event_name: "AccountCreated".to_string(),
+                            attribute_name: "owner-name".to_string(),
+                        },
+                    }],
+                },
+            ),
         ];
         for (name, input, expected) in test_cases {
             let ast = match parse(input) {
@@ -447,33 +1252,54 @@ mod parser_test {
 
     #[test]
     fn test_parse_add() {
-        let test_cases = vec![(
-            "add event to account",
-            r#"add AccountCreated(user_id="123", inital_amount=100.59, currency="SEK") to account(id="123");"#,
-            ast::Transaction {
-                commands: vec![ast::Command::Add {
-                    event: ast::Event {
-                        name: "AccountCreated".to_string(),
-                        values: vec![
-                            ast::AttributeValue {
-                                name: "user_id".to_string(),
-                                value: ast::Value::String("123".to_string()),
-                            },
-                            ast::AttributeValue {
-                                name: "inital_amount".to_string(),
-                                value: ast::Value::Float(100.59),
-                            },
-                            ast::AttributeValue {
-                                name: "currency".to_string(),
-                                value: ast::Value::String("SEK".to_string()),
-                            },
-                        ],
-                    },
-                    stream: "account".to_string(),
-                    stream_id: "123".to_string(),
-                }],
-            },
-        )];
+        let test_cases = vec![
+            (
+                "add event to account",
+                r#"add AccountCreated(user_id="123", inital_amount=100.59, currency="SEK") to account(id="123");"#,
+                ast::Transaction {
+                    commands: vec![ast::Command::Add {
+                        event: ast::Event {
+                            name: "AccountCreated".to_string(),
+                            values: vec![
+                                ast::AttributeValue {
+                                    name: "user_id".to_string(),
+                                    value: ast::Value::String("123".to_string()),
+                                },
+                                ast::AttributeValue {
+                                    name: "inital_amount".to_string(),
+                                    value: ast::Value::Float(100.59),
+                                },
+                                ast::AttributeValue {
+                                    name: "currency".to_string(),
+                                    value: ast::Value::String("SEK".to_string()),
+                                },
+                            ],
+                        },
+                        stream: "account".to_string(),
+                        stream_id: "123".to_string(),
+                        expected_version: None,
+                    }],
+                },
+            ),
+            (
+                "add event expecting a version",
+                r#"add MoneyDeposited(amount=100) to account(id="123") expect version 7;"#,
+                ast::Transaction {
+                    commands: vec![ast::Command::Add {
+                        event: ast::Event {
+                            name: "MoneyDeposited".to_string(),
+                            values: vec![ast::AttributeValue {
+                                name: "amount".to_string(),
+                                value: ast::Value::Int(100),
+                            }],
+                        },
+                        stream: "account".to_string(),
+                        stream_id: "123".to_string(),
+                        expected_version: Some(7),
+                    }],
+                },
+            ),
+        ];
         for (name, input, expected) in test_cases {
             let ast = match parse(input) {
                 Ok(a) => a,
@@ -501,11 +1327,17 @@ mod parser_test {
                             alias: "".to_string(),
                             projection: ast::Expression::Attribute {
                                 stream: "account".to_string(),
+                                event: None,
                                 attribute: "user_id".to_string(),
                             },
                         }],
-                        predicates: vec![],
+                        predicate: None,
+                        group_by: vec![],
+                        as_of: None,
+                        order_by: vec![],
                         limit: Some(ast::Limit(10)),
+                        offset: None,
+                        after: None,
                     }],
                 },
             ),
@@ -525,19 +1357,28 @@ mod parser_test {
                                 function: ast::Function::Sum,
                                 argument: Box::new(ast::Expression::Attribute {
                                     stream: "account".to_string(),
+                                    event: None,
                                     attribute: "amount".to_string(),
                                 }),
                             },
                         }],
-                        predicates: vec![ast::Predicate::BinaryOperation {
-                            left: ast::Expression::Attribute {
+                        predicate: Some(ast::Expression::BinaryOperation {
+                            left: Box::new(ast::Expression::Attribute {
                                 stream: "account".to_string(),
+                                event: None,
                                 attribute: "user_id".to_string(),
-                            },
+                            }),
                             operator: ast::BinaryOperator::Equal,
-                            right: ast::Expression::Literal(ast::Value::String("123".to_string())),
-                        }],
+                            right: Box::new(ast::Expression::Literal(ast::Value::String(
+                                "123".to_string(),
+                            ))),
+                        }),
+                        group_by: vec![],
+                        as_of: None,
+                        order_by: vec![],
                         limit: None,
+                        offset: None,
+                        after: None,
                     }],
                 },
             ),
@@ -556,27 +1397,27 @@ mod parser_test {
                             ast::Projection {
                                 alias: "".to_string(),
                                 projection: ast::Expression::BinaryOperation {
-                                    left: Box::new(ast::Expression::Aggregate {
-                                        function: ast::Function::Sum,
-                                        argument: Box::new(ast::Expression::Attribute {
-                                            stream: "account".to_string(),
-                                            attribute: "amount".to_string(),
-                                        }),
-                                    }),
-                                    operator: ast::BinaryOperator::Add,
-                                    right: Box::new(ast::Expression::BinaryOperation {
+                                    left: Box::new(ast::Expression::BinaryOperation {
                                         left: Box::new(ast::Expression::Aggregate {
+                                            function: ast::Function::Sum,
+                                            argument: Box::new(ast::Expression::Attribute {
+                                                stream: "account".to_string(),
+                                                event: None,
+                                                attribute: "amount".to_string(),
+                                            }),
+                                        }),
+                                        operator: ast::BinaryOperator::Add,
+                                        right: Box::new(ast::Expression::Aggregate {
                                             function: ast::Function::Sum,
                                             argument: Box::new(ast::Expression::Attribute {
                                                 stream: "savings".to_string(),
+                                                event: None,
                                                 attribute: "loan".to_string(),
                                             }),
                                         }),
-                                        operator: ast::BinaryOperator::Add,
-                                        right: Box::new(ast::Expression::Literal(ast::Value::Int(
-                                            100,
-                                        ))),
                                     }),
+                                    operator: ast::BinaryOperator::Add,
+                                    right: Box::new(ast::Expression::Literal(ast::Value::Int(100))),
                                 },
                             },
                             ast::Projection {
@@ -585,13 +1426,134 @@ mod parser_test {
                                     function: ast::Function::Sum,
                                     argument: Box::new(ast::Expression::Attribute {
                                         stream: "account".to_string(),
+                                        event: None,
                                         attribute: "amount".to_string(),
                                     }),
                                 },
                             },
                         ],
-                        predicates: vec![],
+                        predicate: None,
+                        group_by: vec![],
+                        as_of: None,
+                        order_by: vec![],
                         limit: Some(ast::Limit(10)),
+                        offset: None,
+                        after: None,
+                    }],
+                },
+            ),
+            (
+                "find with group by",
+                "find key, sum(account.amount) group by key, account.currency limit 10;",
+                ast::Transaction {
+                    commands: vec![ast::Command::Find {
+                        projections: vec![
+                            ast::Projection {
+                                alias: "".to_string(),
+                                projection: ast::Expression::Key,
+                            },
+                            ast::Projection {
+                                alias: "".to_string(),
+                                projection: ast::Expression::Aggregate {
+                                    function: ast::Function::Sum,
+                                    argument: Box::new(ast::Expression::Attribute {
+                                        stream: "account".to_string(),
+                                        event: None,
+                                        attribute: "amount".to_string(),
+                                    }),
+                                },
+                            },
+                        ],
+                        predicate: None,
+                        group_by: vec![
+                            ast::Expression::Key,
+                            ast::Expression::Attribute {
+                                stream: "account".to_string(),
+                                event: None,
+                                attribute: "currency".to_string(),
+                            },
+                        ],
+                        as_of: None,
+                        order_by: vec![],
+                        limit: Some(ast::Limit(10)),
+                        offset: None,
+                        after: None,
+                    }],
+                },
+            ),
+            (
+                "find with order by, offset and cursor",
+                r#"find key order by account.amount desc, key limit 10 offset 20 after "abc";"#,
+                ast::Transaction {
+                    commands: vec![ast::Command::Find {
+                        projections: vec![ast::Projection {
+                            alias: "".to_string(),
+                            projection: ast::Expression::Key,
+                        }],
+                        predicate: None,
+                        group_by: vec![],
+                        as_of: None,
+                        order_by: vec![
+                            ast::Order {
+                                expression: ast::Expression::Attribute {
+                                    stream: "account".to_string(),
+                                    event: None,
+                                    attribute: "amount".to_string(),
+                                },
+                                direction: ast::Direction::Desc,
+                            },
+                            ast::Order {
+                                expression: ast::Expression::Key,
+                                direction: ast::Direction::Asc,
+                            },
+                        ],
+                        limit: Some(ast::Limit(10)),
+                        offset: Some(ast::Offset(20)),
+                        after: Some("abc".to_string()),
+                    }],
+                },
+            ),
+            (
+                "find as of a timestamp",
+                "find key as of timestamp '2026-01-31 23:59:59.999';",
+                ast::Transaction {
+                    commands: vec![ast::Command::Find {
+                        projections: vec![ast::Projection {
+                            alias: "".to_string(),
+                            projection: ast::Expression::Key,
+                        }],
+                        predicate: None,
+                        as_of: Some(ast::AsOf::Timestamp(1769903999999)),
+                        group_by: vec![],
+                        order_by: vec![],
+                        limit: None,
+                        offset: None,
+                        after: None,
+                    }],
+                },
+            ),
+            (
+                "find as of a version",
+                "find key where key = \"123\" as of version 3 group by key;",
+                ast::Transaction {
+                    commands: vec![ast::Command::Find {
+                        projections: vec![ast::Projection {
+                            alias: "".to_string(),
+                            projection: ast::Expression::Key,
+                        }],
+                        predicate: Some(ast::Expression::BinaryOperation {
+                            left: Box::new(ast::Expression::Key),
+                            operator: ast::BinaryOperator::Equal,
+                            right: Box::new(ast::Expression::Literal(ast::Value::String(
+                                "123".to_string(),
+                            ))),
+                        }),
+                        as_of: Some(ast::AsOf::Version(3)),
+                        group_by: vec![ast::Expression::Key],
+                        order_by: vec![],
+                        limit: None,
+                        offset: None,
+                        after: None,
                     }],
                 },
             ),
@@ -606,4 +1568,251 @@ mod parser_test {
             assert_eq!(expected, ast)
         }
     }
+
+    fn attribute(name: &str) -> ast::Expression {
+        ast::Expression::Attribute {
+            stream: "account".to_string(),
+            event: None,
+            attribute: name.to_string(),
+        }
+    }
+
+    fn int(v: i64) -> ast::Expression {
+        ast::Expression::Literal(ast::Value::Int(v))
+    }
+
+    fn binary(
+        left: ast::Expression,
+        operator: ast::BinaryOperator,
+        right: ast::Expression,
+    ) -> ast::Expression {
+        ast::Expression::BinaryOperation {
+            left: Box::new(left),
+            operator,
+            right: Box::new(right),
+        }
+    }
+
+    fn not(operand: ast::Expression) -> ast::Expression {
+        ast::Expression::UnaryOperation {
+            operator: ast::UnaryOperator::Not,
+            operand: Box::new(operand),
+        }
+    }
+
+    #[test]
+    fn test_parse_find_pattern() {
+        let input = r#"[:find ?owner ?amount
+            :where [?c :type "AccountCreated"] [?c :owner ?owner]
+            [(> (- ?amount ?fee) -10)]];"#;
+        let expected = ast::Transaction {
+            commands: vec![ast::Command::FindPattern {
+                variables: vec!["owner".to_string(), "amount".to_string()],
+                clauses: vec![
+                    ast::Clause::Pattern {
+                        entity: "c".to_string(),
+                        attribute: "type".to_string(),
+                        value: ast::Term::Literal(ast::Value::String("AccountCreated".to_string())),
+                    },
+                    ast::Clause::Pattern {
+                        entity: "c".to_string(),
+                        attribute: "owner".to_string(),
+                        value: ast::Term::Variable("owner".to_string()),
+                    },
+                    ast::Clause::Predicate(ast::Term::Operation {
+                        operator: ast::BinaryOperator::GreaterThan,
+                        left: Box::new(ast::Term::Operation {
+                            operator: ast::BinaryOperator::Subtract,
+                            left: Box::new(ast::Term::Variable("amount".to_string())),
+                            right: Box::new(ast::Term::Variable("fee".to_string())),
+                        }),
+                        right: Box::new(ast::Term::Literal(ast::Value::Int(-10))),
+                    }),
+                ],
+            }],
+        };
+        match parse(input) {
+            Ok(ast) => assert_eq!(expected, ast),
+            Err(e) => panic!("failed parsing: {}", e),
+        }
+
+        for (name, input) in [
+            ("missing where", "[:find ?owner [?c :owner ?owner]];"),
+            (
+                "operation as value",
+                "[:find ?a :where [?c :amount (+ ?a 1)]];",
+            ),
+            (
+                "entity is not a variable",
+                r#"[:find ?a :where ["c" :amount ?a]];"#,
+            ),
+        ] {
+            if let Ok(ast) = parse(input) {
+                panic!("test case '{}' did not fail: {:?}", name, ast)
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_expression() {
+        use ast::BinaryOperator::*;
+
+        let test_cases = vec![
+            (
+                "and binds tighter than or",
+                "account.a = 1 or account.b != 2 and account.c == 3;",
+                binary(
+                    binary(attribute("a"), Equal, int(1)),
+                    Or,
+                    binary(
+                        binary(attribute("b"), NotEqual, int(2)),
+                        And,
+                        binary(attribute("c"), Equal, int(3)),
+                    ),
+                ),
+            ),
+            (
+                "left associative",
+                "account.a < 1 and account.b > 2 and account.c >= 3;",
+                binary(
+                    binary(
+                        binary(attribute("a"), LessThan, int(1)),
+                        And,
+                        binary(attribute("b"), GreaterThan, int(2)),
+                    ),
+                    And,
+                    binary(attribute("c"), GreaterEqual, int(3)),
+                ),
+            ),
+            (
+                "parentheses and not",
+                "not (account.a <= 1 or account.b = null) and account.c = true;",
+                binary(
+                    not(binary(
+                        binary(attribute("a"), LessEqual, int(1)),
+                        Or,
+                        binary(
+                            attribute("b"),
+                            Equal,
+                            ast::Expression::Literal(ast::Value::Null),
+                        ),
+                    )),
+                    And,
+                    binary(
+                        attribute("c"),
+                        Equal,
+                        ast::Expression::Literal(ast::Value::Bool(true)),
+                    ),
+                ),
+            ),
+            (
+                "not binds looser than comparisons",
+                "not account.a = 1;",
+                not(binary(attribute("a"), Equal, int(1))),
+            ),
+            (
+                "arithmetic is left associative",
+                "account.a - account.b - 1 / 2 * -account.c % 3;",
+                binary(
+                    binary(attribute("a"), Subtract, attribute("b")),
+                    Subtract,
+                    binary(
+                        binary(
+                            binary(int(1), Divide, int(2)),
+                            Multiply,
+                            ast::Expression::UnaryOperation {
+                                operator: ast::UnaryOperator::Negate,
+                                operand: Box::new(attribute("c")),
+                            },
+                        ),
+                        Modulus,
+                        int(3),
+                    ),
+                ),
+            ),
+            (
+                "arithmetic binds tighter than comparisons",
+                "(account.a + 1) * 2 > account.b - 1;",
+                binary(
+                    binary(binary(attribute("a"), Add, int(1)), Multiply, int(2)),
+                    GreaterThan,
+                    binary(attribute("b"), Subtract, int(1)),
+                ),
+            ),
+            (
+                "functions",
+                "last(account.a) - nth(account.a, 2);",
+                binary(
+                    ast::Expression::Aggregate {
+                        function: ast::Function::Last,
+                        argument: Box::new(attribute("a")),
+                    },
+                    Subtract,
+                    ast::Expression::Aggregate {
+                        function: ast::Function::Nth(2),
+                        argument: Box::new(attribute("a")),
+                    },
+                ),
+            ),
+            (
+                "attribute of event",
+                "account.MoneyDeposited.amount;",
+                ast::Expression::Attribute {
+                    stream: "account".to_string(),
+                    event: Some("MoneyDeposited".to_string()),
+                    attribute: "amount".to_string(),
+                },
+            ),
+            (
+                "in and not in",
+                "account.a in (1, 2) or key not in (\"1\");",
+                binary(
+                    binary(
+                        attribute("a"),
+                        In,
+                        ast::Expression::List(vec![int(1), int(2)]),
+                    ),
+                    Or,
+                    binary(
+                        ast::Expression::Key,
+                        NotIn,
+                        ast::Expression::List(vec![ast::Expression::Literal(ast::Value::String(
+                            "1".to_string(),
+                        ))]),
+                    ),
+                ),
+            ),
+            (
+                "between and pseudo-attributes",
+                "timestamp between '2026-01-01' and '2026-01-02' and version > 1 or event = \"A\";",
+                binary(
+                    binary(
+                        ast::Expression::Between {
+                            expression: Box::new(ast::Expression::Timestamp),
+                            low: Box::new(int(1767225600000)),
+                            high: Box::new(int(1767312000000)),
+                        },
+                        And,
+                        binary(ast::Expression::Version, GreaterThan, int(1)),
+                    ),
+                    Or,
+                    binary(
+                        ast::Expression::Event,
+                        Equal,
+                        ast::Expression::Literal(ast::Value::String("A".to_string())),
+                    ),
+                ),
+            ),
+        ];
+
+        for (name, input, expected) in test_cases {
+            let mut tokens = tokenize(input);
+            let predicate = match parse_expression(&mut tokens) {
+                Ok(p) => p,
+                Err(e) => panic!("test case '{}' failed parsing: {}", name, e),
+            };
+            assert_eq!(expected, predicate, "test case '{}'", name);
+            assert_eq!(Token::EOF, tokens.next().unwrap(), "test case '{}'", name);
+        }
+    }
 }
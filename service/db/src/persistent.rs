@@ -0,0 +1,704 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tokio::sync::{self, Notify};
+
+use crate::db::{blocking, DBError, DB};
+use crate::event::{Attribute, Event, Value};
+use crate::planner::{self, Catalog, Subscription};
+use crate::storage::EventStore;
+use crate::subscription::Subscriber;
+
+// A persistent subscription is kept by the server under its name, and the connections that
+// join it are the members of its consumer group. Each event is sent to one member, which
+// acks it when it is done with it or nacks it when it failed. A nacked event is sent again,
+// to any member, until it has failed park after times. It is then parked, and left until
+// the parked events are replayed. The events a member has not acked or nacked when it
+// leaves are sent to the other members.
+//
+// The state of a subscription is kept as events in the system stream $subscriptions, with
+// the name of the subscription as key;
+//
+//   Checkpointed(position)  the events before the position are done with
+//   Parked(position)        the event is parked
+//   Replayed(position)      the parked event is sent again
+//   Acked(position)         the replayed event is done with
+//
+// The checkpoint is moved as events are done with, but not for every event, so events that
+// were done with after the last checkpoint are sent again after a restart. How many times
+// an event has failed is not kept, it starts over after a restart.
+pub const STATE_STREAM: &str = "$subscriptions";
+
+// a checkpoint is written once this many events are done with since the last one, or once
+// all events read so far are done with
+const CHECKPOINT_AFTER: u64 = 64;
+
+// the state of a persistent subscription, as kept in its system stream
+#[derive(Debug, Default, PartialEq)]
+pub struct State {
+    // position of the first event that is not done with
+    pub checkpoint: u64,
+    pub parked: BTreeSet<u64>,
+    // parked events that are sent again
+    pub replayed: BTreeSet<u64>,
+}
+
+impl State {
+    // the state of a subscription that starts at from_position after its events
+    pub fn load(from_position: u64, events: &[Event]) -> Result<Self, PersistentError> {
+        let mut state = State {
+            checkpoint: from_position,
+            ..Default::default()
+        };
+        for event in events {
+            state.apply(Change::from_event(event)?);
+        }
+        Ok(state)
+    }
+
+    fn apply(&mut self, change: Change) {
+        match change {
+            Change::Checkpointed(position) => self.checkpoint = position,
+            Change::Parked(position) => {
+                self.replayed.remove(&position);
+                self.parked.insert(position);
+            }
+            Change::Replayed(position) => {
+                self.parked.remove(&position);
+                self.replayed.insert(position);
+            }
+            Change::Acked(position) => {
+                self.replayed.remove(&position);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Change {
+    Checkpointed(u64),
+    Parked(u64),
+    Replayed(u64),
+    Acked(u64),
+}
+
+impl Change {
+    fn to_event(self, subscription: &str, version: u64, timestamp: u128) -> Event {
+        let (name, position) = match self {
+            Change::Checkpointed(position) => ("Checkpointed", position),
+            Change::Parked(position) => ("Parked", position),
+            Change::Replayed(position) => ("Replayed", position),
+            Change::Acked(position) => ("Acked", position),
+        };
+        Event::new(
+            STATE_STREAM.to_string(),
+            subscription.to_string(),
+            name.to_string(),
+            version,
+            timestamp,
+            vec![Attribute {
+                name: "position".to_string(),
+                value: Value::Int(position as i64),
+            }],
+        )
+    }
+
+    fn from_event(event: &Event) -> Result<Self, PersistentError> {
+        let position = match event.attributes.iter().find(|a| a.name == "position") {
+            Some(Attribute {
+                value: Value::Int(position),
+                ..
+            }) => *position as u64,
+            _ => {
+                return Err(PersistentError::new(&format!(
+                    "'{}' at version {} has no position",
+                    event.event, event.version
+                )))
+            }
+        };
+        match event.event.as_str() {
+            "Checkpointed" => Ok(Change::Checkpointed(position)),
+            "Parked" => Ok(Change::Parked(position)),
+            "Replayed" => Ok(Change::Replayed(position)),
+            "Acked" => Ok(Change::Acked(position)),
+            name => Err(PersistentError::new(&format!(
+                "unknown subscription event '{}'",
+                name
+            ))),
+        }
+    }
+}
+
+// the groups of the persistent subscriptions that have been joined or replayed since the
+// server started
+pub struct Groups<S: EventStore> {
+    db: Arc<DB<S>>,
+    groups: sync::Mutex<HashMap<String, Arc<Group<S>>>>,
+}
+
+impl<S: EventStore + 'static> Groups<S> {
+    pub fn new(db: Arc<DB<S>>) -> Self {
+        Groups {
+            db,
+            groups: sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    // the group of the subscription, which is loaded from its state the first time
+    pub async fn get(&self, name: &str) -> Result<Arc<Group<S>>, PersistentError> {
+        let mut groups = self.groups.lock().await;
+        if let Some(group) = groups.get(name) {
+            return Ok(group.clone());
+        }
+        let group = Arc::new(Group::load(self.db.clone(), name).await?);
+        groups.insert(name.to_string(), group.clone());
+        Ok(group)
+    }
+}
+
+pub struct Group<S: EventStore> {
+    db: Arc<DB<S>>,
+    name: String,
+    park_after: u64,
+    // reads the events after the checkpoint, for one member at a time
+    subscriber: sync::Mutex<Subscriber<S>>,
+    deliveries: Mutex<Deliveries>,
+    // notified when events are queued
+    queued: Notify,
+    // the state is changed by one member at a time, so the changes are added to the stream
+    // key of the subscription in the order they are made
+    changing: sync::Mutex<()>,
+    next_member: AtomicU64,
+}
+
+struct Deliveries {
+    // events to send, nacked and replayed events first
+    queue: VecDeque<Event>,
+    // events sent and not yet acked or nacked, with the member they were sent to
+    in_flight: BTreeMap<u64, (u64, Event)>,
+    // times the events have been nacked
+    failures: HashMap<u64, u64>,
+    parked: BTreeSet<u64>,
+    replayed: BTreeSet<u64>,
+    // position after the last event read by the subscriber
+    read_position: u64,
+    // the last checkpoint written
+    checkpoint: u64,
+}
+
+impl Deliveries {
+    // position of the first event read by the subscriber that is not done with. Replayed
+    // events are left out as they are kept apart from the checkpoint
+    fn checkpoint(&self) -> u64 {
+        self.queue
+            .iter()
+            .map(|event| event.position)
+            .chain(self.in_flight.keys().copied())
+            .filter(|position| !self.replayed.contains(position))
+            .min()
+            .unwrap_or(self.read_position)
+    }
+
+    // the checkpoint to write, if enough events are done with since the last one
+    fn next_checkpoint(&mut self) -> Option<Change> {
+        let checkpoint = self.checkpoint();
+        let is_idle = self.queue.is_empty() && self.in_flight.is_empty();
+        if checkpoint >= self.checkpoint + CHECKPOINT_AFTER
+            || (is_idle && checkpoint > self.checkpoint)
+        {
+            self.checkpoint = checkpoint;
+            return Some(Change::Checkpointed(checkpoint));
+        }
+        None
+    }
+
+    // takes the events at the positions out of flight, in the order of their positions, if
+    // all of them were sent to the member
+    fn settle(&mut self, member: u64, positions: &[u64]) -> Result<Vec<Event>, PersistentError> {
+        let positions = positions.iter().copied().collect::<BTreeSet<u64>>();
+        if let Some(position) = positions
+            .iter()
+            .find(|p| self.in_flight.get(p).is_none_or(|(m, _)| *m != member))
+        {
+            return Err(PersistentError::new(&format!(
+                "the event at position {} is not waiting for an ack or nack from this member",
+                position
+            )));
+        }
+        Ok(positions
+            .iter()
+            .filter_map(|p| self.in_flight.remove(p))
+            .map(|(_, event)| event)
+            .collect())
+    }
+
+    // the events are sent before the events already queued
+    fn requeue(&mut self, events: Vec<Event>) {
+        for event in events.into_iter().rev() {
+            self.queue.push_front(event);
+        }
+    }
+}
+
+impl<S: EventStore + 'static> Group<S> {
+    async fn load(db: Arc<DB<S>>, name: &str) -> Result<Self, PersistentError> {
+        let details = db
+            .subscription(name)?
+            .ok_or_else(|| PersistentError::new(&format!("unknown subscription '{}'", name)))?;
+        let state_name = name.to_string();
+        let (state, replayed) = blocking(&db, move |db| -> Result<_, PersistentError> {
+            let state = db.subscription_state(&state_name)?;
+            let replayed = db.read_positions(&state.replayed)?;
+            Ok((state, replayed))
+        })
+        .await?;
+
+        let subscription = Subscription {
+            stream: match details.stream_name.as_str() {
+                "$all" => None,
+                stream => Some(stream.to_string()),
+            },
+            from_position: state.checkpoint,
+            predicates: vec![],
+        };
+        Ok(Group {
+            subscriber: sync::Mutex::new(Subscriber::new(db.clone(), subscription)),
+            db,
+            name: name.to_string(),
+            park_after: details.park_after,
+            deliveries: Mutex::new(Deliveries {
+                queue: replayed.into(),
+                in_flight: BTreeMap::new(),
+                failures: HashMap::new(),
+                parked: state.parked,
+                replayed: state.replayed,
+                read_position: state.checkpoint,
+                checkpoint: state.checkpoint,
+            }),
+            queued: Notify::new(),
+            changing: sync::Mutex::new(()),
+            next_member: AtomicU64::new(1),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn join(self: &Arc<Self>) -> Member<S> {
+        Member {
+            group: self.clone(),
+            id: self.next_member.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    // all parked events are sent again, as if they had not failed, and the number of them
+    // is returned
+    pub async fn replay_parked(&self) -> Result<usize, PersistentError> {
+        let _changing = self.changing.lock().await;
+        let parked = self.deliveries()?.parked.clone();
+        let events = blocking(&self.db, move |db| db.read_positions(&parked)).await?;
+
+        let n_replayed = events.len();
+        let mut changes = vec![];
+        {
+            let mut deliveries = self.deliveries()?;
+            for event in events.iter() {
+                deliveries.parked.remove(&event.position);
+                deliveries.replayed.insert(event.position);
+                deliveries.failures.remove(&event.position);
+                changes.push(Change::Replayed(event.position));
+            }
+            deliveries.requeue(events);
+        }
+        self.queued.notify_waiters();
+        self.change(changes).await?;
+        Ok(n_replayed)
+    }
+
+    fn deliveries(&self) -> Result<MutexGuard<'_, Deliveries>, PersistentError> {
+        self.deliveries
+            .lock()
+            .map_err(|_| PersistentError::new("failed to lock deliveries"))
+    }
+
+    // the next queued event, which is then in flight to the member
+    fn take(&self, member: u64) -> Result<Option<Event>, PersistentError> {
+        let mut deliveries = self.deliveries()?;
+        let Some(event) = deliveries.queue.pop_front() else {
+            return Ok(None);
+        };
+        deliveries
+            .in_flight
+            .insert(event.position, (member, event.clone()));
+        Ok(Some(event))
+    }
+
+    // queues the next events of the subscriber, unless another member did while waiting
+    // for it. Parked and replayed events are already done with or queued, they are read
+    // again when the checkpoint is behind them
+    async fn read(&self) -> Result<(), PersistentError> {
+        let mut subscriber = self.subscriber.lock().await;
+        if !self.deliveries()?.queue.is_empty() {
+            return Ok(());
+        }
+        let events = subscriber
+            .next()
+            .await
+            .map_err(|e| PersistentError::new(&e.to_string()))?;
+
+        let mut deliveries = self.deliveries()?;
+        if let Some(event) = events.last() {
+            deliveries.read_position = event.position + 1;
+        }
+        for event in events {
+            if !deliveries.parked.contains(&event.position)
+                && !deliveries.replayed.contains(&event.position)
+            {
+                deliveries.queue.push_back(event);
+            }
+        }
+        drop(deliveries);
+        self.queued.notify_waiters();
+        Ok(())
+    }
+
+    // the changes are added one at a time after the last version of the stream key, which
+    // only changes while changing is locked
+    async fn change(&self, changes: Vec<Change>) -> Result<(), PersistentError> {
+        if changes.is_empty() {
+            return Ok(());
+        }
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| PersistentError::new(&format!("failed to get timestamp: {}", e)))?
+            .as_millis();
+        let name = self.name.clone();
+        blocking(&self.db, move |db| -> Result<_, PersistentError> {
+            let mut version = db.last_version(STATE_STREAM, &name)?;
+            for change in changes {
+                version += 1;
+                db.add_event(change.to_event(&name, version, timestamp))?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    // the events in flight to the member are sent to the other members
+    fn leave(&self, member: u64) -> Result<(), PersistentError> {
+        let mut deliveries = self.deliveries()?;
+        let positions = deliveries
+            .in_flight
+            .iter()
+            .filter(|(_, (m, _))| *m == member)
+            .map(|(position, _)| *position)
+            .collect::<Vec<u64>>();
+        let events = deliveries.settle(member, &positions)?;
+        deliveries.requeue(events);
+        drop(deliveries);
+        self.queued.notify_waiters();
+        Ok(())
+    }
+}
+
+// a member of the consumer group of a subscription, it leaves the group when dropped
+pub struct Member<S: EventStore + 'static> {
+    group: Arc<Group<S>>,
+    id: u64,
+}
+
+impl<S: EventStore + 'static> Member<S> {
+    pub fn group(&self) -> &Group<S> {
+        &self.group
+    }
+
+    // the next event for the member, it waits until there is one. An event is only taken
+    // out of the queue once it is returned, so a call that is cancelled does not lose any
+    pub async fn next(&self) -> Result<Event, PersistentError> {
+        loop {
+            // created before the queue is checked, so events queued after are not missed
+            let queued = self.group.queued.notified();
+            if let Some(event) = self.group.take(self.id)? {
+                return Ok(event);
+            }
+            tokio::select! {
+                _ = queued => {}
+                result = self.group.read() => result?,
+            }
+        }
+    }
+
+    // number of events sent to the member that it has not acked or nacked
+    pub fn in_flight(&self) -> Result<usize, PersistentError> {
+        Ok(self
+            .group
+            .deliveries()?
+            .in_flight
+            .values()
+            .filter(|(member, _)| *member == self.id)
+            .count())
+    }
+
+    pub async fn ack(&self, positions: &[u64]) -> Result<(), PersistentError> {
+        let _changing = self.group.changing.lock().await;
+        let changes = {
+            let mut deliveries = self.group.deliveries()?;
+            let mut changes = vec![];
+            for event in deliveries.settle(self.id, positions)? {
+                deliveries.failures.remove(&event.position);
+                if deliveries.replayed.remove(&event.position) {
+                    changes.push(Change::Acked(event.position));
+                }
+            }
+            changes.extend(deliveries.next_checkpoint());
+            changes
+        };
+        self.group.change(changes).await
+    }
+
+    // the events are sent again, or parked if they have failed park after times
+    pub async fn nack(&self, positions: &[u64]) -> Result<(), PersistentError> {
+        let _changing = self.group.changing.lock().await;
+        let changes = {
+            let mut deliveries = self.group.deliveries()?;
+            let mut changes = vec![];
+            let mut retries = vec![];
+            for event in deliveries.settle(self.id, positions)? {
+                let failures = deliveries.failures.entry(event.position).or_insert(0);
+                *failures += 1;
+                if *failures < self.group.park_after {
+                    retries.push(event);
+                    continue;
+                }
+                deliveries.failures.remove(&event.position);
+                deliveries.replayed.remove(&event.position);
+                deliveries.parked.insert(event.position);
+                changes.push(Change::Parked(event.position));
+            }
+            deliveries.requeue(retries);
+            changes.extend(deliveries.next_checkpoint());
+            changes
+        };
+        self.group.queued.notify_waiters();
+        self.group.change(changes).await
+    }
+}
+
+impl<S: EventStore + 'static> Drop for Member<S> {
+    fn drop(&mut self) {
+        if let Err(e) = self.group.leave(self.id) {
+            eprintln!("failed to leave subscription {}: {}", self.group.name, e);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PersistentError {
+    message: String,
+}
+
+impl PersistentError {
+    fn new(message: &str) -> Self {
+        PersistentError {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for PersistentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for PersistentError {}
+
+impl From<DBError> for PersistentError {
+    fn from(e: DBError) -> PersistentError {
+        PersistentError::new(&e.to_string())
+    }
+}
+
+impl From<planner::PlanError> for PersistentError {
+    fn from(e: planner::PlanError) -> PersistentError {
+        PersistentError::new(&e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod persistent_test {
+    use super::*;
+    use crate::storage::memory::MemoryStore;
+    use std::time::Duration;
+
+    fn setup(park_after: u64) -> Arc<DB> {
+        DB::with_schema(&[
+            "create stream account;",
+            "create event MoneyDeposited(amount int) on account;",
+            "create stream user;",
+            "create event UserCreated(name string) on user;",
+            &format!(
+                "create subscription workers to account park after {};",
+                park_after
+            ),
+        ])
+    }
+
+    fn deposit(db: &DB, key: &str) {
+        db.run(&format!(
+            r#"add MoneyDeposited(amount=1) to account(id="{}");"#,
+            key
+        ));
+    }
+
+    async fn next(member: &Member<MemoryStore>) -> Event {
+        tokio::time::timeout(Duration::from_secs(5), member.next())
+            .await
+            .expect("no event was received")
+            .unwrap()
+    }
+
+    async fn assert_no_event(member: &Member<MemoryStore>) {
+        if let Ok(event) = tokio::time::timeout(Duration::from_millis(50), member.next()).await {
+            panic!("got unexpected event {:?}", event);
+        }
+    }
+
+    // key and version of the event
+    fn id(event: &Event) -> (&str, u64) {
+        (&event.key, event.version)
+    }
+
+    #[tokio::test]
+    async fn test_load_balance_and_checkpoint() {
+        let db = setup(10);
+        deposit(&db, "1");
+        db.run(r#"add UserCreated(name="axel") to user(id="1");"#);
+        deposit(&db, "2");
+        deposit(&db, "1");
+
+        let group = Groups::new(db.clone()).get("workers").await.unwrap();
+        let a = group.join();
+        let b = group.join();
+
+        // the members take turns, and the events of other streams are not sent
+        let first = next(&a).await;
+        let second = next(&b).await;
+        let third = next(&a).await;
+        assert_eq!(
+            vec![("1", 1), ("2", 1), ("1", 2)],
+            vec![id(&first), id(&second), id(&third)]
+        );
+        assert_eq!(2, a.in_flight().unwrap());
+        assert_no_event(&b).await;
+
+        // events can only be acked by the member they were sent to
+        assert!(a.ack(&[second.position]).await.is_err());
+        a.ack(&[first.position, third.position]).await.unwrap();
+        assert_eq!(0, a.in_flight().unwrap());
+        assert_eq!(1, db.subscription_state("workers").unwrap().checkpoint);
+
+        // the checkpoint is written once all events read are done with
+        b.ack(&[second.position]).await.unwrap();
+        assert_eq!(
+            third.position + 1,
+            db.subscription_state("workers").unwrap().checkpoint
+        );
+
+        // an event that is not acked before a restart is sent again, the events before
+        // the checkpoint are not
+        deposit(&db, "3");
+        let fourth = next(&a).await;
+        assert_eq!(("3", 1), id(&fourth));
+        drop((a, b, group));
+
+        let group = Groups::new(db.clone()).get("workers").await.unwrap();
+        let c = group.join();
+        assert_eq!(fourth, next(&c).await);
+        assert_no_event(&c).await;
+    }
+
+    #[tokio::test]
+    async fn test_nack_park_and_replay() {
+        let db = setup(2);
+        deposit(&db, "1");
+        deposit(&db, "2");
+
+        let groups = Groups::new(db.clone());
+        let member = groups.get("workers").await.unwrap().join();
+
+        // a nacked event is sent again until it has failed park after times
+        let failing = next(&member).await;
+        assert_eq!(("1", 1), id(&failing));
+        member.nack(&[failing.position]).await.unwrap();
+        assert_eq!(failing, next(&member).await);
+        member.nack(&[failing.position]).await.unwrap();
+
+        let event = next(&member).await;
+        assert_eq!(("2", 1), id(&event));
+        member.ack(&[event.position]).await.unwrap();
+        assert_no_event(&member).await;
+
+        let state = db.subscription_state("workers").unwrap();
+        assert_eq!(BTreeSet::from([failing.position]), state.parked);
+        assert_eq!(event.position + 1, state.checkpoint);
+        let parked = db.run("read parked workers;").unwrap();
+        assert_eq!(1, parked.rows.len());
+        assert_eq!(Value::Int(failing.position as i64), parked.rows[0][0]);
+
+        // a replayed event is sent again and stays parked until it is done with, also
+        // after a restart
+        let group = groups.get("workers").await.unwrap();
+        assert_eq!(1, group.replay_parked().await.unwrap());
+        assert_eq!(failing, next(&member).await);
+        let state = db.subscription_state("workers").unwrap();
+        assert_eq!(BTreeSet::from([failing.position]), state.replayed);
+        assert!(state.parked.is_empty());
+
+        let restarted = Groups::new(db.clone()).get("workers").await.unwrap().join();
+        assert_eq!(failing, next(&restarted).await);
+        assert_no_event(&restarted).await;
+
+        member.ack(&[failing.position]).await.unwrap();
+        assert_eq!(
+            State {
+                checkpoint: event.position + 1,
+                parked: BTreeSet::new(),
+                replayed: BTreeSet::new(),
+            },
+            db.subscription_state("workers").unwrap()
+        );
+        assert!(db.run("read parked workers;").unwrap().rows.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_leave() {
+        let db = setup(10);
+        for key in ["1", "2", "3"] {
+            deposit(&db, key);
+        }
+
+        let group = Groups::new(db.clone()).get("workers").await.unwrap();
+        let a = group.join();
+        let b = group.join();
+        let first = next(&a).await;
+        let second = next(&a).await;
+        let third = next(&b).await;
+
+        // the events in flight to a member that leaves are sent to the others, before the
+        // events that are queued
+        drop(a);
+        deposit(&db, "4");
+        assert_eq!(first, next(&b).await);
+        assert_eq!(second, next(&b).await);
+        assert_eq!(("4", 1), id(&next(&b).await));
+        b.ack(&[first.position, second.position, third.position])
+            .await
+            .unwrap();
+    }
+}
@@ -1,11 +1,61 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::ops::Bound;
+use std::time::{SystemTime, UNIX_EPOCH};
 use std::{error::Error, fmt};
 
-use crate::{ast::ast, event};
+use crate::db::AttributeDetails;
+use crate::{ast::ast, cursor, event};
 
-pub fn plan(transaction: &ast::Transaction) -> Result<ExecutionPlan, PlanError> {
-    dbg!("OKEFOKEOKEKFOEKF");
+// state of the database the planner needs to know about to build a plan
+pub trait Catalog {
+    // version of the last event added to the stream key, 0 if there are no events
+    fn last_version(&self, stream_name: &str, key: &str) -> Result<u64, PlanError>;
 
+    // attributes declared for the event by name, None if the event is not in the schema
+    fn event_attributes(
+        &self,
+        stream_name: &str,
+        event_name: &str,
+    ) -> Result<Option<HashMap<String, AttributeDetails>>, PlanError>;
+
+    fn stream_exists(&self, stream_name: &str) -> Result<bool, PlanError>;
+
+    // names of the streams in the schema, sorted
+    fn streams(&self) -> Result<Vec<String>, PlanError>;
+
+    // names of the events declared for the stream, sorted
+    fn stream_events(&self, stream_name: &str) -> Result<Vec<String>, PlanError>;
+
+    // number of events in the stream, used to estimate how many rows it gives
+    fn stream_size(&self, stream_name: &str) -> Result<u64, PlanError>;
+
+    fn has_index(
+        &self,
+        stream_name: &str,
+        event_name: &str,
+        attribute_name: &str,
+    ) -> Result<bool, PlanError>;
+
+    // whether there is a persistent subscription by the name
+    fn subscription_exists(&self, name: &str) -> Result<bool, PlanError>;
+}
+
+// events a member of a persistent subscription can have without an ack or nack, unless it
+// joins with a limit
+const DEFAULT_JOIN_LIMIT: usize = 10;
+
+// times an event of a persistent subscription can fail before it is parked, unless the
+// subscription is created with park after
+const DEFAULT_PARK_AFTER: u64 = 10;
+
+pub fn plan(
+    transaction: &ast::Transaction,
+    catalog: &impl Catalog,
+) -> Result<ExecutionPlan, PlanError> {
     let mut operations = vec![];
+    // versions of the stream keys added to earlier in the transaction
+    let mut versions: HashMap<(&str, &str), u64> = HashMap::new();
     for cmd in transaction.commands.iter() {
         match cmd {
             ast::Command::Create { entity } => match entity {
@@ -26,6 +76,7 @@ pub fn plan(transaction: &ast::Transaction) -> Result<ExecutionPlan, PlanError>
 
                     operations.push(Operation::CheckEventExists {
                         name: name.to_string(),
+                        stream_name: stream_name.to_string(),
                     });
 
                     operations.push(Operation::CreateEvent {
@@ -33,12 +84,78 @@ pub fn plan(transaction: &ast::Transaction) -> Result<ExecutionPlan, PlanError>
                         stream_name: stream_name.to_string(),
                     });
 
-                    operations.extend(attributes.iter().map(|a| Operation::CreateAttribute {
-                        name: a.name.clone(),
-                        event_name: name.clone(),
+                    for attribute in attributes.iter() {
+                        if DataType::from_str(&attribute.data_type).is_none() {
+                            return Err(PlanError::new(&format!(
+                                "attribute '{}' has unsupported type '{}'",
+                                attribute.name, attribute.data_type
+                            )));
+                        }
+                        operations.push(Operation::CreateAttribute {
+                            name: attribute.name.clone(),
+                            event_name: name.clone(),
+                            stream_name: stream_name.clone(),
+                            data_type: attribute.data_type.clone(),
+                            required: attribute.required,
+                        });
+                    }
+                }
+                ast::Entity::Index {
+                    stream_name,
+                    event_name,
+                    attribute_name,
+                } => {
+                    check_attribute(
+                        &Expression::Attribute {
+                            stream: stream_name.clone(),
+                            event: Some(event_name.clone()),
+                            name: attribute_name.clone(),
+                        },
+                        catalog,
+                    )?;
+                    if catalog.has_index(stream_name, event_name, attribute_name)? {
+                        return Err(PlanError::new(&format!(
+                            "index on {}.{}.{} already exists",
+                            stream_name, event_name, attribute_name
+                        )));
+                    }
+                    operations.push(Operation::CreateIndex {
+                        stream_name: stream_name.clone(),
+                        event_name: event_name.clone(),
+                        attribute_name: attribute_name.clone(),
+                    });
+                }
+                ast::Entity::Subscription {
+                    name,
+                    stream_name,
+                    from_position,
+                    park_after,
+                } => {
+                    if catalog.subscription_exists(name)? {
+                        return Err(PlanError::new(&format!(
+                            "subscription '{}' already exists",
+                            name
+                        )));
+                    }
+                    if stream_name != "$all" && !catalog.stream_exists(stream_name)? {
+                        return Err(PlanError::new(&format!("unknown stream '{}'", stream_name)));
+                    }
+                    let park_after = match park_after {
+                        Some(n) if *n < 1 => {
+                            return Err(PlanError::new(&format!(
+                                "park after must be at least 1, got {}",
+                                n
+                            )))
+                        }
+                        Some(n) => *n as u64,
+                        None => DEFAULT_PARK_AFTER,
+                    };
+                    operations.push(Operation::CreateSubscription {
+                        name: name.clone(),
                         stream_name: stream_name.clone(),
-                        data_type: a.data_type.clone(),
-                    }));
+                        from_position: to_position(from_position)?,
+                        park_after,
+                    });
                 }
                 _ => return Err(PlanError::new("unreconizable entity")),
             },
@@ -46,30 +163,1133 @@ pub fn plan(transaction: &ast::Transaction) -> Result<ExecutionPlan, PlanError>
                 event,
                 stream,
                 stream_id,
-            } => {}
+                expected_version,
+            } => {
+                let attributes =
+                    catalog
+                        .event_attributes(stream, &event.name)?
+                        .ok_or_else(|| {
+                            PlanError::new(&format!(
+                                "unknown event '{}' on stream '{}'",
+                                event.name, stream
+                            ))
+                        })?;
+                validate_event(event, &attributes)?;
+
+                // the version is decided here and checked again when the event is
+                // added, so concurrent writes to the same stream key will conflict
+                let expected_version = match (expected_version, versions.get(&(stream, stream_id)))
+                {
+                    (Some(version), _) => *version,
+                    (None, Some(version)) => *version,
+                    (None, None) => catalog.last_version(stream, stream_id)?,
+                };
+                let version = expected_version + 1;
+                versions.insert((stream, stream_id), version);
+                operations.push(Operation::AddEvent {
+                    event: build_event(event, &attributes, stream, stream_id, version)?,
+                });
+            }
+            ast::Command::Find {
+                projections,
+                predicate,
+                as_of,
+                group_by,
+                order_by,
+                limit,
+                offset,
+                after,
+            } => {
+                let mut query = plan_find(projections, predicate, group_by, order_by, catalog)?;
+                query.as_of = match as_of {
+                    Some(ast::AsOf::Timestamp(t)) => {
+                        Some(AsOf::Timestamp(u128::try_from(*t).map_err(|_| {
+                            PlanError::new(&format!("as of timestamp can not be negative: {}", t))
+                        })?))
+                    }
+                    Some(ast::AsOf::Version(n)) => {
+                        Some(AsOf::Version(u64::try_from(*n).map_err(|_| {
+                            PlanError::new(&format!("as of version can not be negative: {}", n))
+                        })?))
+                    }
+                    None => None,
+                };
+                // events after the timestamp are left out by reading only the events up to
+                // it through the time index
+                if let Some(AsOf::Timestamp(t)) = query.as_of {
+                    let (from, to) = query.time_range.unwrap_or((0, t));
+                    query.time_range = Some((from, to.min(t)));
+                }
+                query.limit = match limit {
+                    Some(ast::Limit(n)) => Some(usize::try_from(*n).map_err(|_| {
+                        PlanError::new(&format!("limit can not be negative: {}", n))
+                    })?),
+                    None => None,
+                };
+                query.offset = match offset {
+                    Some(ast::Offset(n)) => usize::try_from(*n).map_err(|_| {
+                        PlanError::new(&format!("offset can not be negative: {}", n))
+                    })?,
+                    None => 0,
+                };
+                // the cursor is checked against the query so it has the values the rows
+                // of the query are ordered by
+                if let Some(cursor) = after {
+                    query.after = Some(
+                        cursor::decode(&query.fingerprint(), cursor)
+                            .map_err(|e| PlanError::new(&e.to_string()))?,
+                    );
+                }
+                operations.push(Operation::Find { query });
+            }
+            ast::Command::Snapshot { path } => {
+                operations.push(Operation::Snapshot { path: path.clone() });
+            }
+            ast::Command::ReadAll {
+                from_position,
+                limit,
+            } => {
+                let from_position = to_position(from_position)?;
+                let limit = match limit {
+                    Some(ast::Limit(n)) => Some(usize::try_from(*n).map_err(|_| {
+                        PlanError::new(&format!("limit can not be negative: {}", n))
+                    })?),
+                    None => None,
+                };
+                operations.push(Operation::ReadAll {
+                    from_position,
+                    limit,
+                });
+            }
+            ast::Command::Subscribe {
+                stream,
+                from_position,
+                predicate,
+            } => {
+                operations.push(Operation::Subscribe {
+                    subscription: plan_subscription(stream, from_position, predicate, catalog)?,
+                });
+            }
+            ast::Command::Join {
+                subscription,
+                limit,
+            } => {
+                check_subscription_exists(subscription, catalog)?;
+                let limit = match limit {
+                    Some(ast::Limit(n)) if *n < 1 => {
+                        return Err(PlanError::new(&format!(
+                            "limit must be at least 1, got {}",
+                            n
+                        )))
+                    }
+                    Some(ast::Limit(n)) => *n as usize,
+                    None => DEFAULT_JOIN_LIMIT,
+                };
+                operations.push(Operation::Join {
+                    subscription: subscription.clone(),
+                    limit,
+                });
+            }
+            ast::Command::Ack { positions } => {
+                operations.push(Operation::Ack {
+                    positions: to_positions(positions)?,
+                });
+            }
+            ast::Command::Nack { positions } => {
+                operations.push(Operation::Nack {
+                    positions: to_positions(positions)?,
+                });
+            }
+            ast::Command::ReadParked { subscription } => {
+                check_subscription_exists(subscription, catalog)?;
+                operations.push(Operation::ReadParked {
+                    subscription: subscription.clone(),
+                });
+            }
+            ast::Command::ReplayParked { subscription } => {
+                check_subscription_exists(subscription, catalog)?;
+                operations.push(Operation::ReplayParked {
+                    subscription: subscription.clone(),
+                });
+            }
+            ast::Command::FindPattern { variables, clauses } => {
+                operations.push(Operation::FindPattern {
+                    query: plan_find_pattern(variables, clauses, catalog)?,
+                });
+            }
             _ => return Err(PlanError::new("cannot handle that command")),
         }
     }
 
-    let plan = ExecutionPlan { operations };
+    Ok(ExecutionPlan { operations })
+}
+
+fn plan_find(
+    projections: &[ast::Projection],
+    predicate: &Option<ast::Expression>,
+    group_by: &[ast::Expression],
+    order_by: &[ast::Order],
+    catalog: &impl Catalog,
+) -> Result<Query, PlanError> {
+    let projections = projections
+        .iter()
+        .map(|p| {
+            let expression = to_expression(&p.projection)?;
+            let name = match p.alias.as_str() {
+                "" => expression.to_string(),
+                alias => alias.to_string(),
+            };
+            Ok(Projection { name, expression })
+        })
+        .collect::<Result<Vec<Projection>, PlanError>>()?;
+    if projections.is_empty() {
+        return Err(PlanError::new("find needs at least one projection"));
+    }
+
+    // the predicate is split on and, so each part can be checked on its own
+    let mut predicates = vec![];
+    if let Some(predicate) = predicate {
+        to_expression(predicate)?.split_and(&mut predicates);
+    }
+    if let Some(predicate) = predicates.iter().find(|p| p.has_aggregate()) {
+        return Err(PlanError::new(&format!(
+            "aggregates can not be used in where, got '{}'",
+            predicate
+        )));
+    }
+
+    let group_by = group_by
+        .iter()
+        .map(to_expression)
+        .collect::<Result<Vec<Expression>, PlanError>>()?;
+    if let Some(expression) = group_by.iter().find(|e| e.has_aggregate()) {
+        return Err(PlanError::new(&format!(
+            "aggregates can not be used in group by, got '{}'",
+            expression
+        )));
+    }
+
+    let order_by = order_by
+        .iter()
+        .map(|order| {
+            Ok(Order {
+                expression: to_expression(&order.expression)?,
+                direction: order.direction,
+            })
+        })
+        .collect::<Result<Vec<Order>, PlanError>>()?;
+    let is_grouped =
+        !group_by.is_empty() || projections.iter().any(|p| p.expression.has_aggregate());
+    if !is_grouped {
+        if let Some(order) = order_by.iter().find(|o| o.expression.has_aggregate()) {
+            return Err(PlanError::new(&format!(
+                "aggregates can only be used in order by when the query is grouped, got '{}'",
+                order.expression
+            )));
+        }
+    }
+
+    // when the query is grouped there is a single row per group, and when any projection
+    // is an aggregate without group by a single row in total. So attributes can only be
+    // used inside aggregates or as one of the expressions that is grouped by
+    if !group_by.is_empty() {
+        if let Some(expression) = projections
+            .iter()
+            .map(|p| &p.expression)
+            .chain(order_by.iter().map(|o| &o.expression))
+            .find_map(|e| e.ungrouped(&group_by))
+        {
+            return Err(PlanError::new(&format!(
+                "'{}' must be used in an aggregate or be in group by",
+                expression
+            )));
+        }
+    } else if projections.iter().any(|p| p.expression.has_aggregate()) {
+        if let Some(attribute) = projections
+            .iter()
+            .find_map(|p| p.expression.ungrouped(&group_by))
+        {
+            return Err(PlanError::new(&format!(
+                "attribute '{}' must be used in an aggregate as other projections are aggregates",
+                attribute
+            )));
+        }
+    }
+
+    let mut streams = vec![];
+    let mut attributes = vec![];
+    for expression in projections
+        .iter()
+        .map(|p| &p.expression)
+        .chain(predicates.iter())
+        .chain(group_by.iter())
+        .chain(order_by.iter().map(|o| &o.expression))
+    {
+        expression.streams(&mut streams);
+        expression.attributes(&mut attributes);
+    }
+    // key, timestamp, version and event are of the events of all streams when no stream
+    // is named
+    if streams.is_empty()
+        && projections
+            .iter()
+            .map(|p| &p.expression)
+            .chain(predicates.iter())
+            .any(|e| e.pseudo_attribute().is_some())
+    {
+        streams = catalog.streams()?;
+    }
+    streams.sort();
+    streams.dedup();
+    for stream in streams.iter() {
+        if !catalog.stream_exists(stream)? {
+            return Err(PlanError::new(&format!("unknown stream '{}'", stream)));
+        }
+    }
+    for attribute in attributes {
+        check_attribute(attribute, catalog)?;
+    }
+
+    let join = plan_join(&streams, &mut predicates, catalog)?;
+    if join.is_some() {
+        if let Some(expression) = projections
+            .iter()
+            .map(|p| &p.expression)
+            .chain(predicates.iter())
+            .chain(group_by.iter())
+            .chain(order_by.iter().map(|o| &o.expression))
+            .find_map(|e| e.pseudo_attribute())
+        {
+            return Err(PlanError::new(&format!(
+                "{} can not be used in a join, as the events are of different streams",
+                expression
+            )));
+        }
+    }
+    let time_range = time_range(&predicates);
+    let mut indexable = predicates.iter().collect::<Vec<&Expression>>();
+    if let Some(join) = &join {
+        indexable.extend(join.build.predicates.iter());
+        indexable.extend(join.probe.predicates.iter());
+    }
+    let index = index_scan(&indexable, catalog)?;
+    let event_types = match join {
+        Some(_) => None,
+        None => event_types(&streams, &predicates),
+    };
+
+    Ok(Query {
+        streams,
+        predicates,
+        projections,
+        group_by,
+        order_by,
+        join: join.map(Box::new),
+        time_range,
+        index: index.map(Box::new),
+        event_types,
+        as_of: None,
+        limit: None,
+        offset: 0,
+        after: None,
+    })
+}
+
+// the comparison with its sides swapped, a < b is b > a
+fn flip(operator: &ast::BinaryOperator) -> ast::BinaryOperator {
+    match operator {
+        ast::BinaryOperator::LessThan => ast::BinaryOperator::GreaterThan,
+        ast::BinaryOperator::GreaterThan => ast::BinaryOperator::LessThan,
+        ast::BinaryOperator::LessEqual => ast::BinaryOperator::GreaterEqual,
+        ast::BinaryOperator::GreaterEqual => ast::BinaryOperator::LessEqual,
+        operator => *operator,
+    }
+}
+
+// An index is used for the predicates comparing an indexed attribute to a literal with =,
+// <, <=, > or >=, the range of values is narrowed by each predicate on the same attribute.
+// An index with an equality is preferred as it is likely to give the fewest events,
+// otherwise the first index is used. The predicates are still checked for the events
+// read through the index
+fn index_scan(
+    predicates: &[&Expression],
+    catalog: &impl Catalog,
+) -> Result<Option<IndexScan>, PlanError> {
+    let mut scans: Vec<IndexScan> = vec![];
+    for predicate in predicates {
+        let Expression::Binary {
+            left,
+            operator,
+            right,
+        } = predicate
+        else {
+            continue;
+        };
+        let (attribute, operator, value) = match (left.as_ref(), right.as_ref()) {
+            (attribute, Expression::Literal(value)) => (attribute, *operator, value),
+            (Expression::Literal(value), attribute) => (attribute, flip(operator), value),
+            _ => continue,
+        };
+        let Expression::Attribute {
+            stream,
+            event: Some(event),
+            name,
+        } = attribute
+        else {
+            continue;
+        };
+        if *value == event::Value::Null || !catalog.has_index(stream, event, name)? {
+            continue;
+        }
+
+        let value = value.clone();
+        let (from, to) = match operator {
+            ast::BinaryOperator::Equal => (Bound::Included(value.clone()), Bound::Included(value)),
+            ast::BinaryOperator::GreaterThan => (Bound::Excluded(value), Bound::Unbounded),
+            ast::BinaryOperator::GreaterEqual => (Bound::Included(value), Bound::Unbounded),
+            ast::BinaryOperator::LessThan => (Bound::Unbounded, Bound::Excluded(value)),
+            ast::BinaryOperator::LessEqual => (Bound::Unbounded, Bound::Included(value)),
+            _ => continue,
+        };
+        match scans
+            .iter_mut()
+            .find(|s| s.stream == *stream && s.event == *event && s.attribute == *name)
+        {
+            Some(scan) => {
+                scan.from = narrower(scan.from.clone(), from, Ordering::Greater);
+                scan.to = narrower(scan.to.clone(), to, Ordering::Less);
+            }
+            None => scans.push(IndexScan {
+                stream: stream.clone(),
+                event: event.clone(),
+                attribute: name.clone(),
+                from,
+                to,
+            }),
+        }
+    }
+
+    let equality = scans.iter().position(|scan| match (&scan.from, &scan.to) {
+        (Bound::Included(from), Bound::Included(to)) => from.total_cmp(to) == Ordering::Equal,
+        _ => false,
+    });
+    Ok(match equality {
+        Some(i) => Some(scans.swap_remove(i)),
+        None => scans.into_iter().next(),
+    })
+}
 
-    dbg!(&plan);
+// the bound of a and b that allows the fewest values, the bound with the value in the
+// narrower direction or the excluded bound if the values are the same
+fn narrower(
+    a: Bound<event::Value>,
+    b: Bound<event::Value>,
+    narrower: Ordering,
+) -> Bound<event::Value> {
+    let (x, y) = match (&a, &b) {
+        (Bound::Unbounded, _) => return b,
+        (_, Bound::Unbounded) => return a,
+        (Bound::Included(x) | Bound::Excluded(x), Bound::Included(y) | Bound::Excluded(y)) => {
+            (x, y)
+        }
+    };
+    match x.total_cmp(y) {
+        Ordering::Equal if matches!(a, Bound::Excluded(_)) => a,
+        Ordering::Equal => b,
+        ordering if ordering == narrower => a,
+        _ => b,
+    }
+}
 
-    Ok(plan)
+// events are read from the first position unless another is given
+fn to_position(position: &Option<i64>) -> Result<u64, PlanError> {
+    match position {
+        Some(n) => u64::try_from(*n)
+            .map_err(|_| PlanError::new(&format!("position can not be negative: {}", n))),
+        None => Ok(1),
+    }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+fn to_positions(positions: &[i64]) -> Result<Vec<u64>, PlanError> {
+    positions
+        .iter()
+        .map(|position| to_position(&Some(*position)))
+        .collect()
+}
+
+fn check_subscription_exists(name: &str, catalog: &impl Catalog) -> Result<(), PlanError> {
+    match catalog.subscription_exists(name)? {
+        true => Ok(()),
+        false => Err(PlanError::new(&format!("unknown subscription '{}'", name))),
+    }
+}
+
+// $all subscribes to the events of all streams. The predicate can use the attributes of
+// the stream subscribed to, or of any stream for $all, and key, version, event and
+// timestamp
+fn plan_subscription(
+    stream: &str,
+    from_position: &Option<i64>,
+    predicate: &Option<ast::Expression>,
+    catalog: &impl Catalog,
+) -> Result<Subscription, PlanError> {
+    let stream = match stream {
+        "$all" => None,
+        stream if catalog.stream_exists(stream)? => Some(stream.to_string()),
+        stream => return Err(PlanError::new(&format!("unknown stream '{}'", stream))),
+    };
+
+    let mut predicates = vec![];
+    if let Some(predicate) = predicate {
+        to_expression(predicate)?.split_and(&mut predicates);
+    }
+    if let Some(predicate) = predicates.iter().find(|p| p.has_aggregate()) {
+        return Err(PlanError::new(&format!(
+            "aggregates can not be used in where, got '{}'",
+            predicate
+        )));
+    }
+
+    let mut streams = vec![];
+    let mut attributes = vec![];
+    for predicate in predicates.iter() {
+        predicate.streams(&mut streams);
+        predicate.attributes(&mut attributes);
+    }
+    for name in streams {
+        match &stream {
+            Some(stream) if *stream != name => {
+                return Err(PlanError::new(&format!(
+                    "subscription to '{}' can not use the attributes of stream '{}'",
+                    stream, name
+                )))
+            }
+            Some(_) => {}
+            None if catalog.stream_exists(&name)? => {}
+            None => return Err(PlanError::new(&format!("unknown stream '{}'", name))),
+        }
+    }
+    for attribute in attributes {
+        check_attribute(attribute, catalog)?;
+    }
+
+    Ok(Subscription {
+        stream,
+        from_position: to_position(from_position)?,
+        predicates,
+    })
+}
+
+// the timestamps (inclusive) that the predicates comparing timestamp to an int allow, if
+// there are any. The range is empty if from is after to
+fn time_range(predicates: &[Expression]) -> Option<(u128, u128)> {
+    let mut range: Option<(i128, i128)> = None;
+    for predicate in predicates {
+        let Expression::Binary {
+            left,
+            operator,
+            right,
+        } = predicate
+        else {
+            continue;
+        };
+        // a literal on the left is the same as on the right with the comparison flipped
+        let (operator, value) = match (left.as_ref(), right.as_ref()) {
+            (Expression::Timestamp, Expression::Literal(event::Value::Int(v))) => (*operator, *v),
+            (Expression::Literal(event::Value::Int(v)), Expression::Timestamp) => {
+                (flip(operator), *v)
+            }
+            _ => continue,
+        };
+        let value = value as i128;
+        let (from, to) = match operator {
+            ast::BinaryOperator::Equal => (value, value),
+            ast::BinaryOperator::GreaterThan => (value + 1, i128::MAX),
+            ast::BinaryOperator::GreaterEqual => (value, i128::MAX),
+            ast::BinaryOperator::LessThan => (0, value - 1),
+            ast::BinaryOperator::LessEqual => (0, value),
+            _ => continue,
+        };
+        let (current_from, current_to) = range.unwrap_or((0, i128::MAX));
+        range = Some((current_from.max(from), current_to.min(to)));
+    }
+
+    let (from, to) = range?;
+    match to < from.max(0) {
+        true => Some((1, 0)),
+        false => Some((from.max(0) as u128, to as u128)),
+    }
+}
+
+// the event types, as (stream, event), that the predicates allow if any of them only
+// allows some. A predicate comparing event to names, or an attribute of an event type to a
+// value, is only true for events of those types, as the attribute is null for other events
+// and a comparison with null is null. Sorted so the events are read in the same order
+fn event_types(streams: &[String], predicates: &[Expression]) -> Option<Vec<(String, String)>> {
+    let mut types: Option<Vec<(String, String)>> = None;
+    for predicate in predicates {
+        // whether the predicate is only true if the expression is one of the values
+        let (expression, values, is_equality) = match predicate {
+            Expression::Binary {
+                left,
+                operator,
+                right,
+            } if matches!(
+                operator,
+                ast::BinaryOperator::Equal
+                    | ast::BinaryOperator::NotEqual
+                    | ast::BinaryOperator::LessThan
+                    | ast::BinaryOperator::GreaterThan
+                    | ast::BinaryOperator::LessEqual
+                    | ast::BinaryOperator::GreaterEqual
+            ) =>
+            {
+                let is_equality = *operator == ast::BinaryOperator::Equal;
+                match (left.as_ref(), right.as_ref()) {
+                    (expression, Expression::Literal(value))
+                    | (Expression::Literal(value), expression) => {
+                        (expression, vec![value], is_equality)
+                    }
+                    _ => continue,
+                }
+            }
+            Expression::In {
+                expression,
+                list,
+                negated: false,
+            } => {
+                let mut values = vec![];
+                for value in list {
+                    match value {
+                        Expression::Literal(value) => values.push(value),
+                        _ => break,
+                    }
+                }
+                if values.len() != list.len() {
+                    continue;
+                }
+                (expression.as_ref(), values, true)
+            }
+            _ => continue,
+        };
+
+        let allowed = match expression {
+            Expression::Event if is_equality => {
+                let mut allowed = vec![];
+                for value in values {
+                    let event::Value::String(name) = value else {
+                        continue;
+                    };
+                    allowed.extend(streams.iter().map(|s| (s.clone(), name.clone())));
+                }
+                allowed
+            }
+            Expression::Attribute {
+                stream,
+                event: Some(event),
+                ..
+            } if values.iter().any(|v| **v != event::Value::Null) => {
+                vec![(stream.clone(), event.clone())]
+            }
+            _ => continue,
+        };
+        types = Some(match types {
+            Some(types) => types.into_iter().filter(|t| allowed.contains(t)).collect(),
+            None => allowed,
+        });
+    }
+
+    let mut types = types?;
+    types.sort();
+    types.dedup();
+    Some(types)
+}
+
+// A query on two streams with a predicate that an attribute of one stream is equal to an
+// attribute of the other is a join of their events. The stream estimated to have the
+// fewest events is the build side of the hash join, and predicates on a single stream
+// are moved to its side so they filter its events before they are joined
+fn plan_join(
+    streams: &[String],
+    predicates: &mut Vec<Expression>,
+    catalog: &impl Catalog,
+) -> Result<Option<Join>, PlanError> {
+    let Some(position) = predicates
+        .iter()
+        .position(|p| p.join_attributes().is_some())
+    else {
+        return Ok(None);
+    };
+    if streams.len() != 2 {
+        return Err(PlanError::new(&format!(
+            "joins are only supported between two streams, got {}",
+            streams.len()
+        )));
+    }
+
+    let predicate = predicates.remove(position);
+    let (left, right) = predicate.join_attributes().unwrap();
+    let mut sides = [left, right].map(|attribute| {
+        let mut streams = vec![];
+        attribute.streams(&mut streams);
+        JoinSide {
+            stream: streams.remove(0),
+            attribute: attribute.clone(),
+            predicates: vec![],
+        }
+    });
+    predicates.retain(|predicate| {
+        let mut streams = vec![];
+        predicate.streams(&mut streams);
+        streams.dedup();
+        match sides
+            .iter_mut()
+            .find(|side| streams == [side.stream.clone()])
+        {
+            Some(side) => {
+                side.predicates.push(predicate.clone());
+                false
+            }
+            None => true,
+        }
+    });
+
+    let [left, right] = sides;
+    let (build, probe) =
+        match catalog.stream_size(&left.stream)? <= catalog.stream_size(&right.stream)? {
+            true => (left, right),
+            false => (right, left),
+        };
+    Ok(Some(Join { build, probe }))
+}
+
+// an attribute must be declared for its event, or for any event of the stream if no
+// event is given
+fn check_attribute(attribute: &Expression, catalog: &impl Catalog) -> Result<(), PlanError> {
+    let Expression::Attribute {
+        stream,
+        event,
+        name,
+    } = attribute
+    else {
+        return Ok(());
+    };
+
+    let events = match event {
+        Some(event) => vec![event.clone()],
+        None => catalog.stream_events(stream)?,
+    };
+    for event_name in events {
+        let attributes = catalog
+            .event_attributes(stream, &event_name)?
+            .ok_or_else(|| {
+                PlanError::new(&format!(
+                    "unknown event '{}' on stream '{}'",
+                    event_name, stream
+                ))
+            })?;
+        if attributes.contains_key(name) {
+            return Ok(());
+        }
+    }
+
+    match event {
+        Some(event) => Err(PlanError::new(&format!(
+            "unknown attribute '{}' on event '{}'",
+            name, event
+        ))),
+        None => Err(PlanError::new(&format!(
+            "unknown attribute '{}' on stream '{}'",
+            name, stream
+        ))),
+    }
+}
+
+// the patterns are grouped by their entity variable, which is an event. Every variable
+// used in :find or a predicate must be the value of a pattern
+fn plan_find_pattern(
+    variables: &[String],
+    clauses: &[ast::Clause],
+    catalog: &impl Catalog,
+) -> Result<PatternQuery, PlanError> {
+    if variables.is_empty() {
+        return Err(PlanError::new(":find needs at least one variable"));
+    }
+
+    let mut entities: Vec<EventVariable> = vec![];
+    let mut predicates = vec![];
+    for clause in clauses {
+        match clause {
+            ast::Clause::Pattern {
+                entity,
+                attribute,
+                value,
+            } => {
+                let pattern = Pattern {
+                    field: Field::from_name(attribute),
+                    value: to_term(value),
+                };
+                match entities.iter_mut().find(|e| &e.variable == entity) {
+                    Some(entity) => entity.patterns.push(pattern),
+                    None => entities.push(EventVariable {
+                        variable: entity.clone(),
+                        streams: vec![],
+                        patterns: vec![pattern],
+                    }),
+                }
+            }
+            ast::Clause::Predicate(term) => predicates.push(to_term(term)),
+        }
+    }
+    if entities.is_empty() {
+        return Err(PlanError::new(":where needs at least one pattern"));
+    }
+
+    let mut bound = vec![];
+    for pattern in entities.iter().flat_map(|e| e.patterns.iter()) {
+        if let Term::Variable(name) = &pattern.value {
+            if entities.iter().any(|e| &e.variable == name) {
+                return Err(PlanError::new(&format!(
+                    "'?{}' is an event and can not be the value of a pattern",
+                    name
+                )));
+            }
+            bound.push(name.as_str());
+        }
+    }
+    if let Some(name) = variables.iter().find(|v| !bound.contains(&v.as_str())) {
+        return Err(PlanError::new(&format!(
+            "variable '?{}' of :find is not bound by a pattern",
+            name
+        )));
+    }
+    for predicate in predicates.iter() {
+        let mut used = vec![];
+        predicate.variables(&mut used);
+        if let Some(name) = used.iter().find(|v| !bound.contains(v)) {
+            return Err(PlanError::new(&format!(
+                "variable '?{}' of predicate '{}' is not bound by a pattern",
+                name, predicate
+            )));
+        }
+    }
+
+    let streams = catalog.streams()?;
+    for entity in entities.iter_mut() {
+        entity.streams = entity_streams(entity, &streams, catalog)?;
+    }
+
+    Ok(PatternQuery {
+        variables: variables.to_vec(),
+        entities,
+        predicates,
+    })
+}
+
+// the streams that have an event that can match all patterns of the event variable, so
+// only those are scanned. Streams, event types and attributes that are not in the schema
+// at all are rejected
+fn entity_streams(
+    entity: &EventVariable,
+    streams: &[String],
+    catalog: &impl Catalog,
+) -> Result<Vec<String>, PlanError> {
+    let literal = |field: &Field| {
+        entity.patterns.iter().find_map(|p| match &p.value {
+            Term::Literal(event::Value::String(v)) if p.field == *field => Some(v.as_str()),
+            _ => None,
+        })
+    };
+    let stream = literal(&Field::Stream);
+    let event = literal(&Field::Event);
+    let attributes = entity
+        .patterns
+        .iter()
+        .filter_map(|p| match &p.field {
+            Field::Attribute(name) => Some(name.as_str()),
+            _ => None,
+        })
+        .collect::<Vec<&str>>();
+
+    let mut known_events = HashSet::new();
+    let mut known_attributes = HashSet::new();
+    let mut matching = vec![];
+    for stream_name in streams {
+        for event_name in catalog.stream_events(stream_name)? {
+            let declared = catalog
+                .event_attributes(stream_name, &event_name)?
+                .unwrap_or_default();
+            known_attributes.extend(declared.keys().cloned());
+            let is_match = stream.is_none_or(|s| s == stream_name)
+                && event.is_none_or(|e| e == event_name)
+                && attributes.iter().all(|a| declared.contains_key(*a));
+            known_events.insert(event_name);
+            if is_match && !matching.contains(stream_name) {
+                matching.push(stream_name.clone());
+            }
+        }
+    }
+
+    if let Some(stream) = stream.filter(|s| !streams.iter().any(|name| name == s)) {
+        return Err(PlanError::new(&format!("unknown stream '{}'", stream)));
+    }
+    if let Some(event) = event.filter(|e| !known_events.contains(*e)) {
+        return Err(PlanError::new(&format!("unknown event '{}'", event)));
+    }
+    if let Some(attribute) = attributes.iter().find(|a| !known_attributes.contains(**a)) {
+        return Err(PlanError::new(&format!(
+            "unknown attribute ':{}'",
+            attribute
+        )));
+    }
+    Ok(matching)
+}
+
+fn to_term(term: &ast::Term) -> Term {
+    match term {
+        ast::Term::Variable(name) => Term::Variable(name.clone()),
+        ast::Term::Literal(value) => Term::Literal(to_value(value, None)),
+        ast::Term::Operation {
+            operator,
+            left,
+            right,
+        } => Term::Binary {
+            left: Box::new(to_term(left)),
+            operator: *operator,
+            right: Box::new(to_term(right)),
+        },
+    }
+}
+
+fn to_expression(expression: &ast::Expression) -> Result<Expression, PlanError> {
+    let expression = match expression {
+        ast::Expression::Literal(value) => Expression::Literal(to_value(value, None)),
+        ast::Expression::Attribute {
+            stream,
+            event,
+            attribute,
+        } => Expression::Attribute {
+            stream: stream.clone(),
+            event: event.clone(),
+            name: attribute.clone(),
+        },
+        ast::Expression::Key => Expression::Key,
+        ast::Expression::Timestamp => Expression::Timestamp,
+        ast::Expression::Version => Expression::Version,
+        ast::Expression::Event => Expression::Event,
+        ast::Expression::Between {
+            expression,
+            low,
+            high,
+        } => {
+            let expression = to_expression(expression)?;
+            let bound = |operator, bound: &ast::Expression| {
+                Ok::<Expression, PlanError>(Expression::Binary {
+                    left: Box::new(expression.clone()),
+                    operator,
+                    right: Box::new(to_expression(bound)?),
+                })
+            };
+            Expression::Binary {
+                left: Box::new(bound(ast::BinaryOperator::GreaterEqual, low)?),
+                operator: ast::BinaryOperator::And,
+                right: Box::new(bound(ast::BinaryOperator::LessEqual, high)?),
+            }
+        }
+        ast::Expression::Aggregate { function, argument } => {
+            let argument = to_expression(argument)?;
+            if argument.has_aggregate() {
+                return Err(PlanError::new(&format!(
+                    "aggregates can not be nested, got '{}'",
+                    argument
+                )));
+            }
+            if let ast::Function::Nth(n) = function {
+                if *n < 1 {
+                    return Err(PlanError::new(&format!(
+                        "the position of nth starts at 1, got {}",
+                        n
+                    )));
+                }
+            }
+            Expression::Aggregate {
+                function: *function,
+                argument: Box::new(argument),
+            }
+        }
+        ast::Expression::UnaryOperation {
+            operator: ast::UnaryOperator::Negate,
+            operand,
+        } => Expression::Negate(Box::new(to_expression(operand)?)),
+        ast::Expression::UnaryOperation {
+            operator: ast::UnaryOperator::Not,
+            operand,
+        } => Expression::Not(Box::new(to_expression(operand)?)),
+        ast::Expression::BinaryOperation {
+            left,
+            operator: operator @ (ast::BinaryOperator::In | ast::BinaryOperator::NotIn),
+            right,
+        } => {
+            let ast::Expression::List(list) = right.as_ref() else {
+                return Err(PlanError::new(&format!(
+                    "{} must be followed by a list",
+                    operator_symbol(operator)
+                )));
+            };
+            Expression::In {
+                expression: Box::new(to_expression(left)?),
+                list: list
+                    .iter()
+                    .map(to_expression)
+                    .collect::<Result<Vec<Expression>, PlanError>>()?,
+                negated: *operator == ast::BinaryOperator::NotIn,
+            }
+        }
+        ast::Expression::BinaryOperation {
+            left,
+            operator,
+            right,
+        } => Expression::Binary {
+            left: Box::new(to_expression(left)?),
+            operator: *operator,
+            right: Box::new(to_expression(right)?),
+        },
+        ast::Expression::List(_) => {
+            return Err(PlanError::new("lists can only be used with in and not in"))
+        }
+    };
+    Ok(expression)
+}
+
+// checks the values of the event against the attributes declared for it in the schema
+fn validate_event(
+    event: &ast::Event,
+    attributes: &HashMap<String, AttributeDetails>,
+) -> Result<(), PlanError> {
+    let mut given = HashSet::new();
+    for value in event.values.iter() {
+        let details = attributes.get(&value.name).ok_or_else(|| {
+            PlanError::new(&format!(
+                "unknown attribute '{}' on event '{}'",
+                value.name, event.name
+            ))
+        })?;
+        if !given.insert(value.name.as_str()) {
+            return Err(PlanError::new(&format!(
+                "attribute '{}' is given more than once",
+                value.name
+            )));
+        }
+
+        if value.value == ast::Value::Null {
+            if details.required {
+                return Err(PlanError::new(&format!(
+                    "attribute '{}' is required and can not be null",
+                    value.name
+                )));
+            }
+            continue;
+        }
+        let accepted = DataType::from_str(&details.attribute_type)
+            .is_some_and(|data_type| data_type.accepts(&value.value));
+        if !accepted {
+            return Err(PlanError::new(&format!(
+                "attribute '{}' is of type {} but got {}",
+                value.name,
+                details.attribute_type,
+                value_type_name(&value.value)
+            )));
+        }
+    }
+
+    // sorted so the same attribute is reported every time
+    let mut missing = attributes
+        .iter()
+        .filter(|(name, details)| details.required && !given.contains(name.as_str()))
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<&str>>();
+    missing.sort();
+    if let Some(name) = missing.first() {
+        return Err(PlanError::new(&format!(
+            "missing required attribute '{}' on event '{}'",
+            name, event.name
+        )));
+    }
+
+    Ok(())
+}
+
+fn build_event(
+    event: &ast::Event,
+    attributes: &HashMap<String, AttributeDetails>,
+    stream: &str,
+    stream_id: &str,
+    version: u64,
+) -> Result<event::Event, PlanError> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| PlanError::new(&format!("failed to get timestamp: {}", e)))?
+        .as_millis();
+
+    let attributes = event
+        .values
+        .iter()
+        .map(|v| event::Attribute {
+            name: v.name.clone(),
+            value: to_value(
+                &v.value,
+                attributes
+                    .get(&v.name)
+                    .and_then(|details| DataType::from_str(&details.attribute_type)),
+            ),
+        })
+        .collect();
+
+    Ok(event::Event::new(
+        stream.to_string(),
+        stream_id.to_string(),
+        event.name.clone(),
+        version,
+        timestamp,
+        attributes,
+    ))
+}
+
+fn value_type_name(value: &ast::Value) -> &'static str {
+    match value {
+        ast::Value::Null => "null",
+        ast::Value::Bool(_) => "bool",
+        ast::Value::String(_) => "string",
+        ast::Value::Int(_) => "int",
+        ast::Value::Float(_) => "float",
+    }
+}
+
+// int values given for float attributes are stored as floats
+fn to_value(value: &ast::Value, data_type: Option<DataType>) -> event::Value {
+    match value {
+        ast::Value::Null => event::Value::Null,
+        ast::Value::Bool(v) => event::Value::Bool(*v),
+        ast::Value::Int(v) if data_type == Some(DataType::Float) => event::Value::Float(*v as f64),
+        ast::Value::Int(v) => event::Value::Int(*v),
+        ast::Value::Float(v) => event::Value::Float(*v),
+        ast::Value::String(v) => event::Value::String(v.clone()),
+    }
+}
+
+#[derive(Debug, PartialEq)]
 pub struct ExecutionPlan {
     pub operations: Vec<Operation>,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq)]
 pub enum Operation {
     CheckStreamExists {
         name: String,
     },
     CheckEventExists {
         name: String,
+        stream_name: String,
     },
     CreateStream {
         name: String,
@@ -83,11 +1303,617 @@ pub enum Operation {
         event_name: String,
         stream_name: String,
         data_type: String,
+        required: bool,
+    },
+    CreateIndex {
+        stream_name: String,
+        event_name: String,
+        attribute_name: String,
+    },
+    // stream_name is $all for all streams
+    CreateSubscription {
+        name: String,
+        stream_name: String,
+        from_position: u64,
+        park_after: u64,
     },
 
     AddEvent {
         event: event::Event,
     },
+
+    Snapshot {
+        path: String,
+    },
+
+    // the events of all streams from the position, in the order they were added
+    ReadAll {
+        from_position: u64,
+        limit: Option<usize>,
+    },
+
+    Subscribe {
+        subscription: Subscription,
+    },
+
+    // the connection becomes a member of the persistent subscription, which is sent at
+    // most limit events at a time without an ack or nack
+    Join {
+        subscription: String,
+        limit: usize,
+    },
+    // acks and nacks are sent by members for the events they were sent, by position
+    Ack {
+        positions: Vec<u64>,
+    },
+    Nack {
+        positions: Vec<u64>,
+    },
+    ReadParked {
+        subscription: String,
+    },
+    ReplayParked {
+        subscription: String,
+    },
+
+    Find {
+        query: Query,
+    },
+
+    FindPattern {
+        query: PatternQuery,
+    },
+}
+
+// events of the streams are scanned, filtered by the predicates and then projected. The
+// query returns a single row if any projection is an aggregate, otherwise a row per event
+#[derive(Debug, PartialEq)]
+pub struct Query {
+    pub streams: Vec<String>,
+    // all predicates must be true for an event to match
+    pub predicates: Vec<Expression>,
+    pub projections: Vec<Projection>,
+    // events with equal values for the expressions are grouped into a single row
+    pub group_by: Vec<Expression>,
+    pub order_by: Vec<Order>,
+    // rows are pairs of events of the two streams if the query is a join
+    pub join: Option<Box<Join>>,
+    // the timestamps (inclusive) of the events the predicates allow, which are read
+    // through the time index of the streams rather than scanning all events
+    pub time_range: Option<(u128, u128)>,
+    // the events of the stream of the index are read through it rather than scanning all
+    // events of the stream
+    pub index: Option<Box<IndexScan>>,
+    // only the events of the event types, as (stream, event), are read through the event
+    // type index of the streams rather than scanning all events
+    pub event_types: Option<Vec<(String, String)>>,
+    // only the events at or before the point are used, for each stream key
+    pub as_of: Option<AsOf>,
+    pub limit: Option<usize>,
+    pub offset: usize,
+    // the values the last row of the previous page is ordered by, see cursor
+    pub after: Option<Vec<event::Value>>,
+}
+
+impl Query {
+    // a row is computed per group of events rather than per event. Aggregate queries
+    // without group by have a single group with all events
+    pub fn is_grouped(&self) -> bool {
+        !self.group_by.is_empty()
+            || self
+                .projections
+                .iter()
+                .any(|p| p.expression.has_aggregate())
+    }
+
+    // identifies which rows the query returns and how they are ordered, so a cursor of
+    // one query can not be used with another
+    pub fn fingerprint(&self) -> String {
+        fn join(values: &[impl fmt::Display]) -> String {
+            values
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        }
+        let on = match &self.join {
+            Some(join) => format!(" join on {}", join),
+            None => "".to_string(),
+        };
+        let as_of = match &self.as_of {
+            Some(as_of) => format!(" as of {}", as_of),
+            None => "".to_string(),
+        };
+        format!(
+            "{}{} where {}{} group by {} order by {}",
+            join(&self.streams),
+            on,
+            join(&self.predicates),
+            as_of,
+            join(&self.group_by),
+            join(&self.order_by)
+        )
+    }
+}
+
+// the events of the stream, or of all streams if there is none, from the position that
+// match the predicates. First the events already added are sent and then the events as
+// they are added, in the order of their positions
+#[derive(Debug, PartialEq)]
+pub struct Subscription {
+    pub stream: Option<String>,
+    pub from_position: u64,
+    // all predicates must be true for an event to be sent
+    pub predicates: Vec<Expression>,
+}
+
+// the events with a value of the indexed attribute in the range
+#[derive(Debug, PartialEq)]
+pub struct IndexScan {
+    pub stream: String,
+    pub event: String,
+    pub attribute: String,
+    pub from: Bound<event::Value>,
+    pub to: Bound<event::Value>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AsOf {
+    // milliseconds since the epoch
+    Timestamp(u128),
+    Version(u64),
+}
+
+impl fmt::Display for AsOf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AsOf::Timestamp(t) => write!(f, "timestamp {}", t),
+            AsOf::Version(n) => write!(f, "version {}", n),
+        }
+    }
+}
+
+// events of the build side are put in a hash table by the value of their attribute, which
+// the events of the probe side are looked up in
+#[derive(Debug, PartialEq)]
+pub struct Join {
+    pub build: JoinSide,
+    pub probe: JoinSide,
+}
+
+// the sides are written in order of their stream, so the join is the same whichever
+// side is built
+impl fmt::Display for Join {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (a, b) = match self.build.stream <= self.probe.stream {
+            true => (&self.build, &self.probe),
+            false => (&self.probe, &self.build),
+        };
+        write!(f, "{} = {}", a.attribute, b.attribute)?;
+        for predicate in a.predicates.iter().chain(b.predicates.iter()) {
+            write!(f, " and {}", predicate)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct JoinSide {
+    pub stream: String,
+    // the attribute the events are joined on
+    pub attribute: Expression,
+    // predicates on only this stream, which its events must match to be joined
+    pub predicates: Vec<Expression>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Order {
+    pub expression: Expression,
+    pub direction: ast::Direction,
+}
+
+impl fmt::Display for Order {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.direction {
+            ast::Direction::Asc => write!(f, "{} asc", self.expression),
+            ast::Direction::Desc => write!(f, "{} desc", self.expression),
+        }
+    }
+}
+
+// events are matched to the patterns of each event variable and joined on the variables
+// they share. A row is returned for each distinct set of values of the variables of :find
+#[derive(Debug, PartialEq)]
+pub struct PatternQuery {
+    pub variables: Vec<String>,
+    pub entities: Vec<EventVariable>,
+    // all predicates must be true for a row to be returned
+    pub predicates: Vec<Term>,
+}
+
+// an entity variable of a pattern query, which is bound to events
+#[derive(Debug, PartialEq)]
+pub struct EventVariable {
+    pub variable: String,
+    // the streams with events that can match the patterns
+    pub streams: Vec<String>,
+    pub patterns: Vec<Pattern>,
+}
+
+// [?entity :field value], the value is a variable or a literal
+#[derive(Debug, PartialEq)]
+pub struct Pattern {
+    pub field: Field,
+    pub value: Term,
+}
+
+// what a pattern matches on an event. Besides the attributes of the event these are its
+// stream, key, type, version and timestamp
+#[derive(Debug, PartialEq)]
+pub enum Field {
+    Stream,
+    Key,
+    Event,
+    Version,
+    Timestamp,
+    Attribute(String),
+}
+
+impl Field {
+    fn from_name(name: &str) -> Self {
+        match name {
+            "stream" => Field::Stream,
+            "key" => Field::Key,
+            "type" => Field::Event,
+            "version" => Field::Version,
+            "timestamp" => Field::Timestamp,
+            name => Field::Attribute(name.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    Variable(String),
+    Literal(event::Value),
+    Binary {
+        left: Box<Term>,
+        operator: ast::BinaryOperator,
+        right: Box<Term>,
+    },
+}
+
+impl Term {
+    // all variables in the term
+    pub fn variables<'a>(&'a self, variables: &mut Vec<&'a str>) {
+        match self {
+            Term::Variable(name) => variables.push(name),
+            Term::Literal(_) => {}
+            Term::Binary { left, right, .. } => {
+                left.variables(variables);
+                right.variables(variables);
+            }
+        }
+    }
+}
+
+impl fmt::Display for Term {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Term::Variable(name) => write!(f, "?{}", name),
+            Term::Literal(event::Value::String(v)) => write!(f, "{:?}", v),
+            Term::Literal(value) => write!(f, "{}", value),
+            Term::Binary {
+                left,
+                operator,
+                right,
+            } => write!(f, "({} {} {})", operator_symbol(operator), left, right),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Projection {
+    pub name: String,
+    pub expression: Expression,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    Literal(event::Value),
+    // the attribute of events in the stream, or only of events of the event type if it
+    // is given. Null for events that do not have it
+    Attribute {
+        stream: String,
+        event: Option<String>,
+        name: String,
+    },
+    // the stream key of the event
+    Key,
+    Timestamp,
+    Version,
+    // the name of the event
+    Event,
+    Aggregate {
+        function: ast::Function,
+        argument: Box<Expression>,
+    },
+    Negate(Box<Expression>),
+    Not(Box<Expression>),
+    Binary {
+        left: Box<Expression>,
+        operator: ast::BinaryOperator,
+        right: Box<Expression>,
+    },
+    // in, or not in if negated
+    In {
+        expression: Box<Expression>,
+        list: Vec<Expression>,
+        negated: bool,
+    },
+}
+
+impl Expression {
+    pub fn has_aggregate(&self) -> bool {
+        match self {
+            Expression::Literal(_) | Expression::Attribute { .. } => false,
+            Expression::Key | Expression::Timestamp | Expression::Version | Expression::Event => {
+                false
+            }
+            Expression::Aggregate { .. } => true,
+            Expression::Negate(operand) | Expression::Not(operand) => operand.has_aggregate(),
+            Expression::Binary { left, right, .. } => left.has_aggregate() || right.has_aggregate(),
+            Expression::In {
+                expression, list, ..
+            } => expression.has_aggregate() || list.iter().any(|e| e.has_aggregate()),
+        }
+    }
+
+    // the first of key, timestamp, version and event in the expression
+    pub fn pseudo_attribute(&self) -> Option<&Expression> {
+        match self {
+            Expression::Literal(_) | Expression::Attribute { .. } => None,
+            Expression::Key | Expression::Timestamp | Expression::Version | Expression::Event => {
+                Some(self)
+            }
+            Expression::Aggregate { argument, .. } => argument.pseudo_attribute(),
+            Expression::Negate(operand) | Expression::Not(operand) => operand.pseudo_attribute(),
+            Expression::Binary { left, right, .. } => {
+                left.pseudo_attribute().or_else(|| right.pseudo_attribute())
+            }
+            Expression::In {
+                expression, list, ..
+            } => expression
+                .pseudo_attribute()
+                .or_else(|| list.iter().find_map(|e| e.pseudo_attribute())),
+        }
+    }
+
+    // the attributes of a predicate that an attribute of one stream is equal to an
+    // attribute of another
+    fn join_attributes(&self) -> Option<(&Expression, &Expression)> {
+        match self {
+            Expression::Binary {
+                left,
+                operator: ast::BinaryOperator::Equal,
+                right,
+            } => match (left.as_ref(), right.as_ref()) {
+                (
+                    Expression::Attribute { stream: l, .. },
+                    Expression::Attribute { stream: r, .. },
+                ) if l != r => Some((left, right)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn split_and(self, predicates: &mut Vec<Expression>) {
+        match self {
+            Expression::Binary {
+                left,
+                operator: ast::BinaryOperator::And,
+                right,
+            } => {
+                left.split_and(predicates);
+                right.split_and(predicates);
+            }
+            predicate => predicates.push(predicate),
+        }
+    }
+
+    // the first attribute or key that is neither inside an aggregate nor part of an
+    // expression that is grouped by
+    fn ungrouped(&self, group_by: &[Expression]) -> Option<&Expression> {
+        if group_by.contains(self) {
+            return None;
+        }
+        match self {
+            Expression::Literal(_) | Expression::Aggregate { .. } => None,
+            Expression::Attribute { .. }
+            | Expression::Key
+            | Expression::Timestamp
+            | Expression::Version
+            | Expression::Event => Some(self),
+            Expression::Negate(operand) | Expression::Not(operand) => operand.ungrouped(group_by),
+            Expression::Binary { left, right, .. } => left
+                .ungrouped(group_by)
+                .or_else(|| right.ungrouped(group_by)),
+            Expression::In {
+                expression, list, ..
+            } => expression
+                .ungrouped(group_by)
+                .or_else(|| list.iter().find_map(|e| e.ungrouped(group_by))),
+        }
+    }
+
+    // all attributes in the expression
+    pub fn attributes<'a>(&'a self, attributes: &mut Vec<&'a Expression>) {
+        match self {
+            Expression::Literal(_)
+            | Expression::Key
+            | Expression::Timestamp
+            | Expression::Version
+            | Expression::Event => {}
+            Expression::Attribute { .. } => attributes.push(self),
+            Expression::Aggregate { argument, .. } => argument.attributes(attributes),
+            Expression::Negate(operand) | Expression::Not(operand) => {
+                operand.attributes(attributes)
+            }
+            Expression::Binary { left, right, .. } => {
+                left.attributes(attributes);
+                right.attributes(attributes);
+            }
+            Expression::In {
+                expression, list, ..
+            } => {
+                expression.attributes(attributes);
+                list.iter().for_each(|e| e.attributes(attributes));
+            }
+        }
+    }
+
+    fn streams(&self, streams: &mut Vec<String>) {
+        match self {
+            Expression::Literal(_)
+            | Expression::Key
+            | Expression::Timestamp
+            | Expression::Version
+            | Expression::Event => {}
+            Expression::Attribute { stream, .. } => streams.push(stream.clone()),
+            Expression::Aggregate { argument, .. } => argument.streams(streams),
+            Expression::Negate(operand) | Expression::Not(operand) => operand.streams(streams),
+            Expression::Binary { left, right, .. } => {
+                left.streams(streams);
+                right.streams(streams);
+            }
+            Expression::In {
+                expression, list, ..
+            } => {
+                expression.streams(streams);
+                list.iter().for_each(|e| e.streams(streams));
+            }
+        }
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expression::Literal(event::Value::String(v)) => write!(f, "{:?}", v),
+            Expression::Literal(value) => write!(f, "{}", value),
+            Expression::Attribute {
+                stream,
+                event: None,
+                name,
+            } => write!(f, "{}.{}", stream, name),
+            Expression::Attribute {
+                stream,
+                event: Some(event),
+                name,
+            } => write!(f, "{}.{}.{}", stream, event, name),
+            Expression::Key => write!(f, "key"),
+            Expression::Timestamp => write!(f, "timestamp"),
+            Expression::Version => write!(f, "version"),
+            Expression::Event => write!(f, "event"),
+            Expression::Aggregate {
+                function: ast::Function::Nth(n),
+                argument,
+            } => write!(f, "nth({}, {})", argument, n),
+            Expression::Aggregate { function, argument } => {
+                write!(f, "{}({})", function_name(function), argument)
+            }
+            Expression::Negate(operand) => {
+                write!(f, "-{}", Operand(operand, ast::NEGATE_PRECEDENCE))
+            }
+            Expression::Not(operand) => {
+                write!(f, "not {}", Operand(operand, ast::NOT_PRECEDENCE))
+            }
+            Expression::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                // operators are left associative, so the right operand needs parentheses
+                // when it has the same precedence
+                let precedence = operator.precedence();
+                write!(
+                    f,
+                    "{} {} {}",
+                    Operand(left, precedence),
+                    operator_symbol(operator),
+                    Operand(right, precedence + 1)
+                )
+            }
+            Expression::In {
+                expression,
+                list,
+                negated,
+            } => {
+                let list = list.iter().map(|e| e.to_string()).collect::<Vec<String>>();
+                write!(
+                    f,
+                    "{} {} ({})",
+                    Operand(expression, ast::BinaryOperator::In.precedence() + 1),
+                    if *negated { "not in" } else { "in" },
+                    list.join(", ")
+                )
+            }
+        }
+    }
+}
+
+// an operand of an operator, which is put in parentheses if it binds looser than the
+// operator
+struct Operand<'a>(&'a Expression, u8);
+
+impl fmt::Display for Operand<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let precedence = match self.0 {
+            Expression::Negate(_) => ast::NEGATE_PRECEDENCE,
+            Expression::Not(_) => ast::NOT_PRECEDENCE,
+            Expression::Binary { operator, .. } => operator.precedence(),
+            Expression::In { .. } => ast::BinaryOperator::In.precedence(),
+            _ => u8::MAX,
+        };
+        match precedence < self.1 {
+            true => write!(f, "({})", self.0),
+            false => write!(f, "{}", self.0),
+        }
+    }
+}
+
+pub fn function_name(function: &ast::Function) -> &'static str {
+    match function {
+        ast::Function::Sum => "sum",
+        ast::Function::Min => "min",
+        ast::Function::Max => "max",
+        ast::Function::Avg => "avg",
+        ast::Function::Count => "count",
+        ast::Function::First => "first",
+        ast::Function::Last => "last",
+        ast::Function::Nth(_) => "nth",
+    }
+}
+
+pub fn operator_symbol(operator: &ast::BinaryOperator) -> &'static str {
+    match operator {
+        ast::BinaryOperator::Add => "+",
+        ast::BinaryOperator::Subtract => "-",
+        ast::BinaryOperator::Multiply => "*",
+        ast::BinaryOperator::Divide => "/",
+        ast::BinaryOperator::Modulus => "%",
+        ast::BinaryOperator::And => "and",
+        ast::BinaryOperator::Or => "or",
+        ast::BinaryOperator::Equal => "==",
+        ast::BinaryOperator::NotEqual => "!=",
+        ast::BinaryOperator::LessThan => "<",
+        ast::BinaryOperator::GreaterThan => ">",
+        ast::BinaryOperator::LessEqual => "<=",
+        ast::BinaryOperator::GreaterEqual => ">=",
+        ast::BinaryOperator::In => "in",
+        ast::BinaryOperator::NotIn => "not in",
+    }
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -98,13 +1924,36 @@ pub enum DataType {
     Bool,
 }
 
+impl DataType {
+    pub fn from_str(name: &str) -> Option<Self> {
+        match name {
+            "string" => Some(DataType::String),
+            "int" => Some(DataType::Int),
+            "float" => Some(DataType::Float),
+            "bool" => Some(DataType::Bool),
+            _ => None,
+        }
+    }
+
+    // int values are accepted for float attributes
+    fn accepts(&self, value: &ast::Value) -> bool {
+        matches!(
+            (self, value),
+            (DataType::String, ast::Value::String(_))
+                | (DataType::Int, ast::Value::Int(_))
+                | (DataType::Float, ast::Value::Float(_) | ast::Value::Int(_))
+                | (DataType::Bool, ast::Value::Bool(_))
+        )
+    }
+}
+
 #[derive(Debug)]
 pub struct PlanError {
     message: String,
 }
 
 impl PlanError {
-    fn new(msg: &str) -> Self {
+    pub fn new(msg: &str) -> Self {
         return PlanError {
             message: msg.to_string(),
         };
@@ -180,4 +2029,451 @@ mod plan_test {
     //         assert_eq!(expected, got)
     //     }
     // }
+
+    // streams with the number of events they have, each with an event that has all
+    // attributes
+    struct TestCatalog(Vec<(&'static str, u64)>);
+
+    impl Catalog for TestCatalog {
+        fn last_version(&self, _: &str, _: &str) -> Result<u64, PlanError> {
+            Ok(0)
+        }
+
+        fn event_attributes(
+            &self,
+            _: &str,
+            _: &str,
+        ) -> Result<Option<HashMap<String, AttributeDetails>>, PlanError> {
+            let details = AttributeDetails {
+                required: true,
+                attribute_type: "string".to_string(),
+            };
+            Ok(Some(
+                ["id", "user_id", "name"]
+                    .into_iter()
+                    .map(|name| (name.to_string(), details.clone()))
+                    .collect(),
+            ))
+        }
+
+        fn stream_exists(&self, stream_name: &str) -> Result<bool, PlanError> {
+            Ok(self.0.iter().any(|(name, _)| *name == stream_name))
+        }
+
+        fn streams(&self) -> Result<Vec<String>, PlanError> {
+            Ok(self.0.iter().map(|(name, _)| name.to_string()).collect())
+        }
+
+        fn stream_events(&self, _: &str) -> Result<Vec<String>, PlanError> {
+            Ok(vec!["Created".to_string()])
+        }
+
+        // user.Created.name is indexed
+        fn has_index(&self, stream: &str, event: &str, attribute: &str) -> Result<bool, PlanError> {
+            Ok((stream, event, attribute) == ("user", "Created", "name"))
+        }
+
+        fn subscription_exists(&self, name: &str) -> Result<bool, PlanError> {
+            Ok(name == "workers")
+        }
+
+        fn stream_size(&self, stream_name: &str) -> Result<u64, PlanError> {
+            Ok(self
+                .0
+                .iter()
+                .find(|(name, _)| *name == stream_name)
+                .map_or(0, |(_, size)| *size))
+        }
+    }
+
+    #[test]
+    fn test_plan_transaction() {
+        let catalog = TestCatalog(vec![("user", 10)]);
+        let trx = crate::parser::parse(
+            r#"add UserCreated(id="1", user_id="1", name="axel") to user(id="1");
+               add UserCreated(id="2", user_id="2", name="bob") to user(id="2");
+               add UserRenamed(id="1", user_id="1", name="carl") to user(id="1");"#,
+        )
+        .unwrap();
+
+        // the versions of a stream key follow each other within the transaction
+        let plan = plan(&trx, &catalog).unwrap();
+        let versions = plan
+            .operations
+            .iter()
+            .map(|op| match op {
+                Operation::AddEvent { event } => (event.key.as_str(), event.version),
+                op => panic!("unexpected operation {:?}", op),
+            })
+            .collect::<Vec<(&str, u64)>>();
+        assert_eq!(vec![("1", 1), ("2", 1), ("1", 2)], versions);
+    }
+
+    #[test]
+    fn test_plan_join() {
+        let test_cases = vec![
+            ("fewer users", vec![("user", 10), ("account", 100)], "user"),
+            (
+                "fewer accounts",
+                vec![("user", 100), ("account", 10)],
+                "account",
+            ),
+        ];
+        let trx = crate::parser::parse(
+            r#"find user.name where account.user_id = user.id and user.name = "axel";"#,
+        )
+        .unwrap();
+
+        for (name, streams, expected_build) in test_cases {
+            let plan = match plan(&trx, &TestCatalog(streams)) {
+                Ok(plan) => plan,
+                Err(e) => panic!("test case '{}' failed planning: {}", name, e),
+            };
+            let Some(Operation::Find { query }) = plan.operations.first() else {
+                panic!("test case '{}' did not plan a find", name)
+            };
+            let join = query.join.as_ref().expect("query is not a join");
+            assert_eq!(expected_build, join.build.stream, "test case '{}'", name);
+            let user = match join.build.stream == "user" {
+                true => &join.build,
+                false => &join.probe,
+            };
+            assert_eq!(1, user.predicates.len(), "test case '{}'", name);
+            assert!(query.predicates.is_empty(), "test case '{}'", name);
+        }
+    }
+
+    #[test]
+    fn test_plan_time_range() {
+        let test_cases = vec![
+            (
+                "after",
+                "find user.name where timestamp >= 100;",
+                Some((100, i128::MAX as u128)),
+            ),
+            (
+                "between timestamp literals",
+                r#"find user.name where timestamp between '2026-01-01' and '2026-01-02' and user.name = "axel";"#,
+                Some((1767225600000, 1767312000000)),
+            ),
+            (
+                "literal on the left",
+                "find user.name where 100 < timestamp and timestamp < 200;",
+                Some((101, 199)),
+            ),
+            (
+                "empty",
+                "find user.name where timestamp > 200 and timestamp < 100;",
+                Some((1, 0)),
+            ),
+            (
+                "or",
+                r#"find user.name where timestamp > 100 or user.name = "axel";"#,
+                None,
+            ),
+            (
+                "no timestamp",
+                r#"find user.name where user.name = "axel";"#,
+                None,
+            ),
+        ];
+
+        for (name, query, expected) in test_cases {
+            let trx = crate::parser::parse(query).unwrap();
+            let plan = match plan(&trx, &TestCatalog(vec![("user", 10)])) {
+                Ok(plan) => plan,
+                Err(e) => panic!("test case '{}' failed planning: {}", name, e),
+            };
+            let Some(Operation::Find { query }) = plan.operations.first() else {
+                panic!("test case '{}' did not plan a find", name)
+            };
+            assert_eq!(expected, query.time_range, "test case '{}'", name);
+        }
+    }
+
+    #[test]
+    fn test_plan_index() {
+        let index = |from, to| {
+            Some(Box::new(IndexScan {
+                stream: "user".to_string(),
+                event: "Created".to_string(),
+                attribute: "name".to_string(),
+                from,
+                to,
+            }))
+        };
+        let name = |name: &str| event::Value::String(name.to_string());
+        let test_cases = vec![
+            (
+                "equality",
+                r#"find user.id where user.Created.name = "axel";"#,
+                index(Bound::Included(name("axel")), Bound::Included(name("axel"))),
+            ),
+            (
+                "narrowed range",
+                r#"find user.id where user.Created.name > "a" and user.Created.name <= "m" and "c" < user.Created.name;"#,
+                index(Bound::Excluded(name("c")), Bound::Included(name("m"))),
+            ),
+            (
+                "join",
+                r#"find user.id where account.user_id = user.id and user.Created.name >= "b";"#,
+                index(Bound::Included(name("b")), Bound::Unbounded),
+            ),
+            (
+                "attribute of all events",
+                r#"find user.id where user.name = "axel";"#,
+                None,
+            ),
+            (
+                "or",
+                r#"find user.id where user.Created.name = "axel" or user.id = "1";"#,
+                None,
+            ),
+            ("null", "find user.id where user.Created.name = null;", None),
+        ];
+
+        let catalog = TestCatalog(vec![("user", 10), ("account", 10)]);
+        for (name, query, expected) in test_cases {
+            let trx = crate::parser::parse(query).unwrap();
+            let plan = match plan(&trx, &catalog) {
+                Ok(plan) => plan,
+                Err(e) => panic!("test case '{}' failed planning: {}", name, e),
+            };
+            let Some(Operation::Find { query }) = plan.operations.first() else {
+                panic!("test case '{}' did not plan a find", name)
+            };
+            assert_eq!(expected, query.index, "test case '{}'", name);
+        }
+    }
+
+    #[test]
+    fn test_plan_subscription() {
+        let catalog = TestCatalog(vec![("user", 10), ("account", 10)]);
+        let trx =
+            crate::parser::parse(r#"subscribe to $all from position 3 where event = "Created";"#)
+                .unwrap();
+        let expected = Subscription {
+            stream: None,
+            from_position: 3,
+            predicates: vec![Expression::Binary {
+                left: Box::new(Expression::Event),
+                operator: ast::BinaryOperator::Equal,
+                right: Box::new(Expression::Literal(event::Value::String(
+                    "Created".to_string(),
+                ))),
+            }],
+        };
+        match plan(&trx, &catalog) {
+            Ok(plan) => assert_eq!(
+                vec![Operation::Subscribe {
+                    subscription: expected
+                }],
+                plan.operations
+            ),
+            Err(e) => panic!("failed planning: {}", e),
+        }
+
+        let test_cases = vec![
+            (
+                "unknown stream",
+                "subscribe to loan;",
+                "unknown stream 'loan'",
+            ),
+            (
+                "attribute of another stream",
+                r#"subscribe to account where user.name = "axel";"#,
+                "subscription to 'account' can not use the attributes of stream 'user'",
+            ),
+            (
+                "unknown attribute",
+                "subscribe to $all where user.age > 30;",
+                "unknown attribute",
+            ),
+            (
+                "aggregate",
+                "subscribe to user where count(user.name) > 1;",
+                "aggregates can not be used in where",
+            ),
+        ];
+        for (name, query, expected) in test_cases {
+            let trx = crate::parser::parse(query).unwrap();
+            match plan(&trx, &catalog) {
+                Ok(plan) => panic!("test case '{}' did not fail: {:?}", name, plan),
+                Err(e) => assert!(
+                    e.to_string().contains(expected),
+                    "test case '{}' got '{}'",
+                    name,
+                    e
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn test_plan_persistent_subscription() {
+        let catalog = TestCatalog(vec![("user", 10), ("account", 10)]);
+        let test_cases = vec![
+            (
+                "create",
+                "create subscription jobs to account;",
+                Operation::CreateSubscription {
+                    name: "jobs".to_string(),
+                    stream_name: "account".to_string(),
+                    from_position: 1,
+                    park_after: DEFAULT_PARK_AFTER,
+                },
+            ),
+            (
+                "create on $all",
+                "create subscription jobs to $all from position 5 park after 3;",
+                Operation::CreateSubscription {
+                    name: "jobs".to_string(),
+                    stream_name: "$all".to_string(),
+                    from_position: 5,
+                    park_after: 3,
+                },
+            ),
+            (
+                "join",
+                "join workers;",
+                Operation::Join {
+                    subscription: "workers".to_string(),
+                    limit: DEFAULT_JOIN_LIMIT,
+                },
+            ),
+            (
+                "join with limit",
+                "join workers limit 1;",
+                Operation::Join {
+                    subscription: "workers".to_string(),
+                    limit: 1,
+                },
+            ),
+            (
+                "nack",
+                "nack 3, 5;",
+                Operation::Nack {
+                    positions: vec![3, 5],
+                },
+            ),
+            (
+                "read parked",
+                "read parked workers;",
+                Operation::ReadParked {
+                    subscription: "workers".to_string(),
+                },
+            ),
+        ];
+        for (name, query, expected) in test_cases {
+            let trx = crate::parser::parse(query).unwrap();
+            match plan(&trx, &catalog) {
+                Ok(plan) => assert_eq!(vec![expected], plan.operations, "test case '{}'", name),
+                Err(e) => panic!("test case '{}' failed planning: {}", name, e),
+            }
+        }
+
+        let test_cases = vec![
+            (
+                "already exists",
+                "create subscription workers to account;",
+                "subscription 'workers' already exists",
+            ),
+            (
+                "unknown stream",
+                "create subscription jobs to loan;",
+                "unknown stream 'loan'",
+            ),
+            (
+                "park after 0",
+                "create subscription jobs to account park after 0;",
+                "park after must be at least 1",
+            ),
+            ("join unknown", "join jobs;", "unknown subscription 'jobs'"),
+            (
+                "limit 0",
+                "join workers limit 0;",
+                "limit must be at least 1",
+            ),
+            (
+                "replay unknown",
+                "replay parked jobs;",
+                "unknown subscription 'jobs'",
+            ),
+        ];
+        for (name, query, expected) in test_cases {
+            let trx = crate::parser::parse(query).unwrap();
+            match plan(&trx, &catalog) {
+                Ok(plan) => panic!("test case '{}' did not fail: {:?}", name, plan),
+                Err(e) => assert!(
+                    e.to_string().contains(expected),
+                    "test case '{}' got '{}'",
+                    name,
+                    e
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn test_plan_event_types() {
+        let types = |types: &[(&str, &str)]| {
+            Some(
+                types
+                    .iter()
+                    .map(|(stream, event)| (stream.to_string(), event.to_string()))
+                    .collect::<Vec<(String, String)>>(),
+            )
+        };
+        let test_cases = vec![
+            (
+                "event of all streams",
+                r#"find key where event = "Created";"#,
+                types(&[("account", "Created"), ("user", "Created")]),
+            ),
+            (
+                "event in",
+                r#"find user.id where event in ("Deleted", "Created");"#,
+                types(&[("user", "Created"), ("user", "Deleted")]),
+            ),
+            (
+                "attribute of an event type",
+                r#"find user.id where "axel" != user.Created.name;"#,
+                types(&[("user", "Created")]),
+            ),
+            (
+                "intersected",
+                r#"find user.id where event = "Deleted" and user.Created.name = "axel";"#,
+                types(&[]),
+            ),
+            (
+                "event not equal",
+                r#"find user.id where event != "Created";"#,
+                None,
+            ),
+            (
+                "attribute of all events",
+                r#"find user.id where user.name = "axel";"#,
+                None,
+            ),
+            ("null", "find user.id where user.Created.name = null;", None),
+            (
+                "join",
+                r#"find user.id where account.user_id = user.id and user.Created.name = "axel";"#,
+                None,
+            ),
+        ];
+
+        let catalog = TestCatalog(vec![("user", 10), ("account", 10)]);
+        for (name, query, expected) in test_cases {
+            let trx = crate::parser::parse(query).unwrap();
+            let plan = match plan(&trx, &catalog) {
+                Ok(plan) => plan,
+                Err(e) => panic!("test case '{}' failed planning: {}", name, e),
+            };
+            let Some(Operation::Find { query }) = plan.operations.first() else {
+                panic!("test case '{}' did not plan a find", name)
+            };
+            assert_eq!(expected, query.event_types, "test case '{}'", name);
+        }
+    }
 }
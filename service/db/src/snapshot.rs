@@ -0,0 +1,276 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Component, Path, PathBuf};
+
+use crate::codec::{
+    decode_event, decode_frame, encode_event, encode_frame, put_str, CodecError, Reader,
+};
+use crate::event::Event;
+use crate::storage::SchemaChange;
+use crate::wal::{decode_change, encode_change, WalError};
+
+// A snapshot is a header frame followed by one frame per schema change, encoded as in the
+// wal and in the order they were made, and then one frame per event;
+//
+//   [header: magic, format version, number of schema changes, number of events]
+//   [schema change]...[event]...
+//
+// Unlike the wal a snapshot is never appended to, so a frame that is torn or corrupt
+// makes the whole snapshot invalid.
+
+const MAGIC: &str = "adb-snapshot";
+const FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, PartialEq)]
+pub struct Snapshot {
+    pub schema_changes: Vec<SchemaChange>,
+    // events of the same stream key are ordered by version
+    pub events: Vec<Event>,
+}
+
+// the path of the snapshot with the name in dir. Snapshots are named by clients, so the
+// name must be relative and can not leave dir
+pub fn resolve(dir: &Path, name: &str) -> Result<PathBuf, SnapshotError> {
+    let name = Path::new(name);
+    let is_inside_dir = name
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+    if name.as_os_str().is_empty() || !is_inside_dir {
+        return Err(SnapshotError::new(&format!(
+            "invalid snapshot name {:?}, it must be a relative path without '..'",
+            name
+        )));
+    }
+    Ok(dir.join(name))
+}
+
+// the snapshot is written to a temporary file that is linked to path once it has been
+// flushed to disk, so a crash never leaves a partial snapshot behind. An existing file
+// at path is never overwritten
+pub fn write(path: &Path, snapshot: &Snapshot) -> Result<(), SnapshotError> {
+    if path.exists() {
+        return Err(SnapshotError::new(&format!("{:?} already exists", path)));
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| {
+            SnapshotError::new(&format!("failed to create directory {:?}: {}", dir, e))
+        })?;
+    }
+
+    let tmp_path = tmp_path(path);
+    let file = File::create(&tmp_path)
+        .map_err(|e| SnapshotError::new(&format!("failed to create {:?}: {}", tmp_path, e)))?;
+    let mut writer = BufWriter::new(file);
+
+    let mut header = vec![];
+    put_str(&mut header, MAGIC);
+    header.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    header.extend_from_slice(&(snapshot.schema_changes.len() as u64).to_le_bytes());
+    header.extend_from_slice(&(snapshot.events.len() as u64).to_le_bytes());
+
+    let changes = snapshot.schema_changes.iter().map(encode_change);
+    let events = snapshot.events.iter().map(|event| {
+        let mut payload = vec![];
+        encode_event(&mut payload, event);
+        payload
+    });
+    for payload in std::iter::once(header).chain(changes).chain(events) {
+        writer
+            .write_all(&encode_frame(&payload))
+            .map_err(|e| SnapshotError::new(&format!("failed to write snapshot: {}", e)))?;
+    }
+
+    let file = writer
+        .into_inner()
+        .map_err(|e| SnapshotError::new(&format!("failed to write snapshot: {}", e)))?;
+    file.sync_all()
+        .map_err(|e| SnapshotError::new(&format!("failed to sync snapshot: {}", e)))?;
+    // unlike a rename, linking fails if path was created while the snapshot was written
+    let linked = fs::hard_link(&tmp_path, path).map_err(|e| {
+        SnapshotError::new(&format!(
+            "failed to link {:?} to {:?}: {}",
+            tmp_path, path, e
+        ))
+    });
+    let _ = fs::remove_file(&tmp_path);
+    linked?;
+
+    Ok(())
+}
+
+pub fn read(path: &Path) -> Result<Snapshot, SnapshotError> {
+    let buffer = fs::read(path)
+        .map_err(|e| SnapshotError::new(&format!("failed to read {:?}: {}", path, e)))?;
+
+    let (header, mut offset) = decode_frame(&buffer)
+        .ok_or_else(|| SnapshotError::new(&format!("{:?} is not a snapshot", path)))?;
+    let mut reader = Reader::new(header);
+    if reader.str()? != MAGIC {
+        return Err(SnapshotError::new(&format!("{:?} is not a snapshot", path)));
+    }
+    let format_version = reader.u32()?;
+    if format_version != FORMAT_VERSION {
+        return Err(SnapshotError::new(&format!(
+            "unsupported snapshot format version {}",
+            format_version
+        )));
+    }
+    let n_schema_changes = reader.u64()?;
+    let n_events = reader.u64()?;
+
+    let mut snapshot = Snapshot {
+        schema_changes: vec![],
+        events: vec![],
+    };
+    for i in 0..n_schema_changes + n_events {
+        let (payload, frame_len) = buffer
+            .get(offset..)
+            .and_then(decode_frame)
+            .ok_or_else(|| SnapshotError::new("snapshot is truncated or corrupt"))?;
+        if i < n_schema_changes {
+            snapshot.schema_changes.push(decode_change(payload)?);
+        } else {
+            snapshot
+                .events
+                .push(decode_event(&mut Reader::new(payload))?);
+        }
+        offset += frame_len;
+    }
+    if offset != buffer.len() {
+        return Err(SnapshotError::new("snapshot has trailing data"));
+    }
+
+    Ok(snapshot)
+}
+
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".tmp");
+    PathBuf::from(name)
+}
+
+#[derive(Debug)]
+pub struct SnapshotError {
+    message: String,
+}
+
+impl SnapshotError {
+    fn new(message: &str) -> Self {
+        SnapshotError {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for SnapshotError {}
+
+impl From<CodecError> for SnapshotError {
+    fn from(e: CodecError) -> SnapshotError {
+        SnapshotError::new(&e.to_string())
+    }
+}
+
+impl From<WalError> for SnapshotError {
+    fn from(e: WalError) -> SnapshotError {
+        SnapshotError::new(&e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod snapshot_test {
+    use super::*;
+    use crate::event::{Attribute, Value};
+
+    fn test_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir()
+            .join(format!("adb-snapshot-test-{}", std::process::id()))
+            .join(name);
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn snapshot() -> Snapshot {
+        Snapshot {
+            schema_changes: vec![
+                SchemaChange::Stream {
+                    name: "account".to_string(),
+                },
+                SchemaChange::Event {
+                    name: "AccountCreated".to_string(),
+                    stream_name: "account".to_string(),
+                },
+            ],
+            events: (1..=3)
+                .map(|version| {
+                    Event::new(
+                        "account".to_string(),
+                        "123".to_string(),
+                        "AccountCreated".to_string(),
+                        version,
+                        1700000000000,
+                        vec![Attribute {
+                            name: "owner".to_string(),
+                            value: Value::String("axel".to_string()),
+                        }],
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_write_and_read() {
+        let path = test_path("full.snap");
+
+        write(&path, &snapshot()).unwrap();
+        assert_eq!(snapshot(), read(&path).unwrap());
+        assert!(!tmp_path(&path).exists());
+    }
+
+    #[test]
+    fn test_write_does_not_overwrite() {
+        let path = test_path("existing.snap");
+
+        fs::write(&path, "not a snapshot").unwrap();
+        assert!(write(&path, &snapshot()).is_err());
+        assert_eq!("not a snapshot", fs::read_to_string(&path).unwrap());
+        assert!(!tmp_path(&path).exists());
+    }
+
+    #[test]
+    fn test_resolve() {
+        let dir = Path::new("data/snapshots");
+        let test_cases = vec![
+            ("db.snap", Some("data/snapshots/db.snap")),
+            ("backups/db.snap", Some("data/snapshots/backups/db.snap")),
+            ("", None),
+            ("/etc/passwd", None),
+            ("../wal.log", None),
+            ("backups/../../wal.log", None),
+            ("./db.snap", None),
+        ];
+        for (name, expected) in test_cases {
+            let path = resolve(dir, name).ok();
+            assert_eq!(expected.map(PathBuf::from), path, "test case '{}'", name);
+        }
+    }
+
+    #[test]
+    fn test_read_rejects_truncated_snapshot() {
+        let path = test_path("truncated.snap");
+
+        write(&path, &snapshot()).unwrap();
+        let buffer = fs::read(&path).unwrap();
+        fs::write(&path, &buffer[..buffer.len() - 3]).unwrap();
+
+        assert!(read(&path).is_err());
+    }
+}
@@ -0,0 +1,134 @@
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::event::{Event, Value};
+use crate::storage::segment::{SegmentStore, DEFAULT_SEGMENT_SIZE};
+use crate::storage::{EventStore, SchemaChange, StorageError};
+use crate::wal::Wal;
+
+// keeps events in segment files and schema changes in a write-ahead log, everything
+// in dir
+#[derive(Debug)]
+pub struct FileStore {
+    events: SegmentStore,
+    wal: Mutex<Wal>,
+    schema_changes: Mutex<Vec<SchemaChange>>,
+}
+
+impl FileStore {
+    pub fn open(dir: &Path) -> Result<Self, StorageError> {
+        let events = SegmentStore::open(dir, DEFAULT_SEGMENT_SIZE)?;
+        let (wal, schema_changes) = Wal::open(&dir.join("wal.log"))
+            .map_err(|e| StorageError::new(&format!("failed to open write-ahead log: {}", e)))?;
+
+        Ok(FileStore {
+            events,
+            wal: Mutex::new(wal),
+            schema_changes: Mutex::new(schema_changes),
+        })
+    }
+}
+
+impl EventStore for FileStore {
+    fn append(&self, event: Event, expected_version: u64) -> Result<u64, StorageError> {
+        self.events.append(event, expected_version)
+    }
+
+    fn last_version(&self, stream_name: &str, key: &str) -> Result<u64, StorageError> {
+        self.events.last_version(stream_name, key)
+    }
+
+    fn get_events(&self, stream_name: &str, key: &str) -> Result<Option<Vec<Event>>, StorageError> {
+        self.events.get_events(stream_name, key)
+    }
+
+    fn read_range(
+        &self,
+        stream_name: &str,
+        key: &str,
+        from_version: u64,
+        to_version: u64,
+    ) -> Result<Vec<Event>, StorageError> {
+        self.events
+            .read_range(stream_name, key, from_version, to_version)
+    }
+
+    fn read_time_range(
+        &self,
+        stream_name: &str,
+        from: u128,
+        to: u128,
+    ) -> Result<Vec<Event>, StorageError> {
+        self.events.read_time_range(stream_name, from, to)
+    }
+
+    fn read_event_types(
+        &self,
+        stream_name: &str,
+        event_names: &[String],
+    ) -> Result<Vec<Event>, StorageError> {
+        self.events.read_event_types(stream_name, event_names)
+    }
+
+    fn read_all(&self, from_position: u64, limit: usize) -> Result<Vec<Event>, StorageError> {
+        self.events.read_all(from_position, limit)
+    }
+
+    fn create_index(
+        &self,
+        stream_name: &str,
+        event_name: &str,
+        attribute_name: &str,
+    ) -> Result<(), StorageError> {
+        self.events
+            .create_index(stream_name, event_name, attribute_name)
+    }
+
+    fn read_index(
+        &self,
+        stream_name: &str,
+        event_name: &str,
+        attribute_name: &str,
+        range: (Bound<&Value>, Bound<&Value>),
+    ) -> Result<Vec<Event>, StorageError> {
+        self.events
+            .read_index(stream_name, event_name, attribute_name, range)
+    }
+
+    fn stream_keys(&self, stream_name: &str) -> Result<Vec<String>, StorageError> {
+        self.events.stream_keys(stream_name)
+    }
+
+    fn snapshot(&self) -> Result<Vec<Event>, StorageError> {
+        self.events.snapshot()
+    }
+
+    fn persist_schema_change(&self, change: &SchemaChange) -> Result<(), StorageError> {
+        // the changes are locked while writing so they are kept in the same order as in
+        // the log
+        let mut schema_changes = self
+            .schema_changes
+            .lock()
+            .map_err(|_| StorageError::new("failed to lock schema changes"))?;
+
+        self.wal
+            .lock()
+            .map_err(|_| StorageError::new("failed to lock write-ahead log"))?
+            .append(change)
+            .map_err(|e| {
+                StorageError::new(&format!("failed to write to write-ahead log: {}", e))
+            })?;
+
+        schema_changes.push(change.clone());
+        Ok(())
+    }
+
+    fn schema_changes(&self) -> Result<Vec<SchemaChange>, StorageError> {
+        Ok(self
+            .schema_changes
+            .lock()
+            .map_err(|_| StorageError::new("failed to lock schema changes"))?
+            .clone())
+    }
+}
@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+use std::ops::Bound;
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::event::{Event, Value};
+use crate::storage::{
+    version_range, version_runs, AttributeIndexes, EventStore, EventTypeIndex, SchemaChange,
+    StorageError, TimeIndex, VersionConflict,
+};
+
+// (stream, key) : []Events
+#[derive(Debug)]
+pub struct Streams(pub HashMap<(String, String), Arc<RwLock<Vec<Event>>>>);
+
+// keeps all events and schema changes in memory, nothing survives a restart
+#[derive(Debug)]
+pub struct MemoryStore {
+    streams: RwLock<Streams>,
+    times: TimeIndex,
+    types: EventTypeIndex,
+    indexes: AttributeIndexes,
+    // stream, key and version of the event at each position, the event at position n is
+    // at n - 1. Positions are given out while holding the write lock, which sequences the
+    // appends to all stream keys
+    positions: RwLock<Vec<(String, String, u64)>>,
+    schema_changes: Mutex<Vec<SchemaChange>>,
+}
+
+impl MemoryStore {
+    #[cfg(test)]
+    pub fn new() -> Self {
+        MemoryStore {
+            streams: RwLock::new(Streams(HashMap::new())),
+            times: TimeIndex::default(),
+            types: EventTypeIndex::default(),
+            indexes: AttributeIndexes::default(),
+            positions: RwLock::new(vec![]),
+            schema_changes: Mutex::new(vec![]),
+        }
+    }
+
+    // returns the lock for the events of the stream key, creating an empty entry
+    // if this is the first event added to the stream key
+    fn stream_key(
+        &self,
+        stream_name: &str,
+        key: &str,
+    ) -> Result<Arc<RwLock<Vec<Event>>>, StorageError> {
+        let stream_key = (stream_name.to_string(), key.to_string());
+
+        if let Some(events_lock) = self
+            .streams
+            .read()
+            .map_err(|_| StorageError::new("failed to read streams"))?
+            .0
+            .get(&stream_key)
+        {
+            return Ok(events_lock.clone());
+        }
+
+        let mut streams = self
+            .streams
+            .write()
+            .map_err(|_| StorageError::new("failed to write to streams"))?;
+
+        Ok(streams
+            .0
+            .entry(stream_key)
+            .or_insert_with(|| Arc::new(RwLock::new(vec![])))
+            .clone())
+    }
+}
+
+impl EventStore for MemoryStore {
+    fn append(&self, mut event: Event, expected_version: u64) -> Result<u64, StorageError> {
+        let stream_arc = self.stream_key(&event.stream, &event.key)?;
+
+        let mut stream = stream_arc
+            .write()
+            .map_err(|_| StorageError::new("failed to write to stream"))?;
+
+        let last_version = stream.last().map_or(0, |e| e.version);
+        if last_version != expected_version {
+            return Err(StorageError::conflict(VersionConflict {
+                expected_version,
+                current_version: last_version,
+            }));
+        }
+        event.version = expected_version + 1;
+
+        let mut positions = self
+            .positions
+            .write()
+            .map_err(|_| StorageError::new("failed to write to positions"))?;
+        event.position = positions.len() as u64 + 1;
+
+        self.times.insert(&event)?;
+        self.types.insert(&event)?;
+        self.indexes.insert(&event)?;
+        positions.push((event.stream.clone(), event.key.clone(), event.version));
+        let position = event.position;
+        stream.push(event);
+        Ok(position)
+    }
+
+    fn last_version(&self, stream_name: &str, key: &str) -> Result<u64, StorageError> {
+        let streams = self
+            .streams
+            .read()
+            .map_err(|e| StorageError::new(&format!("failed to read streams: {}", e)))?;
+
+        match streams.0.get(&(stream_name.to_string(), key.to_string())) {
+            Some(events_lock) => {
+                let events = events_lock.read().map_err(|e| {
+                    StorageError::new(&format!("failed to read event stream: {}", e))
+                })?;
+                Ok(events.last().map_or(0, |e| e.version))
+            }
+            None => Ok(0),
+        }
+    }
+
+    fn get_events(&self, stream_name: &str, key: &str) -> Result<Option<Vec<Event>>, StorageError> {
+        let streams = self
+            .streams
+            .read()
+            .map_err(|e| StorageError::new(&format!("failed to read streams: {}", e)))?;
+
+        match streams.0.get(&(stream_name.to_string(), key.to_string())) {
+            Some(events_lock) => {
+                let events = events_lock.read().map_err(|e| {
+                    StorageError::new(&format!("failed to read event stream: {}", e))
+                })?;
+                Ok(Some(events.clone()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn read_range(
+        &self,
+        stream_name: &str,
+        key: &str,
+        from_version: u64,
+        to_version: u64,
+    ) -> Result<Vec<Event>, StorageError> {
+        let streams = self
+            .streams
+            .read()
+            .map_err(|e| StorageError::new(&format!("failed to read streams: {}", e)))?;
+
+        match streams.0.get(&(stream_name.to_string(), key.to_string())) {
+            Some(events_lock) => {
+                let events = events_lock.read().map_err(|e| {
+                    StorageError::new(&format!("failed to read event stream: {}", e))
+                })?;
+                let (from, to) = version_range(from_version, to_version, events.len());
+                Ok(events[from..to].to_vec())
+            }
+            None => Ok(vec![]),
+        }
+    }
+
+    fn read_time_range(
+        &self,
+        stream_name: &str,
+        from: u128,
+        to: u128,
+    ) -> Result<Vec<Event>, StorageError> {
+        let mut events = vec![];
+        for (key, from, to) in version_runs(self.times.range(stream_name, from, to)?) {
+            events.extend(self.read_range(stream_name, &key, from, to)?);
+        }
+        Ok(events)
+    }
+
+    fn read_event_types(
+        &self,
+        stream_name: &str,
+        event_names: &[String],
+    ) -> Result<Vec<Event>, StorageError> {
+        let mut events = vec![];
+        for (key, from, to) in version_runs(self.types.postings(stream_name, event_names)?) {
+            events.extend(self.read_range(stream_name, &key, from, to)?);
+        }
+        Ok(events)
+    }
+
+    // the stream keys are read once the lock of the positions is released, an event that
+    // has a position is already in its stream key or is added before the write lock of
+    // the stream key is released
+    fn read_all(&self, from_position: u64, limit: usize) -> Result<Vec<Event>, StorageError> {
+        let entries = {
+            let positions = self
+                .positions
+                .read()
+                .map_err(|_| StorageError::new("failed to read positions"))?;
+            let from = (from_position.max(1) - 1).min(positions.len() as u64) as usize;
+            positions[from..]
+                .iter()
+                .take(limit)
+                .cloned()
+                .collect::<Vec<(String, String, u64)>>()
+        };
+
+        let mut events = vec![];
+        for (stream_name, key, version) in entries {
+            events.extend(self.read_range(&stream_name, &key, version, version)?);
+        }
+        Ok(events)
+    }
+
+    // events added while the existing events are indexed are inserted by append, so the
+    // index is created before the events are read
+    fn create_index(
+        &self,
+        stream_name: &str,
+        event_name: &str,
+        attribute_name: &str,
+    ) -> Result<(), StorageError> {
+        self.indexes
+            .create(stream_name, event_name, attribute_name)?;
+        for key in self.stream_keys(stream_name)? {
+            for event in self.read_range(stream_name, &key, 1, u64::MAX)? {
+                self.indexes.insert(&event)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_index(
+        &self,
+        stream_name: &str,
+        event_name: &str,
+        attribute_name: &str,
+        range: (Bound<&Value>, Bound<&Value>),
+    ) -> Result<Vec<Event>, StorageError> {
+        let mut events = vec![];
+        let postings = self
+            .indexes
+            .range(stream_name, event_name, attribute_name, range)?;
+        for (key, from, to) in version_runs(postings) {
+            events.extend(self.read_range(stream_name, &key, from, to)?);
+        }
+        Ok(events)
+    }
+
+    fn stream_keys(&self, stream_name: &str) -> Result<Vec<String>, StorageError> {
+        let streams = self
+            .streams
+            .read()
+            .map_err(|e| StorageError::new(&format!("failed to read streams: {}", e)))?;
+
+        let mut keys = streams
+            .0
+            .keys()
+            .filter(|(stream, _)| stream == stream_name)
+            .map(|(_, key)| key.clone())
+            .collect::<Vec<String>>();
+        keys.sort();
+        Ok(keys)
+    }
+
+    fn snapshot(&self) -> Result<Vec<Event>, StorageError> {
+        let streams = self
+            .streams
+            .read()
+            .map_err(|e| StorageError::new(&format!("failed to read streams: {}", e)))?;
+
+        let locked_streams = streams
+            .0
+            .values()
+            .map(|events_lock| {
+                events_lock
+                    .read()
+                    .map_err(|e| StorageError::new(&format!("failed to read event stream: {}", e)))
+            })
+            .collect::<Result<Vec<_>, StorageError>>()?;
+
+        Ok(locked_streams
+            .iter()
+            .flat_map(|events| events.iter().cloned())
+            .collect())
+    }
+
+    fn persist_schema_change(&self, change: &SchemaChange) -> Result<(), StorageError> {
+        self.schema_changes
+            .lock()
+            .map_err(|_| StorageError::new("failed to lock schema changes"))?
+            .push(change.clone());
+        Ok(())
+    }
+
+    fn schema_changes(&self) -> Result<Vec<SchemaChange>, StorageError> {
+        Ok(self
+            .schema_changes
+            .lock()
+            .map_err(|_| StorageError::new("failed to lock schema changes"))?
+            .clone())
+    }
+}
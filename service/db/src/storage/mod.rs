@@ -0,0 +1,715 @@
+pub mod file;
+pub mod memory;
+pub mod segment;
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::ops::Bound;
+use std::sync::RwLock;
+
+use crate::event::{Event, Value};
+
+// Where the events of the streams and the schema are kept. Writes to a stream key are
+// serialized by the store, while writes to different stream keys and all reads can run
+// concurrently.
+pub trait EventStore: fmt::Debug + Send + Sync {
+    // adds the event with version expected_version + 1 to the end of its stream key and
+    // returns its position, fails if the last version of the stream key is not
+    // expected_version
+    fn append(&self, event: Event, expected_version: u64) -> Result<u64, StorageError>;
+
+    // version of the last event of the stream key, 0 if the stream key has no events
+    fn last_version(&self, stream_name: &str, key: &str) -> Result<u64, StorageError>;
+
+    // all events of the stream key ordered by version, None if the stream key has no events
+    fn get_events(&self, stream_name: &str, key: &str) -> Result<Option<Vec<Event>>, StorageError>;
+
+    // keys of the stream in order
+    fn stream_keys(&self, stream_name: &str) -> Result<Vec<String>, StorageError>;
+
+    // events of the stream key with a version between from_version and to_version
+    // (inclusive), ordered by version
+    fn read_range(
+        &self,
+        stream_name: &str,
+        key: &str,
+        from_version: u64,
+        to_version: u64,
+    ) -> Result<Vec<Event>, StorageError>;
+
+    // events of the stream with a timestamp between from and to (inclusive), ordered by
+    // key and version
+    fn read_time_range(
+        &self,
+        stream_name: &str,
+        from: u128,
+        to: u128,
+    ) -> Result<Vec<Event>, StorageError>;
+
+    // events of the stream of any of the event types, ordered by key and version
+    fn read_event_types(
+        &self,
+        stream_name: &str,
+        event_names: &[String],
+    ) -> Result<Vec<Event>, StorageError>;
+
+    // at most limit events of all streams with a position from from_position, ordered by
+    // position. Every appended event gets the next position, so the positions are the
+    // order the events were added in across all streams
+    fn read_all(&self, from_position: u64, limit: usize) -> Result<Vec<Event>, StorageError>;
+
+    // indexes the values of the attribute of the events of the stream and event type,
+    // both the events already added and the events added from now on
+    fn create_index(
+        &self,
+        stream_name: &str,
+        event_name: &str,
+        attribute_name: &str,
+    ) -> Result<(), StorageError>;
+
+    // events of the stream and event type with a value of the indexed attribute in the
+    // range, ordered by key and version
+    fn read_index(
+        &self,
+        stream_name: &str,
+        event_name: &str,
+        attribute_name: &str,
+        range: (Bound<&Value>, Bound<&Value>),
+    ) -> Result<Vec<Event>, StorageError>;
+
+    // all events of all stream keys as they were at a single point in time, ordered by
+    // version within each stream key. Writes are blocked while the stream keys are locked
+    fn snapshot(&self) -> Result<Vec<Event>, StorageError>;
+
+    fn persist_schema_change(&self, change: &SchemaChange) -> Result<(), StorageError>;
+
+    // all persisted schema changes in the order they were made
+    fn schema_changes(&self) -> Result<Vec<SchemaChange>, StorageError>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SchemaChange {
+    Stream {
+        name: String,
+    },
+    Event {
+        name: String,
+        stream_name: String,
+    },
+    Attribute {
+        name: String,
+        event_name: String,
+        stream_name: String,
+        data_type: String,
+        required: bool,
+    },
+    Index {
+        stream_name: String,
+        event_name: String,
+        attribute_name: String,
+    },
+    // a persistent subscription, where stream_name is $all for all streams
+    Subscription {
+        name: String,
+        stream_name: String,
+        from_position: u64,
+        park_after: u64,
+    },
+}
+
+// maps the inclusive version range to a range of positions in a stream key with len
+// events, versions start at 1
+pub fn version_range(from_version: u64, to_version: u64, len: usize) -> (usize, usize) {
+    let from = (from_version.max(1) - 1).min(len as u64) as usize;
+    let to = to_version.min(len as u64) as usize;
+    (from, to.max(from))
+}
+
+// stream -> timestamp -> key and version of the events of the stream with the timestamp.
+// Kept in memory by the stores so that the events of a time range can be read without
+// reading all events of the stream
+#[derive(Debug, Default)]
+pub struct TimeIndex(RwLock<HashMap<String, Times>>);
+
+// timestamp -> key and version of the events with the timestamp
+type Times = BTreeMap<u128, Vec<(String, u64)>>;
+
+impl TimeIndex {
+    pub fn insert(&self, event: &Event) -> Result<(), StorageError> {
+        self.0
+            .write()
+            .map_err(|_| StorageError::new("failed to write to time index"))?
+            .entry(event.stream.clone())
+            .or_default()
+            .entry(event.timestamp)
+            .or_default()
+            .push((event.key.clone(), event.version));
+        Ok(())
+    }
+
+    // key and version of the events of the stream with a timestamp between from and to
+    // (inclusive), ordered by key and version
+    pub fn range(
+        &self,
+        stream_name: &str,
+        from: u128,
+        to: u128,
+    ) -> Result<Vec<(String, u64)>, StorageError> {
+        let times = self
+            .0
+            .read()
+            .map_err(|_| StorageError::new("failed to read time index"))?;
+        let Some(stream) = times.get(stream_name).filter(|_| from <= to) else {
+            return Ok(vec![]);
+        };
+
+        let mut events = stream
+            .range(from..=to)
+            .flat_map(|(_, events)| events.iter().cloned())
+            .collect::<Vec<(String, u64)>>();
+        events.sort();
+        Ok(events)
+    }
+}
+
+// (stream, event) -> key and version of the events of the event type, ordered by key and
+// version. Kept in memory by the stores so that the events of an event type can be read
+// without reading the events of all stream keys of the stream
+#[derive(Debug, Default)]
+pub struct EventTypeIndex(RwLock<HashMap<(String, String), Postings>>);
+
+// key and version of the events of an event type
+type Postings = BTreeSet<(String, u64)>;
+
+impl EventTypeIndex {
+    pub fn insert(&self, event: &Event) -> Result<(), StorageError> {
+        self.0
+            .write()
+            .map_err(|_| StorageError::new("failed to write to event type index"))?
+            .entry((event.stream.clone(), event.event.clone()))
+            .or_default()
+            .insert((event.key.clone(), event.version));
+        Ok(())
+    }
+
+    // key and version of the events of the stream of any of the event types, ordered by
+    // key and version
+    pub fn postings(
+        &self,
+        stream_name: &str,
+        event_names: &[String],
+    ) -> Result<Vec<(String, u64)>, StorageError> {
+        let types = self
+            .0
+            .read()
+            .map_err(|_| StorageError::new("failed to read event type index"))?;
+        let mut postings = vec![];
+        for event_name in event_names {
+            if let Some(events) = types.get(&(stream_name.to_string(), event_name.to_string())) {
+                postings.extend(events.iter().cloned());
+            }
+        }
+        postings.sort();
+        postings.dedup();
+        Ok(postings)
+    }
+}
+
+// the key and version of events, ordered by key and version, as runs of consecutive
+// versions of a stream key (key, from version, to version), so the events of a run can be
+// read together
+pub fn version_runs(events: Vec<(String, u64)>) -> Vec<(String, u64, u64)> {
+    let mut runs: Vec<(String, u64, u64)> = vec![];
+    for (key, version) in events {
+        match runs.last_mut() {
+            Some((last_key, _, to)) if *last_key == key && *to + 1 == version => *to = version,
+            _ => runs.push((key, version, version)),
+        }
+    }
+    runs
+}
+
+// (stream, event, attribute) -> value -> key and version of the events with the value.
+// Kept in memory by the stores and built from the events when an index is created or the
+// store is opened. Null values are not indexed, as null is not equal to or in the range of
+// any value
+#[derive(Debug, Default)]
+pub struct AttributeIndexes(RwLock<HashMap<(String, String, String), AttributeIndex>>);
+
+type AttributeIndex = BTreeMap<IndexValue, BTreeSet<(String, u64)>>;
+
+impl AttributeIndexes {
+    // the events are not indexed until they are inserted, an index that already exists is
+    // kept as it is
+    pub fn create(
+        &self,
+        stream_name: &str,
+        event_name: &str,
+        attribute_name: &str,
+    ) -> Result<(), StorageError> {
+        self.0
+            .write()
+            .map_err(|_| StorageError::new("failed to write to attribute indexes"))?
+            .entry((
+                stream_name.to_string(),
+                event_name.to_string(),
+                attribute_name.to_string(),
+            ))
+            .or_default();
+        Ok(())
+    }
+
+    // adds the event to the indexes of its event type, inserting an event that is already
+    // indexed does nothing
+    pub fn insert(&self, event: &Event) -> Result<(), StorageError> {
+        let mut indexes = self
+            .0
+            .write()
+            .map_err(|_| StorageError::new("failed to write to attribute indexes"))?;
+        for attribute in event.attributes.iter() {
+            if attribute.value == Value::Null {
+                continue;
+            }
+            let Some(index) = indexes.get_mut(&(
+                event.stream.clone(),
+                event.event.clone(),
+                attribute.name.clone(),
+            )) else {
+                continue;
+            };
+            index
+                .entry(IndexValue(attribute.value.clone()))
+                .or_default()
+                .insert((event.key.clone(), event.version));
+        }
+        Ok(())
+    }
+
+    // key and version of the events with a value in the range, ordered by key and version
+    pub fn range(
+        &self,
+        stream_name: &str,
+        event_name: &str,
+        attribute_name: &str,
+        (from, to): (Bound<&Value>, Bound<&Value>),
+    ) -> Result<Vec<(String, u64)>, StorageError> {
+        let indexes = self
+            .0
+            .read()
+            .map_err(|_| StorageError::new("failed to read attribute indexes"))?;
+        let index = indexes
+            .get(&(
+                stream_name.to_string(),
+                event_name.to_string(),
+                attribute_name.to_string(),
+            ))
+            .ok_or_else(|| {
+                StorageError::new(&format!(
+                    "no index on {}.{}.{}",
+                    stream_name, event_name, attribute_name
+                ))
+            })?;
+
+        let bound = |bound: Bound<&Value>| bound.map(|value| IndexValue(value.clone()));
+        let (from, to) = (bound(from), bound(to));
+        // a range that ends before it starts is empty, BTreeMap::range panics on it
+        let is_empty = match (&from, &to) {
+            (Bound::Included(from), Bound::Included(to)) => from > to,
+            (
+                Bound::Included(from) | Bound::Excluded(from),
+                Bound::Included(to) | Bound::Excluded(to),
+            ) => from >= to,
+            _ => false,
+        };
+        if is_empty {
+            return Ok(vec![]);
+        }
+
+        let mut events = index
+            .range((from, to))
+            .flat_map(|(_, events)| events.iter().cloned())
+            .collect::<Vec<(String, u64)>>();
+        events.sort();
+        Ok(events)
+    }
+}
+
+// values of an index are ordered by Value::total_cmp, so ints and floats of the same
+// number are the same value like they are equal with =
+#[derive(Debug, Clone)]
+struct IndexValue(Value);
+
+impl PartialEq for IndexValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for IndexValue {}
+
+impl PartialOrd for IndexValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for IndexValue {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+// the stream key was not at the version that the added event was expected to follow
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionConflict {
+    pub expected_version: u64,
+    pub current_version: u64,
+}
+
+impl fmt::Display for VersionConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "version conflict, expected version {} but stream key is at version {}",
+            self.expected_version, self.current_version
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct StorageError {
+    message: String,
+    conflict: Option<VersionConflict>,
+}
+
+impl StorageError {
+    pub fn new(message: &str) -> Self {
+        StorageError {
+            message: message.to_string(),
+            conflict: None,
+        }
+    }
+
+    pub fn conflict(conflict: VersionConflict) -> Self {
+        StorageError {
+            message: conflict.to_string(),
+            conflict: Some(conflict),
+        }
+    }
+
+    pub fn version_conflict(&self) -> Option<VersionConflict> {
+        self.conflict
+    }
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for StorageError {}
+
+#[cfg(test)]
+mod storage_test {
+    use super::file::FileStore;
+    use super::memory::MemoryStore;
+    use super::*;
+    use crate::event::{Attribute, Value};
+    use std::path::PathBuf;
+
+    fn event(key: &str, version: u64, event: &str, amount: i64, position: u64) -> Event {
+        Event {
+            position,
+            ..Event::new(
+                "account".to_string(),
+                key.to_string(),
+                event.to_string(),
+                version,
+                1700000000000 + position as u128,
+                vec![Attribute {
+                    name: "amount".to_string(),
+                    value: Value::Int(amount),
+                }],
+            )
+        }
+    }
+
+    // the events in the order they are added, with the versions and positions they get
+    fn events() -> Vec<Event> {
+        let mut events = (1..=5)
+            .map(|version| event("123", version, "MoneyDeposited", 100, version))
+            .collect::<Vec<Event>>();
+        events.push(event("456", 1, "MoneyDeposited", 100, 6));
+        events.push(event("456", 2, "MoneyDeposited", 50, 7));
+        events.push(event("123", 6, "MoneyWithdrawn", 100, 8));
+        events
+    }
+
+    // the events ordered by key and version
+    fn by_key(mut events: Vec<Event>) -> Vec<Event> {
+        events.sort_by(|a, b| (&a.key, a.version).cmp(&(&b.key, b.version)));
+        events
+    }
+
+    // appends the events, the version and position are set by the store
+    fn add_events(store: &dyn EventStore, events: &[Event]) {
+        for event in events {
+            let added = Event {
+                version: 0,
+                position: 0,
+                ..event.clone()
+            };
+            assert_eq!(
+                event.position,
+                store.append(added, event.version - 1).unwrap()
+            );
+        }
+    }
+
+    fn schema_changes() -> Vec<SchemaChange> {
+        vec![
+            SchemaChange::Stream {
+                name: "account".to_string(),
+            },
+            SchemaChange::Event {
+                name: "MoneyDeposited".to_string(),
+                stream_name: "account".to_string(),
+            },
+            SchemaChange::Attribute {
+                name: "amount".to_string(),
+                event_name: "MoneyDeposited".to_string(),
+                stream_name: "account".to_string(),
+                data_type: "int".to_string(),
+                required: true,
+            },
+        ]
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("adb-storage-test-{}", std::process::id()))
+            .join(name);
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    // the same behaviour is expected of all stores, so the check is run against a new
+    // store of each kind
+    fn check_stores(name: &str, check: fn(&dyn EventStore)) {
+        check(&MemoryStore::new());
+        check(&FileStore::open(&test_dir(name)).unwrap());
+    }
+
+    #[test]
+    fn test_versions() {
+        check_stores("versions", |store| {
+            assert_eq!(0, store.last_version("account", "123").unwrap());
+            assert_eq!(None, store.get_events("account", "123").unwrap());
+
+            add_events(store, &events());
+            assert_eq!(6, store.last_version("account", "123").unwrap());
+            let expected = by_key(events())[..6].to_vec();
+            assert_eq!(Some(expected), store.get_events("account", "123").unwrap());
+
+            for expected_version in [3, 7] {
+                let conflict = store
+                    .append(events()[0].clone(), expected_version)
+                    .unwrap_err()
+                    .version_conflict();
+                assert_eq!(
+                    Some(VersionConflict {
+                        expected_version,
+                        current_version: 6
+                    }),
+                    conflict
+                );
+            }
+            assert_eq!(6, store.last_version("account", "123").unwrap());
+
+            assert_eq!(
+                events()[1..4].to_vec(),
+                store.read_range("account", "123", 2, 4).unwrap()
+            );
+            assert!(store
+                .read_range("account", "123", 7, 10)
+                .unwrap()
+                .is_empty());
+            assert!(store
+                .read_range("account", "789", 1, 10)
+                .unwrap()
+                .is_empty());
+
+            assert_eq!(
+                vec!["123".to_string(), "456".to_string()],
+                store.stream_keys("account").unwrap()
+            );
+            assert!(store.stream_keys("loan").unwrap().is_empty());
+        });
+    }
+
+    #[test]
+    fn test_positions() {
+        check_stores("positions", |store| {
+            assert!(store.read_all(1, usize::MAX).unwrap().is_empty());
+
+            add_events(store, &events());
+            assert_eq!(events(), store.read_all(1, usize::MAX).unwrap());
+            assert_eq!(events()[5..7], store.read_all(6, 2).unwrap());
+            assert!(store.read_all(9, usize::MAX).unwrap().is_empty());
+        });
+    }
+
+    #[test]
+    fn test_time_range() {
+        check_stores("time-range", |store| {
+            add_events(store, &events());
+            assert_eq!(
+                events()[1..3].to_vec(),
+                store
+                    .read_time_range("account", 1700000000002, 1700000000003)
+                    .unwrap()
+            );
+            assert_eq!(
+                vec![events()[0].clone()],
+                store.read_time_range("account", 0, 1700000000001).unwrap()
+            );
+            assert_eq!(
+                by_key(events()[5..].to_vec()),
+                store
+                    .read_time_range("account", 1700000000006, u128::MAX)
+                    .unwrap()
+            );
+            assert!(store
+                .read_time_range("account", 1700000000003, 1700000000002)
+                .unwrap()
+                .is_empty());
+            assert!(store
+                .read_time_range("loan", 0, u128::MAX)
+                .unwrap()
+                .is_empty());
+        });
+    }
+
+    #[test]
+    fn test_event_types() {
+        check_stores("event-types", |store| {
+            add_events(store, &events());
+            assert_eq!(
+                vec![events()[7].clone()],
+                store
+                    .read_event_types("account", &["MoneyWithdrawn".to_string()])
+                    .unwrap()
+            );
+            let event_names = ["MoneyWithdrawn".to_string(), "MoneyDeposited".to_string()];
+            assert_eq!(
+                by_key(events()),
+                store.read_event_types("account", &event_names).unwrap()
+            );
+            assert!(store
+                .read_event_types("account", &["MoneyMoved".to_string()])
+                .unwrap()
+                .is_empty());
+            assert!(store.read_event_types("account", &[]).unwrap().is_empty());
+            assert!(store
+                .read_event_types("loan", &event_names)
+                .unwrap()
+                .is_empty());
+        });
+    }
+
+    #[test]
+    fn test_index() {
+        check_stores("index", |store| {
+            // events added both before and after the index is created are indexed
+            add_events(store, &events()[..6]);
+            store
+                .create_index("account", "MoneyDeposited", "amount")
+                .unwrap();
+            add_events(store, &events()[6..]);
+
+            let range = (
+                Bound::Included(&Value::Int(100)),
+                Bound::Included(&Value::Int(100)),
+            );
+            assert_eq!(
+                events()[..6].to_vec(),
+                store
+                    .read_index("account", "MoneyDeposited", "amount", range)
+                    .unwrap()
+            );
+            let range = (Bound::Unbounded, Bound::Excluded(&Value::Float(100.0)));
+            assert_eq!(
+                vec![events()[6].clone()],
+                store
+                    .read_index("account", "MoneyDeposited", "amount", range)
+                    .unwrap()
+            );
+            let range = (
+                Bound::Excluded(&Value::Int(100)),
+                Bound::Excluded(&Value::Int(100)),
+            );
+            assert!(store
+                .read_index("account", "MoneyDeposited", "amount", range)
+                .unwrap()
+                .is_empty());
+            assert!(store
+                .read_index("account", "MoneyWithdrawn", "amount", range)
+                .is_err());
+        });
+    }
+
+    #[test]
+    fn test_snapshot() {
+        check_stores("snapshot", |store| {
+            assert!(store.snapshot().unwrap().is_empty());
+
+            add_events(store, &events());
+            assert_eq!(by_key(events()), by_key(store.snapshot().unwrap()));
+        });
+    }
+
+    #[test]
+    fn test_schema_changes() {
+        check_stores("schema-changes", |store| {
+            for change in schema_changes() {
+                store.persist_schema_change(&change).unwrap();
+            }
+            assert_eq!(schema_changes(), store.schema_changes().unwrap());
+        });
+    }
+
+    #[test]
+    fn test_file_store_reopen() {
+        let dir = test_dir("reopen");
+        let store = FileStore::open(&dir).unwrap();
+        add_events(&store, &events());
+        for change in schema_changes() {
+            store.persist_schema_change(&change).unwrap();
+        }
+        drop(store);
+
+        let store = FileStore::open(&dir).unwrap();
+        assert_eq!(6, store.last_version("account", "123").unwrap());
+        assert_eq!(events(), store.read_all(1, usize::MAX).unwrap());
+        // the time and event type indexes are built again
+        assert_eq!(
+            vec![events()[0].clone()],
+            store.read_time_range("account", 0, 1700000000001).unwrap()
+        );
+        assert_eq!(
+            vec![events()[7].clone()],
+            store
+                .read_event_types("account", &["MoneyWithdrawn".to_string()])
+                .unwrap()
+        );
+        assert_eq!(schema_changes(), store.schema_changes().unwrap());
+
+        // new events continue from the last version and position
+        let event = event("123", 7, "MoneyDeposited", 10, 9);
+        add_events(&store, std::slice::from_ref(&event));
+        assert_eq!(vec![event], store.read_all(9, usize::MAX).unwrap());
+    }
+}
@@ -0,0 +1,1266 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::ops::Bound;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::codec::{decode_event, decode_frame, encode_event, encode_frame, CodecError, Reader};
+use crate::event::{Event, Value};
+use crate::storage::{
+    version_range, version_runs, AttributeIndexes, EventTypeIndex, StorageError, TimeIndex,
+    VersionConflict,
+};
+
+pub const DEFAULT_SEGMENT_SIZE: u64 = 64 * 1024 * 1024;
+
+// segment id (u32), offset in segment (u64), length of frame (u32)
+const INDEX_ENTRY_SIZE: usize = 16;
+
+// Events are appended, as frames, to the active segment file in <dir>/segments. When the
+// active segment is full a new one is created. Each stream key has an index file in
+// <dir>/index with the location of its events, the n:th entry is the location of the
+// event with version n.
+//
+// The segment and index entry of an event are written and flushed while holding the
+// lock of the active segment, so the index entries are always written in the same order
+// as the events in the segments. If we crash between the two writes only the events at
+// the end of the segments can be missing in the indexes, and they are added back when the
+// store is opened. If writing the index entry fails the event is removed from the segment
+// again. Should that fail as well no more events are written until the store is opened
+// again, which drops index entries of events that are not in the segments.
+//
+// The position of an event is the order of its frame across the segments, so positions are
+// given out in the order the events are written while holding the lock of the active
+// segment. The locations by position, the time index and the event type index are kept in
+// memory. When a segment is full the location, stream, key, version, event type and
+// timestamp of its events are written to a segment index in <dir>/index/segments, which
+// checkpoints the segment. When the store is opened the in memory indexes are built from
+// the segment indexes, only the segments after the last checkpointed segment are read.
+// The attribute indexes are also kept in memory, they are built from the segments when
+// they are created.
+#[derive(Debug)]
+pub struct SegmentStore {
+    dir: PathBuf,
+    segment_size: u64,
+    index: RwLock<Index>,
+    times: TimeIndex,
+    types: EventTypeIndex,
+    indexes: AttributeIndexes,
+    active: Mutex<ActiveSegment>,
+    // location of the event at each position, the event at position n is at n - 1
+    positions: RwLock<Vec<Location>>,
+    // read handles for all segments
+    segments: RwLock<HashMap<u32, Arc<File>>>,
+}
+
+// (stream, key) -> locations of the events of the stream key
+type Index = HashMap<(String, String), Arc<RwLock<KeyIndex>>>;
+
+// the location and event, without attributes, of each event in a segment
+type SegmentIndex = Vec<(Location, Event)>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Location {
+    segment: u32,
+    offset: u64,
+    len: u32,
+}
+
+impl Location {
+    fn encode(&self) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(INDEX_ENTRY_SIZE);
+        buffer.extend_from_slice(&self.segment.to_le_bytes());
+        buffer.extend_from_slice(&self.offset.to_le_bytes());
+        buffer.extend_from_slice(&self.len.to_le_bytes());
+        buffer
+    }
+
+    fn decode(bytes: &[u8]) -> Location {
+        Location {
+            segment: u32::from_le_bytes(bytes[0..4].try_into().unwrap()),
+            offset: u64::from_le_bytes(bytes[4..12].try_into().unwrap()),
+            len: u32::from_le_bytes(bytes[12..16].try_into().unwrap()),
+        }
+    }
+
+    // position right after the event
+    fn end(&self) -> (u32, u64) {
+        (self.segment, self.offset + self.len as u64)
+    }
+}
+
+#[derive(Debug)]
+struct KeyIndex {
+    locations: Vec<Location>,
+    file: File,
+}
+
+impl KeyIndex {
+    fn create(dir: &Path, stream_name: &str, key: &str) -> Result<Self, StorageError> {
+        Ok(KeyIndex {
+            locations: vec![],
+            file: create_file(&index_path(dir, stream_name, key))?,
+        })
+    }
+
+    fn push(&mut self, location: Location) -> Result<(), StorageError> {
+        self.file
+            .write_all(&location.encode())
+            .map_err(|e| StorageError::new(&format!("failed to write index: {}", e)))?;
+        self.file
+            .sync_data()
+            .map_err(|e| StorageError::new(&format!("failed to sync index: {}", e)))?;
+        self.locations.push(location);
+        Ok(())
+    }
+
+    // drops everything in the index file after the entries of the locations, returns
+    // false if that fails
+    fn truncate(&mut self) -> bool {
+        let len = (self.locations.len() * INDEX_ENTRY_SIZE) as u64;
+        self.file
+            .set_len(len)
+            .and_then(|_| self.file.sync_data())
+            .is_ok()
+    }
+}
+
+#[derive(Debug)]
+struct ActiveSegment {
+    id: u32,
+    file: File,
+    len: u64,
+    // set when a failed write could not be undone, see SegmentStore
+    failed: bool,
+    // written when the segment is full
+    events: SegmentIndex,
+}
+
+impl ActiveSegment {
+    // drops everything in the segment after len, returns false if that fails
+    fn truncate(&mut self, len: u64) -> bool {
+        match self.file.set_len(len).and_then(|_| self.file.sync_data()) {
+            Ok(_) => {
+                self.len = len;
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+impl SegmentStore {
+    pub fn open(dir: &Path, segment_size: u64) -> Result<Self, StorageError> {
+        for sub_dir in [segments_dir(dir), index_dir(dir)] {
+            fs::create_dir_all(&sub_dir).map_err(|e| {
+                StorageError::new(&format!("failed to create directory {:?}: {}", sub_dir, e))
+            })?;
+        }
+
+        let mut segment_ids = read_segment_ids(dir)?;
+        if segment_ids.is_empty() {
+            create_file(&segment_path(dir, 0))?;
+            segment_ids.push(0);
+        }
+
+        let mut index = read_indexes(dir)?;
+        recover(dir, &mut index, &segment_ids)?;
+        let times = TimeIndex::default();
+        let types = EventTypeIndex::default();
+        let (positions, events) = read_segment_indexes(dir, &segment_ids, &times, &types)?;
+
+        let last_id = *segment_ids.last().unwrap();
+        let file = open_file(&segment_path(dir, last_id))?;
+        let len = file
+            .metadata()
+            .map_err(|e| StorageError::new(&format!("failed to read segment size: {}", e)))?
+            .len();
+
+        let mut segments = HashMap::new();
+        for id in segment_ids {
+            let file = File::open(segment_path(dir, id))
+                .map_err(|e| StorageError::new(&format!("failed to open segment: {}", e)))?;
+            segments.insert(id, Arc::new(file));
+        }
+
+        Ok(SegmentStore {
+            dir: dir.to_path_buf(),
+            segment_size,
+            index: RwLock::new(
+                index
+                    .into_iter()
+                    .map(|(k, v)| (k, Arc::new(RwLock::new(v))))
+                    .collect(),
+            ),
+            times,
+            types,
+            indexes: AttributeIndexes::default(),
+            active: Mutex::new(ActiveSegment {
+                id: last_id,
+                file,
+                len,
+                failed: false,
+                events,
+            }),
+            positions: RwLock::new(positions),
+            segments: RwLock::new(segments),
+        })
+    }
+
+    // returns the index of the stream key, creating its index file if this is the first
+    // event of the stream key
+    fn key_index(
+        &self,
+        stream_name: &str,
+        key: &str,
+    ) -> Result<Arc<RwLock<KeyIndex>>, StorageError> {
+        let stream_key = (stream_name.to_string(), key.to_string());
+
+        if let Some(key_index) = self
+            .index
+            .read()
+            .map_err(|_| StorageError::new("failed to read index"))?
+            .get(&stream_key)
+        {
+            return Ok(key_index.clone());
+        }
+
+        let mut index = self
+            .index
+            .write()
+            .map_err(|_| StorageError::new("failed to write to index"))?;
+
+        if let Some(key_index) = index.get(&stream_key) {
+            return Ok(key_index.clone());
+        }
+
+        let key_index = Arc::new(RwLock::new(KeyIndex::create(&self.dir, stream_name, key)?));
+        index.insert(stream_key, key_index.clone());
+        Ok(key_index)
+    }
+
+    // writes the frame to the active segment, creating a new segment if it does not fit
+    fn write_frame(
+        &self,
+        active: &mut ActiveSegment,
+        frame: &[u8],
+    ) -> Result<Location, StorageError> {
+        if active.failed {
+            return Err(StorageError::new(
+                "a failed write could not be undone, the store must be opened again",
+            ));
+        }
+        if active.len > 0 && active.len + frame.len() as u64 > self.segment_size {
+            self.roll(active)?;
+        }
+
+        let location = Location {
+            segment: active.id,
+            offset: active.len,
+            len: frame.len() as u32,
+        };
+
+        if let Err(e) = active
+            .file
+            .write_all(frame)
+            .and_then(|_| active.file.sync_data())
+        {
+            // drop whatever part of the frame that made it to the segment
+            if !active.truncate(location.offset) {
+                active.failed = true;
+            }
+            return Err(StorageError::new(&format!(
+                "failed to write to segment: {}",
+                e
+            )));
+        }
+        active.len += frame.len() as u64;
+
+        Ok(location)
+    }
+
+    // checkpoints the active segment and creates the next one
+    fn roll(&self, active: &mut ActiveSegment) -> Result<(), StorageError> {
+        write_segment_index(&self.dir, active.id, &active.events)?;
+
+        let id = active.id + 1;
+        let path = segment_path(&self.dir, id);
+        let file = create_file(&path)?;
+        let read_handle = File::open(&path)
+            .map_err(|e| StorageError::new(&format!("failed to open segment: {}", e)))?;
+
+        self.segments
+            .write()
+            .map_err(|_| StorageError::new("failed to write segments"))?
+            .insert(id, Arc::new(read_handle));
+
+        *active = ActiveSegment {
+            id,
+            file,
+            len: 0,
+            failed: false,
+            events: vec![],
+        };
+        Ok(())
+    }
+
+    fn read_event(&self, location: &Location) -> Result<Event, StorageError> {
+        let segment = self
+            .segments
+            .read()
+            .map_err(|_| StorageError::new("failed to read segments"))?
+            .get(&location.segment)
+            .cloned()
+            .ok_or_else(|| StorageError::new(&format!("segment {} not found", location.segment)))?;
+
+        let mut buffer = vec![0; location.len as usize];
+        segment
+            .read_exact_at(&mut buffer, location.offset)
+            .map_err(|e| StorageError::new(&format!("failed to read segment: {}", e)))?;
+
+        let (payload, _) = decode_frame(&buffer).ok_or_else(|| {
+            StorageError::new(&format!(
+                "corrupt event in segment {} at offset {}",
+                location.segment, location.offset
+            ))
+        })?;
+        let mut event = decode_event(&mut Reader::new(payload))
+            .map_err(|e| StorageError::new(&format!("failed to decode event: {}", e)))?;
+        event.position = self.position(location)?;
+        Ok(event)
+    }
+
+    // the locations are in the order of the positions, which is also the order of the
+    // segments and offsets
+    fn position(&self, location: &Location) -> Result<u64, StorageError> {
+        let positions = self
+            .positions
+            .read()
+            .map_err(|_| StorageError::new("failed to read positions"))?;
+        let i = positions.partition_point(|l| l.end() <= location.end());
+        match i > 0 && positions[i - 1] == *location {
+            true => Ok(i as u64),
+            false => Err(StorageError::new(&format!(
+                "no position for event in segment {} at offset {}",
+                location.segment, location.offset
+            ))),
+        }
+    }
+}
+
+impl SegmentStore {
+    // adds the event with version expected_version + 1 to the end of its stream key and
+    // returns its position, fails if the last version of the stream key is not
+    // expected_version
+    pub fn append(&self, mut event: Event, expected_version: u64) -> Result<u64, StorageError> {
+        let key_index = self.key_index(&event.stream, &event.key)?;
+        let mut key_index = key_index
+            .write()
+            .map_err(|_| StorageError::new("failed to write to stream"))?;
+
+        let last_version = key_index.locations.len() as u64;
+        if last_version != expected_version {
+            return Err(StorageError::conflict(VersionConflict {
+                expected_version,
+                current_version: last_version,
+            }));
+        }
+        event.version = expected_version + 1;
+
+        let mut payload = vec![];
+        encode_event(&mut payload, &event);
+        let frame = encode_frame(&payload);
+
+        let mut active = self
+            .active
+            .lock()
+            .map_err(|_| StorageError::new("failed to lock active segment"))?;
+        let location = self.write_frame(&mut active, &frame)?;
+        if let Err(e) = key_index.push(location) {
+            // the event is removed from the segment so the stream key keeps its version
+            let is_index_truncated = key_index.truncate();
+            if !(active.truncate(location.offset) && is_index_truncated) {
+                active.failed = true;
+            }
+            return Err(e);
+        }
+        let mut positions = self
+            .positions
+            .write()
+            .map_err(|_| StorageError::new("failed to write to positions"))?;
+        positions.push(location);
+        event.position = positions.len() as u64;
+        drop(positions);
+        active.events.push((
+            location,
+            Event {
+                attributes: vec![],
+                ..event.clone()
+            },
+        ));
+        self.times.insert(&event)?;
+        self.types.insert(&event)?;
+        self.indexes.insert(&event)?;
+
+        Ok(event.position)
+    }
+
+    fn get_key_index(
+        &self,
+        stream_name: &str,
+        key: &str,
+    ) -> Result<Option<Arc<RwLock<KeyIndex>>>, StorageError> {
+        Ok(self
+            .index
+            .read()
+            .map_err(|_| StorageError::new("failed to read index"))?
+            .get(&(stream_name.to_string(), key.to_string()))
+            .cloned())
+    }
+
+    pub fn last_version(&self, stream_name: &str, key: &str) -> Result<u64, StorageError> {
+        let Some(key_index) = self.get_key_index(stream_name, key)? else {
+            return Ok(0);
+        };
+
+        let version = key_index
+            .read()
+            .map_err(|_| StorageError::new("failed to read stream"))?
+            .locations
+            .len();
+        Ok(version as u64)
+    }
+
+    pub fn get_events(
+        &self,
+        stream_name: &str,
+        key: &str,
+    ) -> Result<Option<Vec<Event>>, StorageError> {
+        if self.get_key_index(stream_name, key)?.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(self.read_range(stream_name, key, 1, u64::MAX)?))
+    }
+
+    pub fn stream_keys(&self, stream_name: &str) -> Result<Vec<String>, StorageError> {
+        let mut keys = self
+            .index
+            .read()
+            .map_err(|_| StorageError::new("failed to read index"))?
+            .keys()
+            .filter(|(stream, _)| stream == stream_name)
+            .map(|(_, key)| key.clone())
+            .collect::<Vec<String>>();
+        keys.sort();
+        Ok(keys)
+    }
+
+    // only the locations of the versions in the range are read from the index, so only
+    // the requested events are read from the segments
+    pub fn read_range(
+        &self,
+        stream_name: &str,
+        key: &str,
+        from_version: u64,
+        to_version: u64,
+    ) -> Result<Vec<Event>, StorageError> {
+        let Some(key_index) = self.get_key_index(stream_name, key)? else {
+            return Ok(vec![]);
+        };
+
+        let locations = {
+            let key_index = key_index
+                .read()
+                .map_err(|_| StorageError::new("failed to read stream"))?;
+            let (from, to) = version_range(from_version, to_version, key_index.locations.len());
+            key_index.locations[from..to].to_vec()
+        };
+
+        locations
+            .iter()
+            .map(|location| self.read_event(location))
+            .collect()
+    }
+
+    pub fn read_time_range(
+        &self,
+        stream_name: &str,
+        from: u128,
+        to: u128,
+    ) -> Result<Vec<Event>, StorageError> {
+        let mut events = vec![];
+        for (key, from, to) in version_runs(self.times.range(stream_name, from, to)?) {
+            events.extend(self.read_range(stream_name, &key, from, to)?);
+        }
+        Ok(events)
+    }
+
+    pub fn read_event_types(
+        &self,
+        stream_name: &str,
+        event_names: &[String],
+    ) -> Result<Vec<Event>, StorageError> {
+        let mut events = vec![];
+        for (key, from, to) in version_runs(self.types.postings(stream_name, event_names)?) {
+            events.extend(self.read_range(stream_name, &key, from, to)?);
+        }
+        Ok(events)
+    }
+
+    // the locations are collected before the events are read, so the positions are not
+    // locked while reading from the segments
+    pub fn read_all(&self, from_position: u64, limit: usize) -> Result<Vec<Event>, StorageError> {
+        let locations = {
+            let positions = self
+                .positions
+                .read()
+                .map_err(|_| StorageError::new("failed to read positions"))?;
+            let from = (from_position.max(1) - 1).min(positions.len() as u64) as usize;
+            positions[from..]
+                .iter()
+                .take(limit)
+                .copied()
+                .collect::<Vec<Location>>()
+        };
+
+        locations
+            .iter()
+            .map(|location| self.read_event(location))
+            .collect()
+    }
+
+    // events added while the existing events are indexed are inserted by append, so the
+    // index is created before the events are read
+    pub fn create_index(
+        &self,
+        stream_name: &str,
+        event_name: &str,
+        attribute_name: &str,
+    ) -> Result<(), StorageError> {
+        self.indexes
+            .create(stream_name, event_name, attribute_name)?;
+        for key in self.stream_keys(stream_name)? {
+            for event in self.read_range(stream_name, &key, 1, u64::MAX)? {
+                self.indexes.insert(&event)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read_index(
+        &self,
+        stream_name: &str,
+        event_name: &str,
+        attribute_name: &str,
+        range: (Bound<&Value>, Bound<&Value>),
+    ) -> Result<Vec<Event>, StorageError> {
+        let mut events = vec![];
+        let postings = self
+            .indexes
+            .range(stream_name, event_name, attribute_name, range)?;
+        for (key, from, to) in version_runs(postings) {
+            events.extend(self.read_range(stream_name, &key, from, to)?);
+        }
+        Ok(events)
+    }
+
+    // the read locks of all stream keys are held at the same time while the locations of
+    // their events are collected, the events are read from the segments once the locks
+    // have been released
+    pub fn snapshot(&self) -> Result<Vec<Event>, StorageError> {
+        let locations = {
+            let index = self
+                .index
+                .read()
+                .map_err(|_| StorageError::new("failed to read index"))?;
+            let key_indexes = index
+                .values()
+                .map(|key_index| {
+                    key_index
+                        .read()
+                        .map_err(|_| StorageError::new("failed to read stream"))
+                })
+                .collect::<Result<Vec<_>, StorageError>>()?;
+            key_indexes
+                .iter()
+                .flat_map(|key_index| key_index.locations.iter().copied())
+                .collect::<Vec<Location>>()
+        };
+
+        locations
+            .iter()
+            .map(|location| self.read_event(location))
+            .collect()
+    }
+}
+
+fn segments_dir(dir: &Path) -> PathBuf {
+    dir.join("segments")
+}
+
+fn index_dir(dir: &Path) -> PathBuf {
+    dir.join("index")
+}
+
+fn segment_path(dir: &Path, id: u32) -> PathBuf {
+    segments_dir(dir).join(format!("{:08}.seg", id))
+}
+
+fn segment_index_path(dir: &Path, id: u32) -> PathBuf {
+    index_dir(dir)
+        .join("segments")
+        .join(format!("{:08}.idx", id))
+}
+
+// stream and key are hex encoded as they can contain characters that are not allowed in
+// file names
+fn index_path(dir: &Path, stream_name: &str, key: &str) -> PathBuf {
+    index_dir(dir).join(format!("{}.{}.idx", to_hex(stream_name), to_hex(key)))
+}
+
+fn to_hex(s: &str) -> String {
+    s.bytes().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Option<String> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    let bytes = (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+fn read_segment_ids(dir: &Path) -> Result<Vec<u32>, StorageError> {
+    let entries = fs::read_dir(segments_dir(dir))
+        .map_err(|e| StorageError::new(&format!("failed to list segments: {}", e)))?;
+
+    let mut ids = vec![];
+    for entry in entries {
+        let entry =
+            entry.map_err(|e| StorageError::new(&format!("failed to list segments: {}", e)))?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if let Some(id) = name
+            .strip_suffix(".seg")
+            .and_then(|id| id.parse::<u32>().ok())
+        {
+            ids.push(id);
+        }
+    }
+    ids.sort();
+    Ok(ids)
+}
+
+fn read_indexes(dir: &Path) -> Result<HashMap<(String, String), KeyIndex>, StorageError> {
+    let entries = fs::read_dir(index_dir(dir))
+        .map_err(|e| StorageError::new(&format!("failed to list indexes: {}", e)))?;
+
+    let mut indexes = HashMap::new();
+    for entry in entries {
+        let entry =
+            entry.map_err(|e| StorageError::new(&format!("failed to list indexes: {}", e)))?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let stream_key = name.strip_suffix(".idx").and_then(|name| {
+            let (stream, key) = name.split_once('.')?;
+            Some((from_hex(stream)?, from_hex(key)?))
+        });
+        let Some(stream_key) = stream_key else {
+            continue;
+        };
+
+        let mut file = open_file(&entry.path())?;
+        let mut buffer = vec![];
+        file.read_to_end(&mut buffer)
+            .map_err(|e| StorageError::new(&format!("failed to read index: {}", e)))?;
+
+        // a partially written entry is dropped
+        let valid_len = buffer.len() - buffer.len() % INDEX_ENTRY_SIZE;
+        if valid_len < buffer.len() {
+            file.set_len(valid_len as u64)
+                .map_err(|e| StorageError::new(&format!("failed to truncate index: {}", e)))?;
+        }
+
+        let locations = buffer[..valid_len]
+            .chunks(INDEX_ENTRY_SIZE)
+            .map(Location::decode)
+            .collect();
+        indexes.insert(stream_key, KeyIndex { locations, file });
+    }
+    Ok(indexes)
+}
+
+// drops the index entries of events that are not in the segments, indexes the events
+// written after the last indexed event and drops a torn event at the end of the last
+// segment
+fn recover(
+    dir: &Path,
+    index: &mut HashMap<(String, String), KeyIndex>,
+    segment_ids: &[u32],
+) -> Result<(), StorageError> {
+    let mut segment_lens = HashMap::new();
+    for id in segment_ids {
+        let len = fs::metadata(segment_path(dir, *id))
+            .map_err(|e| StorageError::new(&format!("failed to read segment size: {}", e)))?
+            .len();
+        segment_lens.insert(*id, len);
+    }
+    // the entries are in the order of the events in the segments, so only entries at the
+    // end of an index can be of events beyond the end of the segments
+    for key_index in index.values_mut() {
+        let n_locations = key_index.locations.len();
+        while let Some(location) = key_index.locations.last() {
+            let (segment, end) = location.end();
+            if end <= segment_lens.get(&segment).copied().unwrap_or(0) {
+                break;
+            }
+            key_index.locations.pop();
+        }
+        if key_index.locations.len() < n_locations && !key_index.truncate() {
+            return Err(StorageError::new("failed to truncate index"));
+        }
+    }
+
+    let last_id = *segment_ids.last().unwrap();
+    let indexed_end = index
+        .values()
+        .filter_map(|key_index| key_index.locations.last().map(|l| l.end()))
+        .max()
+        .unwrap_or((segment_ids[0], 0));
+
+    for id in segment_ids.iter().filter(|id| **id >= indexed_end.0) {
+        let path = segment_path(dir, *id);
+        let mut buffer = vec![];
+        open_file(&path)?
+            .read_to_end(&mut buffer)
+            .map_err(|e| StorageError::new(&format!("failed to read segment: {}", e)))?;
+
+        let mut offset = if *id == indexed_end.0 {
+            indexed_end.1 as usize
+        } else {
+            0
+        };
+        while let Some((payload, len)) = buffer.get(offset..).and_then(decode_frame) {
+            let event = decode_event(&mut Reader::new(payload))
+                .map_err(|e| StorageError::new(&format!("corrupt segment {}: {}", id, e)))?;
+
+            let key_index = match index.entry((event.stream.clone(), event.key.clone())) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    entry.insert(KeyIndex::create(dir, &event.stream, &event.key)?)
+                }
+            };
+            if event.version != key_index.locations.len() as u64 + 1 {
+                return Err(StorageError::new(&format!(
+                    "corrupt segment {}: event {} of {}:{} is not serial",
+                    id, event.version, event.stream, event.key
+                )));
+            }
+            key_index.push(Location {
+                segment: *id,
+                offset: offset as u64,
+                len: len as u32,
+            })?;
+
+            offset += len;
+        }
+
+        if offset < buffer.len() {
+            if *id != last_id {
+                return Err(StorageError::new(&format!(
+                    "corrupt segment {} at offset {}",
+                    id, offset
+                )));
+            }
+            let file = open_file(&path)?;
+            file.set_len(offset as u64)
+                .map_err(|e| StorageError::new(&format!("failed to truncate segment: {}", e)))?;
+            file.sync_all()
+                .map_err(|e| StorageError::new(&format!("failed to sync segment: {}", e)))?;
+        }
+    }
+
+    Ok(())
+}
+
+// inserts all events in the segments into the time and event type indexes and returns
+// their locations by position together with the segment index of the last segment. The
+// segment indexes of the checkpointed segments are read instead of the segments, a missing
+// or corrupt segment index is written again
+fn read_segment_indexes(
+    dir: &Path,
+    segment_ids: &[u32],
+    times: &TimeIndex,
+    types: &EventTypeIndex,
+) -> Result<(Vec<Location>, SegmentIndex), StorageError> {
+    let mut positions = vec![];
+    let last_id = *segment_ids.last().unwrap();
+    let mut last_events = vec![];
+    for id in segment_ids {
+        let events = match read_segment_index(dir, *id)? {
+            Some(events) if *id != last_id => events,
+            _ => {
+                let events = read_segment(dir, *id)?;
+                if *id != last_id {
+                    write_segment_index(dir, *id, &events)?;
+                }
+                events
+            }
+        };
+        for (location, event) in events.iter() {
+            times.insert(event)?;
+            types.insert(event)?;
+            positions.push(*location);
+        }
+        if *id == last_id {
+            last_events = events;
+        }
+    }
+    Ok((positions, last_events))
+}
+
+fn read_segment(dir: &Path, id: u32) -> Result<SegmentIndex, StorageError> {
+    let mut buffer = vec![];
+    open_file(&segment_path(dir, id))?
+        .read_to_end(&mut buffer)
+        .map_err(|e| StorageError::new(&format!("failed to read segment: {}", e)))?;
+
+    let mut events = vec![];
+    let mut offset = 0;
+    while let Some((payload, len)) = buffer.get(offset..).and_then(decode_frame) {
+        let event = decode_event(&mut Reader::new(payload))
+            .map_err(|e| StorageError::new(&format!("corrupt segment {}: {}", id, e)))?;
+        let location = Location {
+            segment: id,
+            offset: offset as u64,
+            len: len as u32,
+        };
+        events.push((
+            location,
+            Event {
+                attributes: vec![],
+                ..event
+            },
+        ));
+        offset += len;
+    }
+    Ok(events)
+}
+
+// a segment index is a single frame with the number of events followed by the offset,
+// length and event, without attributes, of each event in the segment. It is written to a
+// temporary file that is renamed once it has been flushed to disk
+fn write_segment_index(
+    dir: &Path,
+    id: u32,
+    events: &[(Location, Event)],
+) -> Result<(), StorageError> {
+    let mut payload = vec![];
+    payload.extend_from_slice(&(events.len() as u64).to_le_bytes());
+    for (location, event) in events {
+        payload.extend_from_slice(&location.offset.to_le_bytes());
+        payload.extend_from_slice(&location.len.to_le_bytes());
+        encode_event(&mut payload, event);
+    }
+
+    let path = segment_index_path(dir, id);
+    let tmp_path = path.with_extension("idx.tmp");
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| {
+            StorageError::new(&format!("failed to create directory {:?}: {}", dir, e))
+        })?;
+    }
+    let mut file = File::create(&tmp_path)
+        .map_err(|e| StorageError::new(&format!("failed to create {:?}: {}", tmp_path, e)))?;
+    file.write_all(&encode_frame(&payload))
+        .and_then(|_| file.sync_all())
+        .map_err(|e| StorageError::new(&format!("failed to write segment index: {}", e)))?;
+    fs::rename(&tmp_path, &path)
+        .map_err(|e| StorageError::new(&format!("failed to write segment index: {}", e)))
+}
+
+// the segment index of the segment, None if it has none or it is corrupt
+fn read_segment_index(dir: &Path, id: u32) -> Result<Option<SegmentIndex>, StorageError> {
+    let path = segment_index_path(dir, id);
+    let buffer = match fs::read(&path) {
+        Ok(buffer) => buffer,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(StorageError::new(&format!(
+                "failed to read {:?}: {}",
+                path, e
+            )))
+        }
+    };
+    let Some((payload, _)) = decode_frame(&buffer) else {
+        return Ok(None);
+    };
+
+    let mut reader = Reader::new(payload);
+    let decode = |reader: &mut Reader| -> Result<SegmentIndex, CodecError> {
+        let mut events = vec![];
+        for _ in 0..reader.u64()? {
+            let location = Location {
+                segment: id,
+                offset: reader.u64()?,
+                len: reader.u32()?,
+            };
+            events.push((location, decode_event(reader)?));
+        }
+        Ok(events)
+    };
+    Ok(decode(&mut reader).ok())
+}
+
+fn open_file(path: &Path) -> Result<File, StorageError> {
+    OpenOptions::new()
+        .read(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| StorageError::new(&format!("failed to open {:?}: {}", path, e)))
+}
+
+fn create_file(path: &Path) -> Result<File, StorageError> {
+    OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)
+        .map_err(|e| StorageError::new(&format!("failed to create {:?}: {}", path, e)))
+}
+
+#[cfg(test)]
+mod segment_test {
+    use super::*;
+    use crate::event::{Attribute, Value};
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("adb-segment-test-{}", std::process::id()))
+            .join(name);
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn event(key: &str, version: u64) -> Event {
+        Event::new(
+            "account".to_string(),
+            key.to_string(),
+            "MoneyDeposited".to_string(),
+            version,
+            1700000000000 + version as u128,
+            vec![Attribute {
+                name: "amount".to_string(),
+                value: Value::Int(version as i64 * 100),
+            }],
+        )
+    }
+
+    fn at(event: Event, position: u64) -> Event {
+        Event { position, ..event }
+    }
+
+    // the events of the keys are appended a version at a time, so the event of the i:th
+    // key with version v is at position (v - 1) * keys + i + 1
+    fn append_events(store: &SegmentStore, keys: &[&str], n: u64) {
+        for version in 1..=n {
+            for key in keys {
+                store.append(event(key, version), version - 1).unwrap();
+            }
+        }
+    }
+
+    fn assert_events(store: &SegmentStore, keys: &[&str], n: u64) {
+        for (i, key) in keys.iter().enumerate() {
+            let expected = (1..=n)
+                .map(|v| at(event(key, v), (v - 1) * keys.len() as u64 + i as u64 + 1))
+                .collect::<Vec<Event>>();
+            assert_eq!(Some(expected), store.get_events("account", key).unwrap());
+            assert_eq!(n, store.last_version("account", key).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_append_and_reopen() {
+        let dir = test_dir("reopen");
+
+        let store = SegmentStore::open(&dir, DEFAULT_SEGMENT_SIZE).unwrap();
+        append_events(&store, &["123", "456"], 5);
+        assert_events(&store, &["123", "456"], 5);
+        assert_eq!(None, store.get_events("account", "789").unwrap());
+        assert_eq!(0, store.last_version("account", "789").unwrap());
+        drop(store);
+
+        let store = SegmentStore::open(&dir, DEFAULT_SEGMENT_SIZE).unwrap();
+        assert_events(&store, &["123", "456"], 5);
+        store.append(event("123", 6), 5).unwrap();
+    }
+
+    #[test]
+    fn test_reject_non_serial_version() {
+        let dir = test_dir("serial");
+
+        let store = SegmentStore::open(&dir, DEFAULT_SEGMENT_SIZE).unwrap();
+        append_events(&store, &["123"], 2);
+        assert!(store.append(event("123", 2), 1).is_err());
+        assert!(store.append(event("123", 4), 3).is_err());
+        assert_events(&store, &["123"], 2);
+    }
+
+    #[test]
+    fn test_read_range() {
+        let dir = test_dir("range");
+
+        let store = SegmentStore::open(&dir, 256).unwrap();
+        append_events(&store, &["123"], 10);
+
+        let expected = (4..=6)
+            .map(|v| at(event("123", v), v))
+            .collect::<Vec<Event>>();
+        assert_eq!(expected, store.read_range("account", "123", 4, 6).unwrap());
+
+        let expected = (9..=10)
+            .map(|v| at(event("123", v), v))
+            .collect::<Vec<Event>>();
+        assert_eq!(expected, store.read_range("account", "123", 9, 20).unwrap());
+
+        assert!(store
+            .read_range("account", "123", 11, 20)
+            .unwrap()
+            .is_empty());
+        assert!(store
+            .read_range("account", "456", 1, 20)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_read_time_range() {
+        let dir = test_dir("time-range");
+
+        let store = SegmentStore::open(&dir, 256).unwrap();
+        append_events(&store, &["123", "456"], 5);
+
+        let expected = vec![
+            at(event("123", 2), 3),
+            at(event("123", 3), 5),
+            at(event("456", 2), 4),
+            at(event("456", 3), 6),
+        ];
+        assert_eq!(
+            expected,
+            store
+                .read_time_range("account", 1700000000002, 1700000000003)
+                .unwrap()
+        );
+        drop(store);
+
+        // the time index is built from the segments
+        let store = SegmentStore::open(&dir, 256).unwrap();
+        assert_eq!(
+            expected,
+            store
+                .read_time_range("account", 1700000000002, 1700000000003)
+                .unwrap()
+        );
+        store.append(event("123", 6), 5).unwrap();
+        assert_eq!(
+            vec![at(event("123", 6), 11)],
+            store
+                .read_time_range("account", 1700000000006, u128::MAX)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_read_all() {
+        let dir = test_dir("all");
+
+        // room for a couple of events per segment
+        let store = SegmentStore::open(&dir, 256).unwrap();
+        append_events(&store, &["123", "456"], 5);
+
+        let expected = (1..=5)
+            .flat_map(|v| [event("123", v), event("456", v)])
+            .zip(1..)
+            .map(|(event, position)| at(event, position))
+            .collect::<Vec<Event>>();
+        assert_eq!(expected, store.read_all(1, usize::MAX).unwrap());
+        assert_eq!(expected[3..6], store.read_all(4, 3).unwrap());
+        drop(store);
+
+        // the positions are the order of the events in the segments
+        let store = SegmentStore::open(&dir, 256).unwrap();
+        assert_eq!(expected, store.read_all(0, usize::MAX).unwrap());
+        store.append(event("123", 6), 5).unwrap();
+        assert_eq!(
+            vec![at(event("123", 6), 11)],
+            store.read_all(11, usize::MAX).unwrap()
+        );
+        assert!(store.read_all(12, usize::MAX).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_segments_are_rolled() {
+        let dir = test_dir("roll");
+
+        // room for a couple of events per segment
+        let store = SegmentStore::open(&dir, 256).unwrap();
+        append_events(&store, &["123", "456"], 10);
+        assert_events(&store, &["123", "456"], 10);
+        drop(store);
+
+        assert!(read_segment_ids(&dir).unwrap().len() > 1);
+
+        let store = SegmentStore::open(&dir, 256).unwrap();
+        assert_events(&store, &["123", "456"], 10);
+    }
+
+    #[test]
+    fn test_segments_are_checkpointed() {
+        let dir = test_dir("checkpoint");
+
+        let store = SegmentStore::open(&dir, 256).unwrap();
+        append_events(&store, &["123", "456"], 10);
+        let positions = store.positions.read().unwrap().clone();
+        drop(store);
+
+        // all segments but the active one are checkpointed
+        let segment_ids = read_segment_ids(&dir).unwrap();
+        let (last_id, full_ids) = segment_ids.split_last().unwrap();
+        for id in full_ids {
+            assert!(segment_index_path(&dir, *id).exists());
+        }
+        assert!(!segment_index_path(&dir, *last_id).exists());
+
+        // the checkpointed segments are not read, so the indexes are built even if the
+        // events of a segment can not be read
+        let mut segment = fs::read(segment_path(&dir, full_ids[0])).unwrap();
+        segment.iter_mut().for_each(|b| *b = 0);
+        fs::write(segment_path(&dir, full_ids[0]), &segment).unwrap();
+        let store = SegmentStore::open(&dir, 256).unwrap();
+        assert_eq!(positions, *store.positions.read().unwrap());
+        drop(store);
+
+        // a missing segment index is written again
+        fs::remove_file(segment_index_path(&dir, full_ids[1])).unwrap();
+        let store = SegmentStore::open(&dir, 256).unwrap();
+        assert_eq!(positions, *store.positions.read().unwrap());
+        assert!(segment_index_path(&dir, full_ids[1]).exists());
+        let expected = vec![at(event("123", 6), 11), at(event("456", 6), 12)];
+        assert_eq!(
+            expected,
+            store
+                .read_time_range("account", 1700000000006, 1700000000006)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_recover_events_missing_in_index() {
+        let dir = test_dir("recover-index");
+
+        let store = SegmentStore::open(&dir, 256).unwrap();
+        append_events(&store, &["123"], 5);
+        drop(store);
+
+        // simulate a crash after the event was written to the segment but before its
+        // index entry was completely written
+        let path = index_path(&dir, "account", "123");
+        let len = fs::metadata(&path).unwrap().len();
+        open_file(&path)
+            .unwrap()
+            .set_len(len - INDEX_ENTRY_SIZE as u64 - 3)
+            .unwrap();
+
+        let store = SegmentStore::open(&dir, 256).unwrap();
+        assert_events(&store, &["123"], 5);
+        store.append(event("123", 6), 5).unwrap();
+        drop(store);
+
+        let store = SegmentStore::open(&dir, 256).unwrap();
+        assert_events(&store, &["123"], 6);
+    }
+
+    #[test]
+    fn test_undo_append_when_index_write_fails() {
+        let dir = test_dir("undo-append");
+
+        let store = SegmentStore::open(&dir, 256).unwrap();
+        append_events(&store, &["123", "456"], 3);
+
+        // writing to a read only index file fails, and so does undoing the write
+        let key_index = store.get_key_index("account", "123").unwrap().unwrap();
+        key_index.write().unwrap().file = File::open(index_path(&dir, "account", "123")).unwrap();
+        assert!(store.append(event("123", 4), 3).is_err());
+        assert_eq!(3, store.last_version("account", "123").unwrap());
+        assert_eq!(6, store.read_all(1, usize::MAX).unwrap().len());
+        // no events are written until the store is opened again
+        assert!(store.append(event("456", 4), 3).is_err());
+        drop(store);
+
+        let store = SegmentStore::open(&dir, 256).unwrap();
+        assert_events(&store, &["123", "456"], 3);
+        store.append(event("123", 4), 3).unwrap();
+        store.append(event("456", 4), 3).unwrap();
+        drop(store);
+
+        let store = SegmentStore::open(&dir, 256).unwrap();
+        assert_events(&store, &["123", "456"], 4);
+    }
+
+    #[test]
+    fn test_recover_drops_index_entries_beyond_segments() {
+        let dir = test_dir("recover-segment");
+
+        let store = SegmentStore::open(&dir, DEFAULT_SEGMENT_SIZE).unwrap();
+        append_events(&store, &["123"], 5);
+        let location = store.positions.read().unwrap()[4];
+        drop(store);
+
+        // simulate a failed append where the event was removed from the segment but its
+        // index entry could not be removed
+        open_file(&segment_path(&dir, 0))
+            .unwrap()
+            .set_len(location.offset)
+            .unwrap();
+
+        let store = SegmentStore::open(&dir, DEFAULT_SEGMENT_SIZE).unwrap();
+        assert_events(&store, &["123"], 4);
+        store.append(event("123", 5), 4).unwrap();
+        drop(store);
+
+        let store = SegmentStore::open(&dir, DEFAULT_SEGMENT_SIZE).unwrap();
+        assert_events(&store, &["123"], 5);
+    }
+
+    #[test]
+    fn test_drop_torn_event() {
+        let dir = test_dir("torn");
+
+        let store = SegmentStore::open(&dir, DEFAULT_SEGMENT_SIZE).unwrap();
+        append_events(&store, &["123"], 3);
+        drop(store);
+
+        // simulate a crash in the middle of writing an event
+        let mut payload = vec![];
+        encode_event(&mut payload, &event("123", 4));
+        let frame = encode_frame(&payload);
+        open_file(&segment_path(&dir, 0))
+            .unwrap()
+            .write_all(&frame[..frame.len() - 5])
+            .unwrap();
+
+        let store = SegmentStore::open(&dir, DEFAULT_SEGMENT_SIZE).unwrap();
+        assert_events(&store, &["123"], 3);
+        store.append(event("123", 4), 3).unwrap();
+        drop(store);
+
+        let store = SegmentStore::open(&dir, DEFAULT_SEGMENT_SIZE).unwrap();
+        assert_events(&store, &["123"], 4);
+    }
+}
@@ -0,0 +1,284 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::db::{blocking, DBError, DB};
+use crate::event::Event;
+use crate::executor;
+use crate::planner::Subscription;
+use crate::storage::EventStore;
+
+// events are read from the store in batches of at most this many while catching up
+const BATCH_SIZE: usize = 1024;
+
+// A subscriber first catches up by reading the events already added from the store, in
+// batches, and then receives the events from the live events of the DB as they are added.
+// It starts receiving live events before the first batch is read, so the events added
+// while catching up are not missed, and the live events that were already read from the
+// store are skipped.
+//
+// Events are sent to the live subscribers after they are added, so an event can be
+// received before an event with a lower position that was added at the same time. The
+// events in between are then read from the store. A subscriber that falls too far behind,
+// e.g. when its client is slow, misses live events and catches up from the store again.
+pub struct Subscriber<S: EventStore> {
+    db: Arc<DB<S>>,
+    subscription: Subscription,
+    live_events: broadcast::Receiver<Event>,
+    // position of the next event to send
+    next_position: u64,
+    is_live: bool,
+    // live event received while the events before it are read, kept until it is returned
+    pending: Option<Event>,
+}
+
+impl<S: EventStore + 'static> Subscriber<S> {
+    pub fn new(db: Arc<DB<S>>, subscription: Subscription) -> Self {
+        Subscriber {
+            live_events: db.live_events(),
+            next_position: subscription.from_position.max(1),
+            db,
+            subscription,
+            is_live: false,
+            pending: None,
+        }
+    }
+
+    // the next events of the subscription in order of their positions, once it has caught
+    // up it waits until events are added. The position is only moved past events after
+    // they are returned, and a live event is kept until then, so a call that is cancelled
+    // does not skip any events
+    pub async fn next(&mut self) -> Result<Vec<Event>, SubscriptionError> {
+        loop {
+            let events = match self.is_live {
+                true => self.receive().await?,
+                false => self.catch_up().await?,
+            };
+            if let Some(event) = events.last() {
+                self.next_position = event.position + 1;
+            }
+
+            let mut subscribed = vec![];
+            for event in events {
+                if executor::is_subscribed(&self.subscription, &event)
+                    .map_err(|e| SubscriptionError::new(&e.to_string()))?
+                {
+                    subscribed.push(event);
+                }
+            }
+            if !subscribed.is_empty() {
+                return Ok(subscribed);
+            }
+        }
+    }
+
+    async fn catch_up(&mut self) -> Result<Vec<Event>, SubscriptionError> {
+        let events = self.read(self.next_position, BATCH_SIZE).await?;
+        self.is_live = events.len() < BATCH_SIZE;
+        Ok(events)
+    }
+
+    async fn receive(&mut self) -> Result<Vec<Event>, SubscriptionError> {
+        let position = match &self.pending {
+            Some(event) => event.position,
+            None => match self.live_events.recv().await {
+                Ok(event) if event.position < self.next_position => return Ok(vec![]),
+                Ok(event) => {
+                    let position = event.position;
+                    self.pending = Some(event);
+                    position
+                }
+                Err(RecvError::Lagged(_)) => {
+                    self.is_live = false;
+                    return Ok(vec![]);
+                }
+                Err(RecvError::Closed) => {
+                    return Err(SubscriptionError::new("live events were closed"))
+                }
+            },
+        };
+        let missing = (position - self.next_position) as usize;
+        let mut events = self.read(self.next_position, missing).await?;
+        events.extend(self.pending.take());
+        Ok(events)
+    }
+
+    async fn read(
+        &self,
+        from_position: u64,
+        limit: usize,
+    ) -> Result<Vec<Event>, SubscriptionError> {
+        if limit == 0 {
+            return Ok(vec![]);
+        }
+        Ok(blocking(&self.db, move |db| db.read_all(from_position, limit)).await?)
+    }
+}
+
+#[derive(Debug)]
+pub struct SubscriptionError {
+    message: String,
+}
+
+impl SubscriptionError {
+    fn new(message: &str) -> Self {
+        SubscriptionError {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for SubscriptionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for SubscriptionError {}
+
+impl From<DBError> for SubscriptionError {
+    fn from(e: DBError) -> SubscriptionError {
+        SubscriptionError::new(&e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod subscription_test {
+    use super::*;
+    use crate::{parser, planner};
+    use std::time::Duration;
+
+    fn subscriber(db: &Arc<DB>, cmd: &str) -> Subscriber<crate::storage::memory::MemoryStore> {
+        let trx = parser::parse(cmd).unwrap();
+        let mut plan = planner::plan(&trx, db.as_ref()).unwrap();
+        let planner::Operation::Subscribe { subscription } = plan.operations.remove(0) else {
+            panic!("'{}' did not plan a subscription", cmd)
+        };
+        Subscriber::new(db.clone(), subscription)
+    }
+
+    // position and key of the next events
+    async fn next(
+        subscriber: &mut Subscriber<crate::storage::memory::MemoryStore>,
+    ) -> Vec<(u64, String)> {
+        tokio::time::timeout(Duration::from_secs(5), subscriber.next())
+            .await
+            .expect("no events were received")
+            .unwrap()
+            .into_iter()
+            .map(|e| (e.position, e.key))
+            .collect()
+    }
+
+    fn positions(events: &[(u64, &str)]) -> Vec<(u64, String)> {
+        events
+            .iter()
+            .map(|(position, key)| (*position, key.to_string()))
+            .collect()
+    }
+
+    fn setup() -> Arc<DB> {
+        DB::with_schema(&[
+            "create stream account;",
+            "create event MoneyDeposited(amount int) on account;",
+            "create event MoneyWithdrawn(amount int) on account;",
+            "create stream user;",
+            "create event UserCreated(name string) on user;",
+        ])
+    }
+
+    #[tokio::test]
+    async fn test_catch_up_and_live() {
+        let db = setup();
+        for cmd in [
+            r#"add MoneyDeposited(amount=100) to account(id="1");"#,
+            r#"add UserCreated(name="axel") to user(id="1");"#,
+            r#"add MoneyDeposited(amount=50) to account(id="2");"#,
+            r#"add MoneyWithdrawn(amount=20) to account(id="1");"#,
+        ] {
+            db.run(cmd);
+        }
+
+        let mut all = subscriber(&db, "subscribe to $all from position 2;");
+        let mut account = subscriber(&db, "subscribe to account;");
+        let mut withdrawals = subscriber(
+            &db,
+            r#"subscribe to account where key = "1" and event = "MoneyWithdrawn";"#,
+        );
+        assert_eq!(
+            positions(&[(2, "1"), (3, "2"), (4, "1")]),
+            next(&mut all).await
+        );
+        assert_eq!(
+            positions(&[(1, "1"), (3, "2"), (4, "1")]),
+            next(&mut account).await
+        );
+        assert_eq!(positions(&[(4, "1")]), next(&mut withdrawals).await);
+
+        // the events added from now on are received live
+        db.run(r#"add UserCreated(name="bob") to user(id="2");"#);
+        db.run(r#"add MoneyWithdrawn(amount=10) to account(id="1");"#);
+        assert_eq!(positions(&[(5, "2")]), next(&mut all).await);
+        assert_eq!(positions(&[(6, "1")]), next(&mut all).await);
+        assert_eq!(positions(&[(6, "1")]), next(&mut account).await);
+        assert_eq!(positions(&[(6, "1")]), next(&mut withdrawals).await);
+    }
+
+    #[tokio::test]
+    async fn test_catch_up_after_falling_behind() {
+        let db = setup();
+        let mut subscriber = subscriber(&db, "subscribe to account;");
+
+        // more events are added than the live events can hold while the subscriber is
+        // not reading
+        let n = 2 * BATCH_SIZE as u64 + 10;
+        for i in 1..=n {
+            db.run(&format!(
+                r#"add MoneyDeposited(amount={}) to account(id="1");"#,
+                i
+            ));
+        }
+
+        let mut received = vec![];
+        while (received.len() as u64) < n {
+            received.extend(next(&mut subscriber).await);
+        }
+        let expected = (1..=n)
+            .map(|position| (position, "1".to_string()))
+            .collect::<Vec<(u64, String)>>();
+        assert_eq!(expected, received);
+
+        db.run(r#"add MoneyDeposited(amount=1) to account(id="2");"#);
+        assert_eq!(positions(&[(n + 1, "2")]), next(&mut subscriber).await);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_while_reading_missing_events() {
+        let db = setup();
+        for id in ["1", "2"] {
+            db.run(&format!(
+                r#"add MoneyDeposited(amount=1) to account(id="{}");"#,
+                id
+            ));
+        }
+        // as if the subscriber had caught up before the events were added, so they are
+        // read when the next live event is received
+        let mut subscriber = subscriber(&db, "subscribe to account;");
+        subscriber.is_live = true;
+        db.run(r#"add MoneyDeposited(amount=1) to account(id="3");"#);
+
+        // the live event is received and the call is cancelled while the events before it
+        // are read
+        tokio::select! {
+            biased;
+            events = subscriber.next() => panic!("the call was not cancelled, got {:?}", events),
+            _ = std::future::ready(()) => {}
+        }
+        assert_eq!(
+            positions(&[(1, "1"), (2, "2"), (3, "3")]),
+            next(&mut subscriber).await
+        );
+    }
+}
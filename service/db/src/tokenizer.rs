@@ -43,6 +43,15 @@ impl<'a> Tokens<'a> {
                 break;
             }
 
+            // a string literal is read as a whole, so it can contain any character
+            if is_quote(&c) && buffer.is_empty() {
+                return self.read_string_literal();
+            }
+
+            if is_timestamp_quote(&c) && buffer.is_empty() {
+                return self.read_timestamp_literal();
+            }
+
             if is_seperator(&c) {
                 return Ok(Token::Seperator);
             }
@@ -67,6 +76,14 @@ impl<'a> Tokens<'a> {
                 return Ok(Token::GroupEnd);
             }
 
+            if is_pattern_start(&c) {
+                return Ok(Token::PatternStart);
+            }
+
+            if is_pattern_end(&c) {
+                return Ok(Token::PatternEnd);
+            }
+
             if is_operator(&c) {
                 // we peek on next char to see if operator is a two character operator
                 // e.g. >=
@@ -76,9 +93,9 @@ impl<'a> Tokens<'a> {
                     char_position: self.current_char_idx,
                 })?;
 
-                // if next char is not an operator and char is "="
+                // if char is "=" and not the start of a two character operator
                 // we know its and Assign token
-                if !is_operator(&next_c) && is_assign(&c) && buffer.len() == 0 {
+                if !is_two_char_operator(&c, next_c) && is_assign(&c) && buffer.is_empty() {
                     return Ok(Token::Assign);
                 }
             }
@@ -90,14 +107,23 @@ impl<'a> Tokens<'a> {
                         || is_seperator(&next_c)
                         || is_group_start(&next_c)
                         || is_group_end(&next_c)
+                        || is_pattern_start(&next_c)
+                        || is_pattern_end(&next_c)
                         || (is_accessor(&next_c) && !c.is_numeric())
                     {
                         true
-                    } else if is_operator(&c) && is_operator(&next_c) {
+                    } else if is_operator(buffer.first().unwrap_or(&c)) {
                         // Special case for multi-character operators like <=, >=, ==
-                        // Here we want to continue if both current and next are operators
-                        // This handles cases like <= where both < and = are operators
-                        false
+                        // Here we want to continue if current and next are the two
+                        // characters of one operator. Otherwise an operator is a token of
+                        // its own, so -5 is a minus followed by 5
+                        !(buffer.is_empty() && is_two_char_operator(&c, &next_c))
+                    } else if is_operator(&next_c)
+                        && (next_c != '-' || buffer.first().unwrap_or(&c).is_numeric())
+                    {
+                        // operators end identifiers and numbers, except - which can be
+                        // part of an identifier, e.g. owner-name
+                        true
                     } else if is_accessor(&c) && next_c.is_alphabetic() {
                         true
                     } else if is_assign(&next_c) {
@@ -138,6 +164,21 @@ impl<'a> Tokens<'a> {
                 return Ok(Token::AuxiliaryTo);
             }
 
+            match buffer_string.as_str() {
+                "true" => return Ok(Token::LiteralBool(true)),
+                "false" => return Ok(Token::LiteralBool(false)),
+                "null" => return Ok(Token::LiteralNull),
+                _ => {}
+            }
+
+            // ?name is a variable and :name a symbol of a pattern query
+            if let Some(name) = buffer_string.strip_prefix('?') {
+                return Ok(Token::Variable(name.to_string()));
+            }
+            if let Some(name) = buffer_string.strip_prefix(':') {
+                return Ok(Token::Symbol(name.to_string()));
+            }
+
             if buffer[0].is_numeric() {
                 let is_float = buffer_string.contains(".");
                 return parse_numeric(
@@ -148,16 +189,66 @@ impl<'a> Tokens<'a> {
                 );
             }
 
-            if buffer[0] == '"' {
-                return Ok(Token::LiteralStr(buffer_string.replace('"', "")));
-            }
-
             return Ok(Token::Identifier(buffer_string));
         }
 
         return Ok(Token::EOF);
     }
 
+    fn read_string_literal(&mut self) -> Result<Token, TokenizerError> {
+        let mut value = String::new();
+        loop {
+            let c = self.chars.next().ok_or_else(|| {
+                TokenizerError::new(
+                    "unterminated string literal",
+                    self.current_line_idx,
+                    self.current_char_idx,
+                )
+            })?;
+            self.current_char_idx += 1;
+            if is_quote(&c) {
+                return Ok(Token::LiteralStr(value));
+            }
+            if c == '\n' {
+                self.current_line_idx += 1
+            }
+            value.push(c);
+        }
+    }
+
+    // 'YYYY-MM-DD' or 'YYYY-MM-DDTHH:MM:SS' with optional milliseconds, in UTC
+    fn read_timestamp_literal(&mut self) -> Result<Token, TokenizerError> {
+        let mut value = String::new();
+        loop {
+            let c = self.chars.next().ok_or_else(|| {
+                TokenizerError::new(
+                    "unterminated timestamp literal",
+                    self.current_line_idx,
+                    self.current_char_idx,
+                )
+            })?;
+            self.current_char_idx += 1;
+            if is_timestamp_quote(&c) {
+                break;
+            }
+            value.push(c);
+        }
+
+        match parse_timestamp(&value) {
+            Some(timestamp) => Ok(Token::LiteralTimestamp(timestamp)),
+            None => Err(TokenizerError::new(
+                &format!("invalid timestamp '{}'", value),
+                self.current_line_idx,
+                self.current_char_idx,
+            )),
+        }
+    }
+
+    // true if there is nothing but whitespace left of the input
+    pub fn is_done(&self) -> bool {
+        self.peeked_token.is_none() && self.chars.clone().all(|c| c.is_whitespace())
+    }
+
     pub fn peek(&mut self) -> Result<Token, TokenizerError> {
         match &self.peeked_token {
             Some(t) => t.clone(),
@@ -213,13 +304,20 @@ pub enum Token {
     LiteralInt(i64),
     LiteralFloat(f64),
     LiteralBool(bool),
+    LiteralNull,
+    // milliseconds since the epoch
+    LiteralTimestamp(i64),
     Identifier(String),
     Accessor,
-    EOF,        // ;
-    Seperator,  // ,
-    GroupStart, // (
-    GroupEnd,   // )
+    EOF,          // ;
+    Seperator,    // ,
+    GroupStart,   // (
+    GroupEnd,     // )
+    PatternStart, // [
+    PatternEnd,   // ]
     Assign,
+    Variable(String), // ?name
+    Symbol(String),   // :name
 
     AuxiliaryOn,
     AuxiliaryTo,
@@ -236,10 +334,33 @@ pub enum Keyword {
     Create,
     Add,
     Find,
+    Snapshot,
+    Read,
+    Subscribe,
+    Join,
+    Ack,
+    Nack,
+    Replay,
 
     // Other
     Limit,
     Where,
+    Expect,
+    Group,
+    By,
+    Order,
+    Asc,
+    Desc,
+    Offset,
+    After,
+    And,
+    Or,
+    Not,
+    In,
+    Between,
+    As,
+    Of,
+    From,
 }
 
 impl Keyword {
@@ -249,8 +370,31 @@ impl Keyword {
             "create" => Some(Keyword::Create),
             "add" => Some(Keyword::Add),
             "find" => Some(Keyword::Find),
+            "snapshot" => Some(Keyword::Snapshot),
+            "read" => Some(Keyword::Read),
+            "subscribe" => Some(Keyword::Subscribe),
+            "join" => Some(Keyword::Join),
+            "ack" => Some(Keyword::Ack),
+            "nack" => Some(Keyword::Nack),
+            "replay" => Some(Keyword::Replay),
             "limit" => Some(Keyword::Limit),
             "where" => Some(Keyword::Where),
+            "expect" => Some(Keyword::Expect),
+            "group" => Some(Keyword::Group),
+            "by" => Some(Keyword::By),
+            "order" => Some(Keyword::Order),
+            "asc" => Some(Keyword::Asc),
+            "desc" => Some(Keyword::Desc),
+            "offset" => Some(Keyword::Offset),
+            "after" => Some(Keyword::After),
+            "and" => Some(Keyword::And),
+            "or" => Some(Keyword::Or),
+            "not" => Some(Keyword::Not),
+            "in" => Some(Keyword::In),
+            "between" => Some(Keyword::Between),
+            "as" => Some(Keyword::As),
+            "of" => Some(Keyword::Of),
+            "from" => Some(Keyword::From),
             _ => None,
         }
     }
@@ -263,6 +407,9 @@ pub enum Function {
     Min,
     Avg,
     Count,
+    First,
+    Last,
+    Nth,
 }
 
 impl Function {
@@ -273,6 +420,9 @@ impl Function {
             "min" => Some(Function::Min),
             "avg" => Some(Function::Avg),
             "count" => Some(Function::Count),
+            "first" => Some(Function::First),
+            "last" => Some(Function::Last),
+            "nth" => Some(Function::Nth),
             _ => None,
         }
     }
@@ -301,6 +451,7 @@ impl Operator {
             "-" => Some(Operator::Subtract),
             "/" => Some(Operator::Divide),
             "==" => Some(Operator::Equal),
+            "!=" => Some(Operator::NotEqual),
             "<" => Some(Operator::Less),
             ">" => Some(Operator::Greater),
             ">=" => Some(Operator::GreaterOrEqual),
@@ -319,6 +470,14 @@ fn is_group_end(c: &char) -> bool {
     return c == &')';
 }
 
+fn is_pattern_start(c: &char) -> bool {
+    c == &'['
+}
+
+fn is_pattern_end(c: &char) -> bool {
+    c == &']'
+}
+
 fn is_eof(c: &char) -> bool {
     return c == &';';
 }
@@ -331,18 +490,109 @@ fn is_accessor(c: &char) -> bool {
     return c == &'.';
 }
 
+fn is_quote(c: &char) -> bool {
+    return c == &'"';
+}
+
+fn is_timestamp_quote(c: &char) -> bool {
+    c == &'\''
+}
+
 fn is_assign(c: &char) -> bool {
     return c == &'=';
 }
 
 fn is_operator(c: &char) -> bool {
-    return c == &'=' || c == &'+' || c == &'-' || c == &'*' || c == &'<' || c == &'>';
+    return c == &'='
+        || c == &'!'
+        || c == &'+'
+        || c == &'-'
+        || c == &'*'
+        || c == &'/'
+        || c == &'%'
+        || c == &'<'
+        || c == &'>';
+}
+
+fn is_two_char_operator(c: &char, next_c: &char) -> bool {
+    Operator::from_str(&format!("{}{}", c, next_c)).is_some()
 }
 
 fn is_supported_identifier_literal_char(c: &char) -> bool {
     return c.is_alphanumeric() || c == &'_' || c == &'-' || c == &'"' || c == &'.';
 }
 
+// milliseconds since the epoch of the UTC date and time, the time is optional
+//
+//   2026-01-01
+//   2026-01-01T12:30:00
+//   2026-01-01 12:30:00.250Z
+fn parse_timestamp(s: &str) -> Option<i64> {
+    let s = s.strip_suffix('Z').unwrap_or(s);
+    let (date, time) = match s.split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (s, None),
+    };
+
+    let number = |s: &str, len: usize| match s.len() == len && s.bytes().all(|b| b.is_ascii_digit())
+    {
+        true => s.parse::<i64>().ok(),
+        false => None,
+    };
+    let mut parts = date.split('-');
+    let year = number(parts.next()?, 4)?;
+    let month = number(parts.next()?, 2)?;
+    let day = number(parts.next()?, 2)?;
+    if parts.next().is_some()
+        || !(1..=12).contains(&month)
+        || day < 1
+        || day > days_in_month(year, month)
+    {
+        return None;
+    }
+
+    let (mut hour, mut minute, mut second, mut millis) = (0, 0, 0, 0);
+    if let Some(time) = time {
+        let (time, fraction) = match time.split_once('.') {
+            Some((time, fraction)) => (time, Some(fraction)),
+            None => (time, None),
+        };
+        let mut parts = time.split(':');
+        hour = number(parts.next()?, 2)?;
+        minute = number(parts.next()?, 2)?;
+        second = number(parts.next()?, 2)?;
+        if parts.next().is_some() || hour > 23 || minute > 59 || second > 59 {
+            return None;
+        }
+        if let Some(fraction) = fraction {
+            millis = number(fraction, 3)?;
+        }
+    }
+
+    let days = days_from_civil(year, month, day);
+    Some((((days * 24 + hour) * 60 + minute) * 60 + second) * 1000 + millis)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// days since 1970-01-01 of the date in the proleptic Gregorian calendar, see
+// http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
 // Helper function to avoid code duplication for numeric parsing
 fn parse_numeric(
     s: &str,
@@ -854,6 +1104,38 @@ mod tokenizer_test {
                     Token::EOF,
                 ],
             ),
+            (
+                "string with spaces and accessors",
+                r#"snapshot to "backups/db 1.snap";"#,
+                vec![
+                    Token::Keyword(Keyword::Snapshot),
+                    Token::AuxiliaryTo,
+                    Token::LiteralStr("backups/db 1.snap".to_string()),
+                    Token::EOF,
+                ],
+            ),
+            (
+                "bool and null literals",
+                r#"add Flagged(active=true, deleted=false, note=null) to account(id="1");"#,
+                vec![
+                    Token::Keyword(Keyword::Add),
+                    Token::Identifier("Flagged".to_string()),
+                    Token::GroupStart,
+                    Token::Identifier("active".to_string()),
+                    Token::Assign,
+                    Token::LiteralBool(true),
+                    Token::Seperator,
+                    Token::Identifier("deleted".to_string()),
+                    Token::Assign,
+                    Token::LiteralBool(false),
+                    Token::Seperator,
+                    Token::Identifier("note".to_string()),
+                    Token::Assign,
+                    Token::LiteralNull,
+                    Token::GroupEnd,
+                    Token::AuxiliaryTo,
+                ],
+            ),
             (
                 "join",
                 r#"
@@ -886,4 +1168,135 @@ mod tokenizer_test {
             }
         }
     }
+
+    #[test]
+    fn test_operators() {
+        let test_cases = vec![
+            (
+                "leading minus",
+                "-5 - -account.amount;",
+                vec![
+                    Token::Operator(Operator::Subtract),
+                    Token::LiteralInt(5),
+                    Token::Operator(Operator::Subtract),
+                    Token::Operator(Operator::Subtract),
+                    Token::Identifier("account".to_string()),
+                    Token::Accessor,
+                    Token::Identifier("amount".to_string()),
+                    Token::EOF,
+                ],
+            ),
+            (
+                "operators without spaces",
+                "(1+2)*3/4%5<=-6!=7;",
+                vec![
+                    Token::GroupStart,
+                    Token::LiteralInt(1),
+                    Token::Operator(Operator::Add),
+                    Token::LiteralInt(2),
+                    Token::GroupEnd,
+                    Token::Operator(Operator::Multiply),
+                    Token::LiteralInt(3),
+                    Token::Operator(Operator::Divide),
+                    Token::LiteralInt(4),
+                    Token::Operator(Operator::Modulus),
+                    Token::LiteralInt(5),
+                    Token::Operator(Operator::LessOrEqual),
+                    Token::Operator(Operator::Subtract),
+                    Token::LiteralInt(6),
+                    Token::Operator(Operator::NotEqual),
+                    Token::LiteralInt(7),
+                    Token::EOF,
+                ],
+            ),
+            (
+                "assign negative number and identifier with minus",
+                "owner-name=-1.5;",
+                vec![
+                    Token::Identifier("owner-name".to_string()),
+                    Token::Assign,
+                    Token::Operator(Operator::Subtract),
+                    Token::LiteralFloat(1.5),
+                    Token::EOF,
+                ],
+            ),
+        ];
+
+        for (test_name, input, expected_tokens) in test_cases {
+            let mut tokens = tokenize(input);
+            for expected_token in expected_tokens {
+                let t = tokens.next().unwrap();
+                assert_eq!(t, expected_token, "Failed in test case: {}", test_name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_pattern_query() {
+        let input = r#"[:find ?account-id
+            :where [?c :type "AccountCreated"] [?c :key ?account-id]
+            [(< (- ?withdrawn ?created) 3600000)]];"#;
+        let expected_tokens = vec![
+            Token::PatternStart,
+            Token::Symbol("find".to_string()),
+            Token::Variable("account-id".to_string()),
+            Token::Symbol("where".to_string()),
+            Token::PatternStart,
+            Token::Variable("c".to_string()),
+            Token::Symbol("type".to_string()),
+            Token::LiteralStr("AccountCreated".to_string()),
+            Token::PatternEnd,
+            Token::PatternStart,
+            Token::Variable("c".to_string()),
+            Token::Symbol("key".to_string()),
+            Token::Variable("account-id".to_string()),
+            Token::PatternEnd,
+            Token::PatternStart,
+            Token::GroupStart,
+            Token::Operator(Operator::Less),
+            Token::GroupStart,
+            Token::Operator(Operator::Subtract),
+            Token::Variable("withdrawn".to_string()),
+            Token::Variable("created".to_string()),
+            Token::GroupEnd,
+            Token::LiteralInt(3600000),
+            Token::GroupEnd,
+            Token::PatternEnd,
+            Token::PatternEnd,
+            Token::EOF,
+        ];
+
+        let mut tokens = tokenize(input);
+        for expected_token in expected_tokens {
+            assert_eq!(tokens.next().unwrap(), expected_token);
+        }
+    }
+
+    #[test]
+    fn test_timestamp() {
+        let test_cases = vec![
+            ("epoch", "'1970-01-01'", Ok(0)),
+            ("date", "'2026-01-01'", Ok(1767225600000)),
+            ("date and time", "'2026-01-01T12:30:15'", Ok(1767270615000)),
+            (
+                "milliseconds",
+                "'2026-01-01 12:30:15.250Z'",
+                Ok(1767270615250),
+            ),
+            ("leap day", "'2024-02-29'", Ok(1709164800000)),
+            ("not a leap day", "'2023-02-29'", Err(())),
+            ("month out of range", "'2026-13-01'", Err(())),
+            ("not a date", "'yesterday'", Err(())),
+        ];
+
+        for (name, input, expected) in test_cases {
+            let got = tokenize(&format!("{};", input)).next().map_err(|_| ());
+            assert_eq!(
+                expected.map(Token::LiteralTimestamp),
+                got,
+                "test case '{}'",
+                name
+            );
+        }
+    }
 }
@@ -0,0 +1,368 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::codec::{decode_frame, encode_frame, put_str, CodecError, Reader};
+use crate::storage::SchemaChange;
+
+// The log holds the schema changes, each written as a frame (see codec). A torn frame at
+// the end of the log is the result of a crash in the middle of a write, it and everything
+// after it is dropped when the log is opened.
+
+const TAG_CREATE_STREAM: u8 = 1;
+const TAG_CREATE_EVENT: u8 = 2;
+const TAG_CREATE_ATTRIBUTE: u8 = 5;
+const TAG_CREATE_INDEX: u8 = 7;
+const TAG_CREATE_SUBSCRIPTION: u8 = 8;
+
+#[derive(Debug)]
+pub struct Wal {
+    file: File,
+    // length of the valid part of the log
+    len: u64,
+    // set when a failed append could not be undone
+    failed: bool,
+}
+
+impl Wal {
+    // opens the log at path, creating it if it does not exist, and returns it together
+    // with all schema changes that has been written to it
+    pub fn open(path: &Path) -> Result<(Wal, Vec<SchemaChange>), WalError> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| {
+                WalError::new(&format!("failed to create directory {:?}: {}", dir, e))
+            })?;
+        }
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)
+            .map_err(|e| WalError::new(&format!("failed to open {:?}: {}", path, e)))?;
+
+        let mut buffer = vec![];
+        file.read_to_end(&mut buffer)
+            .map_err(|e| WalError::new(&format!("failed to read {:?}: {}", path, e)))?;
+
+        let (records, valid_len) = decode_frames(&buffer)?;
+        if valid_len < buffer.len() {
+            file.set_len(valid_len as u64)
+                .map_err(|e| WalError::new(&format!("failed to truncate {:?}: {}", path, e)))?;
+            file.sync_all()
+                .map_err(|e| WalError::new(&format!("failed to sync {:?}: {}", path, e)))?;
+        }
+        file.seek(SeekFrom::End(0))
+            .map_err(|e| WalError::new(&format!("failed to seek {:?}: {}", path, e)))?;
+
+        let wal = Wal {
+            file,
+            len: valid_len as u64,
+            failed: false,
+        };
+        Ok((wal, records))
+    }
+
+    // appends the schema change and waits for it to be flushed to disk
+    pub fn append(&mut self, change: &SchemaChange) -> Result<(), WalError> {
+        if self.failed {
+            return Err(WalError::new(
+                "a failed append could not be undone, the log must be opened again",
+            ));
+        }
+
+        let frame = encode_frame(&encode_change(change));
+        if let Err(e) = self
+            .file
+            .write_all(&frame)
+            .and_then(|_| self.file.sync_data())
+        {
+            // drop whatever part of the frame that made it to the log, otherwise the
+            // changes appended after it are dropped with it when the log is opened
+            if !self.undo() {
+                self.failed = true;
+            }
+            return Err(WalError::new(&format!("failed to write record: {}", e)));
+        }
+        self.len += frame.len() as u64;
+        Ok(())
+    }
+
+    // drops everything in the log after its valid part, returns false if that fails
+    fn undo(&mut self) -> bool {
+        self.file
+            .set_len(self.len)
+            .and_then(|_| self.file.sync_data())
+            .is_ok()
+    }
+}
+
+// returns the schema changes of all valid frames and the length of the valid part of buffer
+fn decode_frames(buffer: &[u8]) -> Result<(Vec<SchemaChange>, usize), WalError> {
+    let mut records = vec![];
+    let mut offset = 0;
+    while let Some((payload, frame_len)) = decode_frame(&buffer[offset..]) {
+        records.push(decode_change(payload)?);
+        offset += frame_len;
+    }
+    Ok((records, offset))
+}
+
+pub fn encode_change(change: &SchemaChange) -> Vec<u8> {
+    let mut buffer = vec![];
+    match change {
+        SchemaChange::Stream { name } => {
+            buffer.push(TAG_CREATE_STREAM);
+            put_str(&mut buffer, name);
+        }
+        SchemaChange::Event { name, stream_name } => {
+            buffer.push(TAG_CREATE_EVENT);
+            put_str(&mut buffer, name);
+            put_str(&mut buffer, stream_name);
+        }
+        SchemaChange::Attribute {
+            name,
+            event_name,
+            stream_name,
+            data_type,
+            required,
+        } => {
+            buffer.push(TAG_CREATE_ATTRIBUTE);
+            put_str(&mut buffer, name);
+            put_str(&mut buffer, event_name);
+            put_str(&mut buffer, stream_name);
+            put_str(&mut buffer, data_type);
+            buffer.push(*required as u8);
+        }
+        SchemaChange::Index {
+            stream_name,
+            event_name,
+            attribute_name,
+        } => {
+            buffer.push(TAG_CREATE_INDEX);
+            put_str(&mut buffer, stream_name);
+            put_str(&mut buffer, event_name);
+            put_str(&mut buffer, attribute_name);
+        }
+        SchemaChange::Subscription {
+            name,
+            stream_name,
+            from_position,
+            park_after,
+        } => {
+            buffer.push(TAG_CREATE_SUBSCRIPTION);
+            put_str(&mut buffer, name);
+            put_str(&mut buffer, stream_name);
+            buffer.extend_from_slice(&from_position.to_le_bytes());
+            buffer.extend_from_slice(&park_after.to_le_bytes());
+        }
+    }
+    buffer
+}
+
+pub fn decode_change(payload: &[u8]) -> Result<SchemaChange, WalError> {
+    let mut reader = Reader::new(payload);
+    let change = match reader.u8()? {
+        TAG_CREATE_STREAM => SchemaChange::Stream {
+            name: reader.str()?,
+        },
+        TAG_CREATE_EVENT => SchemaChange::Event {
+            name: reader.str()?,
+            stream_name: reader.str()?,
+        },
+        TAG_CREATE_ATTRIBUTE => SchemaChange::Attribute {
+            name: reader.str()?,
+            event_name: reader.str()?,
+            stream_name: reader.str()?,
+            data_type: reader.str()?,
+            required: reader.u8()? != 0,
+        },
+        TAG_CREATE_INDEX => SchemaChange::Index {
+            stream_name: reader.str()?,
+            event_name: reader.str()?,
+            attribute_name: reader.str()?,
+        },
+        TAG_CREATE_SUBSCRIPTION => SchemaChange::Subscription {
+            name: reader.str()?,
+            stream_name: reader.str()?,
+            from_position: reader.u64()?,
+            park_after: reader.u64()?,
+        },
+        tag => return Err(WalError::new(&format!("unknown record tag {}", tag))),
+    };
+    Ok(change)
+}
+
+#[derive(Debug)]
+pub struct WalError {
+    message: String,
+}
+
+impl WalError {
+    fn new(message: &str) -> Self {
+        WalError {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for WalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for WalError {}
+
+impl From<CodecError> for WalError {
+    fn from(e: CodecError) -> WalError {
+        WalError::new(&e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod wal_test {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn test_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir()
+            .join(format!("adb-wal-test-{}", std::process::id()))
+            .join(name);
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn records() -> Vec<SchemaChange> {
+        vec![
+            SchemaChange::Stream {
+                name: "account".to_string(),
+            },
+            SchemaChange::Event {
+                name: "AccountCreated".to_string(),
+                stream_name: "account".to_string(),
+            },
+            SchemaChange::Attribute {
+                name: "owner".to_string(),
+                event_name: "AccountCreated".to_string(),
+                stream_name: "account".to_string(),
+                data_type: "string".to_string(),
+                required: false,
+            },
+            SchemaChange::Index {
+                stream_name: "account".to_string(),
+                event_name: "AccountCreated".to_string(),
+                attribute_name: "owner".to_string(),
+            },
+            SchemaChange::Subscription {
+                name: "workers".to_string(),
+                stream_name: "account".to_string(),
+                from_position: 1,
+                park_after: 10,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_replay() {
+        let path = test_path("replay.log");
+
+        let (mut wal, replayed) = Wal::open(&path).unwrap();
+        assert_eq!(Vec::<SchemaChange>::new(), replayed);
+        for record in records() {
+            wal.append(&record).unwrap();
+        }
+        drop(wal);
+
+        let (_, replayed) = Wal::open(&path).unwrap();
+        assert_eq!(records(), replayed);
+    }
+
+    #[test]
+    fn test_replay_drops_torn_write() {
+        let path = test_path("torn.log");
+
+        let (mut wal, _) = Wal::open(&path).unwrap();
+        for record in records() {
+            wal.append(&record).unwrap();
+        }
+        drop(wal);
+
+        // simulate a crash in the middle of writing the last record
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 3)
+            .unwrap();
+
+        let (mut wal, replayed) = Wal::open(&path).unwrap();
+        assert_eq!(records()[..4].to_vec(), replayed);
+
+        // the log can be appended to after the torn record was dropped
+        wal.append(&records()[4]).unwrap();
+        drop(wal);
+        let (_, replayed) = Wal::open(&path).unwrap();
+        assert_eq!(records(), replayed);
+    }
+
+    #[test]
+    fn test_failed_append() {
+        let path = test_path("failed.log");
+
+        let (mut wal, _) = Wal::open(&path).unwrap();
+        for record in &records()[..2] {
+            wal.append(record).unwrap();
+        }
+
+        // simulate an append that fails after part of the record is written, on a read only
+        // handle so undoing the write fails too
+        let frame = encode_frame(&encode_change(&records()[2]));
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(&frame[..frame.len() - 3])
+            .unwrap();
+        wal.file = File::open(&path).unwrap();
+        assert!(wal.append(&records()[2]).is_err());
+        // nothing is appended after the torn record until the log is opened again
+        assert!(wal.append(&records()[3]).is_err());
+        drop(wal);
+
+        let (mut wal, replayed) = Wal::open(&path).unwrap();
+        assert_eq!(records()[..2].to_vec(), replayed);
+        for record in &records()[2..] {
+            wal.append(record).unwrap();
+        }
+        drop(wal);
+        let (_, replayed) = Wal::open(&path).unwrap();
+        assert_eq!(records(), replayed);
+    }
+
+    #[test]
+    fn test_undo_failed_append() {
+        let path = test_path("undo.log");
+
+        let (mut wal, _) = Wal::open(&path).unwrap();
+        for record in &records()[..2] {
+            wal.append(record).unwrap();
+        }
+
+        // a failed append leaves part of the record in the log, which is dropped so the
+        // records appended after it are kept
+        let frame = encode_frame(&encode_change(&records()[2]));
+        wal.file.write_all(&frame[..frame.len() - 3]).unwrap();
+        assert!(wal.undo());
+        for record in &records()[2..] {
+            wal.append(record).unwrap();
+        }
+        drop(wal);
+
+        let (_, replayed) = Wal::open(&path).unwrap();
+        assert_eq!(records(), replayed);
+    }
+}